@@ -0,0 +1,181 @@
+//! Sets of IPv4 / IPv6 addresses built from `IP` and `IP/PREFIX` entries
+//!
+//! Entries are merged into sorted, non-overlapping ranges so that lookups are a binary search,
+//! which keeps country-sized lists (tens of thousands of prefixes) cheap to query.
+
+use anyhow::{bail, Context, Result};
+use std::net::IpAddr;
+
+#[derive(Default, Clone)]
+pub struct IpSet {
+    v4: Vec<(u32, u32)>,
+    v6: Vec<(u128, u128)>,
+}
+
+impl IpSet {
+    /// 由 IP / CIDR 或文件路径组成的参数构建；不是地址的参数按文件读取，每行一个条目
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let mut builder = Builder::default();
+        for arg in args {
+            if parse_entry(arg).is_ok() {
+                builder.add(arg)?;
+            } else {
+                builder.add_file(arg)?;
+            }
+        }
+        Ok(builder.build())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v4.is_empty() && self.v6.is_empty()
+    }
+
+    /// 区间 (合并后) 的个数
+    pub fn len(&self) -> usize {
+        self.v4.len() + self.v6.len()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => lookup(&self.v4, u32::from(ip)),
+            IpAddr::V6(ip) => lookup(&self.v6, u128::from(ip)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    v4: Vec<(u32, u32)>,
+    v6: Vec<(u128, u128)>,
+}
+
+impl Builder {
+    fn add(&mut self, entry: &str) -> Result<()> {
+        match parse_entry(entry)? {
+            (IpAddr::V4(ip), prefix) => {
+                let host_bits = 32 - prefix;
+                let start = u32::from(ip).checked_shr(host_bits).unwrap_or(0).checked_shl(host_bits).unwrap_or(0);
+                self.v4.push((start, start | u32::MAX.checked_shr(prefix).unwrap_or(0)));
+            }
+            (IpAddr::V6(ip), prefix) => {
+                let host_bits = 128 - prefix;
+                let start = u128::from(ip).checked_shr(host_bits).unwrap_or(0).checked_shl(host_bits).unwrap_or(0);
+                self.v6.push((start, start | u128::MAX.checked_shr(prefix).unwrap_or(0)));
+            }
+        }
+        Ok(())
+    }
+
+    /// 每行一个 IP 或 CIDR，`#` 之后为注释
+    fn add_file(&mut self, path: &str) -> Result<()> {
+        let content = std::fs::read_to_string(path).with_context(|| format!("failed to read IP list {}", path))?;
+        for (no, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() { continue; }
+            self.add(line).with_context(|| format!("{}:{}", path, no + 1))?;
+        }
+        Ok(())
+    }
+
+    fn build(self) -> IpSet {
+        IpSet { v4: merge(self.v4), v6: merge(self.v6) }
+    }
+}
+
+/// "10.0.0.1" 视为 /32 (IPv6 为 /128)，"10.0.0.0/8"；主机位不为 0 时按前缀截断
+fn parse_entry(entry: &str) -> Result<(IpAddr, u32)> {
+    let (addr, prefix) = entry.split_once('/').map_or((entry, None), |(a, p)| (a, Some(p)));
+    let ip: IpAddr = addr.trim().parse().with_context(|| format!("invalid address {}", entry))?;
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(p) => p.trim().parse().with_context(|| format!("invalid prefix length in {}", entry))?,
+        None => max,
+    };
+    if prefix > max { bail!("prefix length out of range in {}", entry); }
+    Ok((ip, prefix))
+}
+
+/// 排序并合并重叠的区间
+fn merge<T: Copy + Ord>(mut ranges: Vec<(T, T)>) -> Vec<(T, T)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(T, T)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged.shrink_to_fit();
+    merged
+}
+
+fn lookup<T: Copy + Ord>(ranges: &[(T, T)], ip: T) -> bool {
+    // 第一个 start > ip 的区间之前那一个是唯一可能包含 ip 的区间
+    let i = ranges.partition_point(|&(start, _)| start <= ip);
+    i > 0 && ip <= ranges[i - 1].1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(entries: &[&str]) -> IpSet {
+        IpSet::from_args(&entries.iter().map(|e| e.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    fn has(set: &IpSet, ip: &str) -> bool {
+        set.contains(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn masks_prefixes() {
+        // 主机位不为 0 时按前缀截断；/32 与 /128 只含一个地址
+        let hosts = set(&["10.1.2.3/8", "192.0.2.7", "2001:db8::1/128"]);
+        assert!(has(&hosts, "10.0.0.0") && has(&hosts, "10.255.255.255"));
+        assert!(!has(&hosts, "11.0.0.0") && !has(&hosts, "9.255.255.255"));
+        assert!(has(&hosts, "192.0.2.7") && !has(&hosts, "192.0.2.8"));
+        assert!(has(&hosts, "2001:db8::1") && !has(&hosts, "2001:db8::2"));
+
+        let all = set(&["0.0.0.0/0", "::/0"]);
+        assert!(has(&all, "0.0.0.0") && has(&all, "255.255.255.255"));
+        assert!(has(&all, "::") && has(&all, "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"));
+
+        assert!(parse_entry("10.0.0.0/33").is_err());
+        assert!(parse_entry("::/129").is_err());
+        assert!(parse_entry("10.0.0.0/x").is_err());
+        assert!(IpSet::from_args(&["/nonexistent/ip-list.txt".to_string()]).is_err());
+    }
+
+    #[test]
+    fn merges_overlapping_ranges() {
+        let merged = set(&["10.0.0.0/24", "10.0.0.128/25", "10.0.1.0/24", "10.0.0.1", "10.0.3.0/24", "2001:db8::/32", "2001:db8:1::/48"]);
+        // 重叠的合并为一个区间，相邻但不重叠的各自保留
+        assert_eq!(merged.len(), 4);
+        assert!(has(&merged, "10.0.1.255"));
+        assert!(!has(&merged, "10.0.2.0"));
+        assert!(has(&merged, "10.0.3.0"));
+        assert_eq!(merge(vec![(5u32, 9), (1, 3), (2, 6), (20, 30)]), vec![(1, 9), (20, 30)]);
+        assert!(IpSet::default().is_empty() && !merged.is_empty());
+    }
+
+    #[test]
+    fn matches_ipv4_mapped_addresses() {
+        let v4 = set(&["192.0.2.0/24"]);
+        assert!(has(&v4, "::ffff:192.0.2.1"));
+        assert!(!has(&v4, "::ffff:198.51.100.1"));
+        // IPv4 条目不会匹配普通 IPv6 地址
+        assert!(!has(&v4, "::c000:201"));
+    }
+
+    #[test]
+    fn reads_list_files() {
+        let path = std::env::temp_dir().join(format!("coredns-cidr-{}.txt", std::process::id()));
+        std::fs::write(&path, "# China\n1.0.1.0/24 # comment\n\n240e::/20\n").unwrap();
+        let list = set(&[path.to_str().unwrap(), "203.0.113.1"]);
+        assert!(has(&list, "1.0.1.9") && has(&list, "240e:1::1") && has(&list, "203.0.113.1"));
+        std::fs::write(&path, "1.0.1.0/24\nnot-an-ip\n").unwrap();
+        let err = IpSet::from_args(&[path.to_str().unwrap().to_string()]).err().unwrap();
+        assert!(format!("{:#}", err).contains(":2"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,14 +1,25 @@
 //! Configuration parsing for CoreDNS
 
 use crate::plugin::{create_plugin, SharedState, Plugin};
-use anyhow::Result;
+use crate::tls::TlsSettings;
+use anyhow::{Context, Result};
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone, Debug)]
 pub struct PluginConfig {
     pub name: String,
     pub args: Vec<String>,
     pub block: Vec<PluginConfig>,
+    /// 所属 server block 的规范化标识 (见 ZoneConfig::key)，只在顶层指令上填充
+    pub zone: String,
+}
+
+impl PluginConfig {
+    /// 所属 server block 的 zone 后缀，与 ZoneConfig::origin 相同
+    pub fn origin(&self) -> String {
+        block_origin(&self.zone)
+    }
 }
 
 pub struct Config {
@@ -18,6 +29,54 @@ pub struct Config {
 pub struct ZoneConfig {
     pub name: String,
     pub plugins: Vec<Box<dyn Plugin>>,
+    pub tls: Option<TlsSettings>,
+}
+
+impl ZoneConfig {
+    /// 监听协议，取自 server block 的前缀 (dns:// 或省略 / tls:// / https://)
+    pub fn scheme(&self) -> &str {
+        self.name.split_once("://").map(|(scheme, _)| scheme).unwrap_or("dns")
+    }
+
+    /// 监听端口，未写明时按协议取默认值
+    pub fn port(&self) -> u16 {
+        let name = self.name.split_once("://").map(|(_, rest)| rest).unwrap_or(&self.name);
+        match name.rsplit_once(':').and_then(|(_, port)| port.parse().ok()) {
+            Some(port) => port,
+            None => match self.scheme() { "tls" | "quic" => 853, "https" => 443, _ => 53 },
+        }
+    }
+
+    /// 跨热重载稳定的 server block 标识，如 ".:53" 与 "dns://.:53" 均为 "dns://.:53"
+    pub fn key(&self) -> String {
+        format!("{}://{}:{}", self.scheme(), self.origin(), self.port())
+    }
+
+    /// Server block 对应的 zone 后缀 (小写、无尾点，根域为 ".")，如 "Example.com.:53" -> "example.com"
+    pub fn origin(&self) -> String {
+        block_origin(&self.name)
+    }
+}
+
+fn block_origin(name: &str) -> String {
+    let name = name.split_once("://").map(|(_, rest)| rest).unwrap_or(name);
+    let name = match name.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => name,
+    };
+    let origin = name.trim_end_matches('.').to_ascii_lowercase();
+    if origin.is_empty() { ".".to_string() } else { origin }
+}
+
+/// Corefile 中的时长："500ms"、"30s"、"5m"、"1h"，单独的 "0" 即零
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    if let Some(stripped) = s.strip_suffix("ms") { Ok(Duration::from_millis(stripped.parse()?)) }
+    else if let Some(stripped) = s.strip_suffix('s') { Ok(Duration::from_secs(stripped.parse()?)) }
+    else if let Some(stripped) = s.strip_suffix('m') { Ok(Duration::from_secs(stripped.parse::<u64>()? * 60)) }
+    else if let Some(stripped) = s.strip_suffix('h') { Ok(Duration::from_secs(stripped.parse::<u64>()? * 3600)) }
+    else if s == "0" { Ok(Duration::ZERO) }
+    else { anyhow::bail!("invalid duration {}", s) }
 }
 
 #[derive(Debug, PartialEq)]
@@ -26,32 +85,38 @@ enum Token { Text(String), OpenBrace, CloseBrace, Newline }
 struct RawZone { name: String, plugins: Vec<PluginConfig> }
 
 impl Config {
-    /// Load configuration from a file path
-    pub fn load(path: &str, shared: Arc<SharedState>) -> Result<Self> {
-        let content = std::fs::read_to_string(path)
-            .map_err(|e| anyhow::anyhow!("Failed to read config file '{}': {}", path, e))?;
-        Self::parse(&content, shared)
-    }
-
     pub fn parse(content: &str, shared: Arc<SharedState>) -> Result<Self> {
         let tokens = Self::lex(content);
         let raw_zones = Self::parse_tokens(&tokens)?;
         let mut zones = Vec::new();
         
         for raw in raw_zones {
+            let mut zone = ZoneConfig { name: raw.name, plugins: Vec::new(), tls: None };
+            let zone_key = zone.key();
             let mut plugins = Vec::new();
+            let mut tls = None;
             for p_cfg in &raw.plugins {
-                if let Ok(plugin) = create_plugin(p_cfg, shared.clone()) {
-                    plugins.push(plugin);
+                // tls 不是插件，而是当前 server block 监听器的证书配置
+                if p_cfg.name == "tls" {
+                    let settings = TlsSettings::from_config(p_cfg).with_context(|| format!("invalid tls directive in {}", zone.name))?;
+                    for file in settings.files() { shared.watch_file(&file); }
+                    tls = Some(settings);
+                    continue;
                 }
+                let p_cfg = PluginConfig { zone: zone_key.clone(), ..p_cfg.clone() };
+                // 任何插件创建失败都让整份配置加载失败，热重载时由调用方沿用旧配置
+                let plugin = create_plugin(&p_cfg, shared.clone()).with_context(|| format!("failed to create plugin {} in {}", p_cfg.name, zone.name))?;
+                plugins.push(plugin);
             }
             
             // 【核心修复】：严格遵守 CoreDNS 规范！
             // 插件的执行顺序必须由内置的 Priority 决定，与 Corefile 书写顺序无关。
             // 按照优先级从大到小排序 (比如 Cache:120 必须在 Forward:100 之前拦截执行)
-            plugins.sort_by(|a, b| b.priority().cmp(&a.priority()));
+            plugins.sort_by_key(|p| std::cmp::Reverse(p.priority()));
             
-            zones.push(ZoneConfig { name: raw.name, plugins });
+            zone.plugins = plugins;
+            zone.tls = tls;
+            zones.push(zone);
         }
         Ok(Config { zones })
     }
@@ -63,17 +128,29 @@ impl Config {
             if c == '\n' { tokens.push(Token::Newline); chars.next(); } 
             else if c.is_whitespace() { chars.next(); } 
             else if c == '#' { while let Some(&c) = chars.peek() { if c == '\n' { break; } chars.next(); } } 
-            else if c == '{' { tokens.push(Token::OpenBrace); chars.next(); } 
+            else if c == '{' && placeholder_len(&chars) == 0 { tokens.push(Token::OpenBrace); chars.next(); } 
             else if c == '}' { tokens.push(Token::CloseBrace); chars.next(); } 
             else if c == '"' {
                 chars.next(); 
                 let mut s = String::new();
-                while let Some(&c) = chars.peek() { if c == '"' { chars.next(); break; } s.push(c); chars.next(); }
+                while let Some(c) = chars.next() {
+                    if c == '"' { break; }
+                    // 引号内 \" 表示引号本身，其余反斜杠原样保留 (如正则中的 \.)
+                    if c == '\\' && chars.peek() == Some(&'"') { s.push('"'); chars.next(); continue; }
+                    s.push(c);
+                }
                 tokens.push(Token::Text(s));
             } else {
                 let mut s = String::new();
                 while let Some(&c) = chars.peek() {
-                    if c.is_whitespace() || c == '#' || c == '{' || c == '}' || c == '"' { break; }
+                    // {1} 这类占位符 (如 rewrite 的捕获组引用) 属于当前词
+                    if c == '{' {
+                        let len = placeholder_len(&chars);
+                        if len == 0 { break; }
+                        for _ in 0..len { s.extend(chars.next()); }
+                        continue;
+                    }
+                    if c.is_whitespace() || c == '#' || c == '}' || c == '"' { break; }
                     s.push(c); chars.next();
                 }
                 tokens.push(Token::Text(s));
@@ -124,11 +201,67 @@ impl Config {
                             Token::Newline | Token::CloseBrace => { break; }
                         }
                     }
-                    plugins.push(PluginConfig { name: plugin_name, args, block });
+                    plugins.push(PluginConfig { name: plugin_name, args, block, zone: String::new() });
                 }
                 _ => { i += 1; }
             }
         }
         Ok((plugins, i))
     }
-}
\ No newline at end of file
+}
+
+/// 从 `{` 开始、中间不含空白与花括号的 `{...}` 占位符长度；不是占位符 (即块的开始) 时为 0
+fn placeholder_len(chars: &std::iter::Peekable<std::str::Chars>) -> usize {
+    let mut ahead = chars.clone();
+    if ahead.next() != Some('{') { return 0; }
+    let mut len = 1;
+    for c in ahead {
+        len += 1;
+        if c == '}' { return if len > 2 { len } else { 0 }; }
+        if c.is_whitespace() || c == '{' || c == '#' || c == '"' { return 0; }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration(" 30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("0").unwrap(), Duration::ZERO);
+        for bad in ["", "10", "-1s", "1d", "fast"] { assert!(parse_duration(bad).is_err(), "{}", bad); }
+    }
+
+    #[test]
+    fn block_origins() {
+        let plugin = |zone: &str| PluginConfig { name: "hosts".to_string(), args: Vec::new(), block: Vec::new(), zone: zone.to_string() };
+        assert_eq!(plugin("dns://Example.ORG.:53").origin(), "example.org");
+        assert_eq!(plugin("tls://.:853").origin(), ".");
+        assert_eq!(plugin("").origin(), ".");
+        assert_eq!(block_origin("example.org"), "example.org");
+        assert_eq!(block_origin("https://example.org:8443"), "example.org");
+    }
+
+    #[tokio::test]
+    async fn plugin_errors_fail_the_load() {
+        let shared = || Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        let config = Config::parse(".:5300 {\n    forward . 127.0.0.1 {\n        match domain:example.org\n    }\n    whoami\n}\n", shared()).unwrap();
+        assert_eq!(config.zones[0].plugins.len(), 2);
+
+        // match 中的正则有误时不能悄悄丢掉这一组 forward
+        let err = Config::parse(".:5300 {\n    forward . 127.0.0.1 {\n        match regexp:(\n    }\n    whoami\n}\n", shared()).err().unwrap();
+        assert!(format!("{:#}", err).contains("failed to create plugin forward in .:5300"), "{:#}", err);
+        assert!(Config::parse(".:5300 {\n    nosuchplugin\n}\n", shared()).is_err());
+
+        // tls 指令有误时整份配置同样加载失败，不能带着 tls: None 监听
+        let err = Config::parse("tls://.:8853 {\n    tls cert.pem\n    whoami\n}\n", shared()).err().unwrap();
+        assert!(format!("{:#}", err).contains("invalid tls directive in tls://.:8853"), "{:#}", err);
+        let err = Config::parse("tls://.:8853 {\n    tls cert.pem key.pem {\n        client_auth require\n    }\n    whoami\n}\n", shared()).err().unwrap();
+        assert!(format!("{:#}", err).contains("client_auth requires a CA file"), "{:#}", err);
+    }
+}
@@ -1,14 +1,52 @@
 use crate::config::Config;
-use crate::types::DnsMessage;
-use crate::plugin::SharedState;
+use crate::types::{DnsMessage, Edns};
+use crate::wire;
+use crate::plugin::{Plugin, SharedState};
+use crate::plugin::prometheus::{DOH_REQUESTS_TOTAL, DOH_RESPONSES_TOTAL, DOH_REQUEST_DURATION};
+use crate::tls;
 use anyhow::Result;
 use std::sync::Arc;
 use tokio::net::{UdpSocket, TcpListener};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::watch;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, watch, Semaphore};
+use tokio::time::{timeout, Duration};
+use tokio_rustls::TlsAcceptor;
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use hyper::body::HttpBody;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use std::convert::Infallible;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 
-/// DNS Server instance that handles UDP and TCP connections
+/// 本端通告的 EDNS0 UDP 负载大小 (DNS Flag Day 2020 推荐值)
+const SERVER_UDP_PAYLOAD: u16 = 1232;
+/// 无论客户端通告多大，UDP 应答都不超过该长度
+const MAX_UDP_RESPONSE: u16 = 4096;
+/// RFC 8914 Extended DNS Error，上游给出的该选项会透传给客户端
+const EDNS_OPTION_EDE: u16 = 15;
+
+/// DoH 服务路径与媒体类型 (RFC 8484)
+const DOH_PATH: &str = "/dns-query";
+const DOH_MEDIA_TYPE: &str = "application/dns-message";
+
+/// TCP 连接上两次查询之间允许的最长空闲时间
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// 读到长度前缀后，报文体必须在该时间内到齐
+const TCP_READ_TIMEOUT: Duration = Duration::from_secs(2);
+/// 单条 TCP 连接上同时处理的查询数上限
+const TCP_MAX_INFLIGHT: usize = 64;
+
+/// DoQ 应用层错误码 (RFC 9250 4.3)
+const DOQ_PROTOCOL_ERROR: u32 = 0x2;
+const DOQ_INTERNAL_ERROR: u32 = 0x1;
+
+/// 同一监听地址上的 Zone 路由表: (zone 后缀, Config.zones 下标)
+type ZoneRoutes = Arc<Vec<(String, usize)>>;
+
+/// DNS Server instance that handles UDP, TCP, DNS-over-TLS, DNS-over-HTTPS and DNS-over-QUIC connections
 pub struct DnsServer {
     config: Arc<Config>,
     _shared: Arc<SharedState>,
@@ -17,7 +55,9 @@ pub struct DnsServer {
 impl DnsServer {
     /// Create a new DNS server instance
     pub fn new(config: Config, shared: Arc<SharedState>) -> Result<Self> {
-        Ok(Self { config: Arc::new(config), _shared: shared })
+        let config = Arc::new(config);
+        shared.set_config(&config);
+        Ok(Self { config, _shared: shared })
     }
 
     /// Run the DNS server, listening on configured ports
@@ -26,161 +66,738 @@ impl DnsServer {
         // Extract the base IP address from the default address (e.g., "0.0.0.0" from "0.0.0.0:53")
         let base_ip = default_address.split(':').next().unwrap_or("0.0.0.0");
 
-        // 按监听端口分组 (bind_addr -> Vec<Zone Index>)
+        // 按 (协议, 监听地址) 分组 -> Vec<(zone 后缀, Zone Index)>
         // 这样可以支持在同一个端口上配置多个不同的域名后缀 (如 a.com:53 和 b.com:53)
-        let mut bind_map: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut bind_map: HashMap<(String, String), Vec<(String, usize)>> = HashMap::new();
         for (i, zone) in self.config.zones.iter().enumerate() {
-            // 解析 Corefile 中定义的端口，比如 ".:1053" 提取出 "1053"
-            let port = if let Some(idx) = zone.name.rfind(':') {
-                &zone.name[idx + 1..]
-            } else {
-                "53"
-            };
-            let bind_addr = format!("{}:{}", base_ip, port);
-            bind_map.entry(bind_addr).or_default().push(i);
+            let bind_addr = format!("{}:{}", base_ip, zone.port());
+            bind_map.entry((zone.scheme().to_string(), bind_addr)).or_default().push((zone.origin(), i));
         }
 
         // 存放所有异步监听任务的句柄，方便重载时安全销毁
         let mut tasks = Vec::new();
+        // QUIC 端点由独立的驱动任务持有 socket，重载时需要显式关闭
+        let mut quic_endpoints = Vec::new();
+
+        for ((scheme, bind_addr), routes) in bind_map {
+            let routes: ZoneRoutes = Arc::new(routes);
+            let port = bind_addr.rsplit(':').next().unwrap_or("53").parse::<u16>().unwrap_or(53);
 
-        // 为 Corefile 里定义的每一个独立端口，分配专属的 UDP 和 TCP 监听器
-        for (bind_addr, zone_indices) in bind_map {
-            let udp_socket = match UdpSocket::bind(&bind_addr).await {
-                Ok(s) => Arc::new(s),
-                Err(e) => {
-                    tracing::error!("Failed to bind UDP {}: {}", bind_addr, e);
-                    continue;
+            match scheme.as_str() {
+                "dns" => {
+                    // 为 Corefile 里定义的每一个独立端口，分配专属的 UDP 和 TCP 监听器
+                    let udp_socket = match UdpSocket::bind(&bind_addr).await {
+                        Ok(s) => Arc::new(s),
+                        Err(e) => {
+                            tracing::error!("Failed to bind UDP {}: {}", bind_addr, e);
+                            continue;
+                        }
+                    };
+                    let tcp_listener = match TcpListener::bind(&bind_addr).await {
+                        Ok(s) => s,
+                        Err(e) => {
+                            tracing::error!("Failed to bind TCP {}: {}", bind_addr, e);
+                            continue;
+                        }
+                    };
+                    tracing::info!("🚀 Server successfully bound to TCP & UDP on {} for {} zone(s)", bind_addr, routes.len());
+                    tasks.push(tokio::spawn(serve_udp(udp_socket, port, self.config.clone(), routes.clone())));
+                    tasks.push(tokio::spawn(serve_tcp(tcp_listener, None, port, self.config.clone(), routes)));
                 }
-            };
-            let tcp_listener = match TcpListener::bind(&bind_addr).await {
-                Ok(s) => Arc::new(s),
-                Err(e) => {
-                    tracing::error!("Failed to bind TCP {}: {}", bind_addr, e);
-                    continue;
+                "tls" => {
+                    let Some(acceptor) = self.tls_acceptor(&routes, &bind_addr, &[b"dot"]) else { continue };
+                    let tcp_listener = match TcpListener::bind(&bind_addr).await {
+                        Ok(s) => s,
+                        Err(e) => {
+                            tracing::error!("Failed to bind TLS {}: {}", bind_addr, e);
+                            continue;
+                        }
+                    };
+                    tracing::info!("🔒 Server successfully bound to DNS-over-TLS on {} for {} zone(s)", bind_addr, routes.len());
+                    tasks.push(tokio::spawn(serve_tcp(tcp_listener, Some(acceptor), port, self.config.clone(), routes)));
                 }
-            };
+                "https" => {
+                    let Some(acceptor) = self.tls_acceptor(&routes, &bind_addr, &[b"h2", b"http/1.1"]) else { continue };
+                    let tcp_listener = match TcpListener::bind(&bind_addr).await {
+                        Ok(s) => s,
+                        Err(e) => {
+                            tracing::error!("Failed to bind HTTPS {}: {}", bind_addr, e);
+                            continue;
+                        }
+                    };
+                    tracing::info!("🌐 Server successfully bound to DNS-over-HTTPS on {} for {} zone(s)", bind_addr, routes.len());
+                    tasks.push(tokio::spawn(serve_https(tcp_listener, acceptor, port, self.config.clone(), routes)));
+                }
+                "quic" => {
+                    let Some(tls_config) = self.tls_config(&routes, &bind_addr, &[b"doq"]) else { continue };
+                    let mut tls_config = (*tls_config).clone();
+                    // 允许客户端 0-RTT 携带查询 (DNS 查询可安全重放)
+                    tls_config.max_early_data_size = u32::MAX;
+                    let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(tls_config));
+                    let mut transport = quinn::TransportConfig::default();
+                    transport.max_concurrent_bidi_streams((TCP_MAX_INFLIGHT as u32).into());
+                    transport.max_concurrent_uni_streams(0u32.into());
+                    transport.max_idle_timeout(TCP_IDLE_TIMEOUT.try_into().ok());
+                    server_config.transport_config(Arc::new(transport));
 
-            let port = bind_addr.split(':').last().unwrap_or("53").parse::<u16>().unwrap_or(53);
-            tracing::info!("🚀 Server successfully bound to TCP & UDP on {} for {} zone(s)", bind_addr, zone_indices.len());
-
-            // ==============================
-            // 分支 1: UDP 协议处理流水线
-            // ==============================
-            let config_udp = self.config.clone();
-            let socket_udp = udp_socket.clone();
-            let zones_udp = zone_indices.clone();
-            
-            let udp_task = tokio::spawn(async move {
-                let mut buf = vec![0u8; 4096];
-                loop {
-                    if let Ok((size, src)) = socket_udp.recv_from(&mut buf).await {
-                        let query = buf[..size].to_vec();
-                        let config = config_udp.clone();
-                        let socket = socket_udp.clone();
-                        let z_indices = zones_udp.clone();
-
-                        tokio::spawn(async move {
-                            let mut msg = DnsMessage::default();
-                            msg.raw_query = query;
-                            msg.client_addr = Some(src);
-                            msg.protocol = "udp".to_string();
-                            msg.server_port = Some(port);
-
-                            if msg.raw_query.len() >= 12 {
-                                msg.header.id = ((msg.raw_query[0] as u16) << 8) | (msg.raw_query[1] as u16);
-                            }
-
-                            // 默认分配给绑定在该端口上的第一个 Zone 块配置
-                            let target_zone_idx = z_indices[0]; 
-                            let mut final_msg = msg.clone();
-
-                            for plugin in &config.zones[target_zone_idx].plugins {
-                                if final_msg.halt_chain { break; }
-                                if let Ok(new_msg) = plugin.process(&mut final_msg).await { final_msg = new_msg; }
-                            }
-                            for plugin in config.zones[target_zone_idx].plugins.iter().rev() {
-                                let _ = plugin.post_process(&mut final_msg).await;
-                            }
-
-                            if let Some(resp) = final_msg.raw_response {
-                                let mut final_resp = resp;
-                                if final_resp.len() > 1232 {
-                                    final_resp.truncate(1232);
-                                    final_resp[2] |= 0x02; // 打上 TC(Truncated) 截断标志
-                                }
-                                let _ = socket.send_to(&final_resp, src).await;
-                            }
-                        });
-                    }
+                    let endpoint = match bind_quic(server_config, &bind_addr).await {
+                        Ok(e) => e,
+                        Err(e) => {
+                            tracing::error!("Failed to bind QUIC {}: {}", bind_addr, e);
+                            continue;
+                        }
+                    };
+                    tracing::info!("⚡ Server successfully bound to DNS-over-QUIC on {} for {} zone(s)", bind_addr, routes.len());
+                    quic_endpoints.push(endpoint.clone());
+                    tasks.push(tokio::spawn(serve_quic(endpoint, port, self.config.clone(), routes)));
                 }
-            });
-            tasks.push(udp_task);
-
-            // ==============================
-            // 分支 2: TCP 协议处理流水线
-            // ==============================
-            let config_tcp = self.config.clone();
-            let listener_tcp = tcp_listener.clone();
-            let zones_tcp = zone_indices.clone();
-            
-            let tcp_task = tokio::spawn(async move {
-                loop {
-                    if let Ok((mut stream, src)) = listener_tcp.accept().await {
-                        let config = config_tcp.clone();
-                        let z_indices = zones_tcp.clone();
-
-                        tokio::spawn(async move {
-                            let mut len_buf = [0u8; 2];
-                            if stream.read_exact(&mut len_buf).await.is_err() { return; }
-                            let len = u16::from_be_bytes(len_buf) as usize;
-                            
-                            let mut query = vec![0u8; len];
-                            if stream.read_exact(&mut query).await.is_err() { return; }
-
-                            let mut msg = DnsMessage::default();
-                            msg.raw_query = query;
-                            msg.client_addr = Some(src);
-                            msg.protocol = "tcp".to_string();
-                            msg.server_port = Some(port);
-
-                            if msg.raw_query.len() >= 12 {
-                                msg.header.id = ((msg.raw_query[0] as u16) << 8) | (msg.raw_query[1] as u16);
-                            }
-
-                            let target_zone_idx = z_indices[0];
-                            let mut final_msg = msg.clone();
-
-                            for plugin in &config.zones[target_zone_idx].plugins {
-                                if final_msg.halt_chain { break; }
-                                if let Ok(new_msg) = plugin.process(&mut final_msg).await { final_msg = new_msg; }
-                            }
-                            for plugin in config.zones[target_zone_idx].plugins.iter().rev() {
-                                let _ = plugin.post_process(&mut final_msg).await;
-                            }
-
-                            if let Some(resp) = final_msg.raw_response {
-                                let resp_len = resp.len() as u16;
-                                let _ = stream.write_all(&resp_len.to_be_bytes()).await;
-                                let _ = stream.write_all(&resp).await;
-                            }
-                        });
+                other => tracing::error!("Unsupported server block scheme '{}://' on {}", other, bind_addr),
+            }
+        }
+
+        // ==============================
+        // 监听热重载与平滑退出
+        // ==============================
+        let is_reload = tokio::select! {
+            _ = reload_rx.changed() => true,
+            _ = shutdown_signal() => {
+                tracing::info!("Shutdown signal received, stopping listeners...");
+                false
+            }
+        };
+
+        // 立刻取消当前所有端口的监听任务，释放端口
+        for task in &tasks {
+            task.abort();
+        }
+        // 等待任务真正退出 (监听 socket 被 drop)，否则新配置重新绑定同一端口会失败
+        for task in tasks {
+            let _ = task.await;
+        }
+        for endpoint in quic_endpoints {
+            endpoint.close(0u32.into(), b"reload");
+            let _ = timeout(Duration::from_secs(1), endpoint.wait_idle()).await;
+        }
+        Ok(is_reload)
+    }
+
+    /// 取同一监听地址上第一个带 tls 指令的 server block 的证书构建 TLS 接入器
+    fn tls_acceptor(&self, routes: &[(String, usize)], bind_addr: &str, alpn: &[&[u8]]) -> Option<TlsAcceptor> {
+        self.tls_config(routes, bind_addr, alpn).map(TlsAcceptor::from)
+    }
+
+    fn tls_config(&self, routes: &[(String, usize)], bind_addr: &str, alpn: &[&[u8]]) -> Option<Arc<tokio_rustls::rustls::ServerConfig>> {
+        let Some(settings) = routes.iter().find_map(|(_, idx)| self.config.zones[*idx].tls.as_ref()) else {
+            tracing::error!("Server block on {} has no tls directive, listener not started", bind_addr);
+            return None;
+        };
+        match tls::server_config(settings, alpn) {
+            Ok(cfg) => Some(cfg),
+            Err(e) => {
+                tracing::error!("Failed to load TLS certificate for {}: {}", bind_addr, e);
+                None
+            }
+        }
+    }
+}
+
+/// 等待 Ctrl-C 或 SIGTERM (systemd 停止服务、容器重新部署)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        if let Ok(mut term) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = term.recv() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+// ==============================
+// UDP 协议处理流水线
+// ==============================
+async fn serve_udp(socket: Arc<UdpSocket>, port: u16, config: Arc<Config>, routes: ZoneRoutes) {
+    // 按 UDP 理论最大值接收，真正的应答上限由客户端 EDNS0 通告决定
+    let mut buf = vec![0u8; 65535];
+    loop {
+        if let Ok((size, src)) = socket.recv_from(&mut buf).await {
+            let query = buf[..size].to_vec();
+            let config = config.clone();
+            let socket = socket.clone();
+            let routes = routes.clone();
+
+            tokio::spawn(async move {
+                let msg = match parse_query(query, src, "udp", port) {
+                    Ok(m) => m,
+                    Err(Some(formerr)) => { let _ = socket.send_to(&formerr, src).await; return; }
+                    Err(None) => return,
+                };
+
+                // 非 EDNS 客户端按 RFC 1035 限制在 512 字节
+                let max_size = msg.edns.as_ref().map(|e| e.udp_payload_size.clamp(512, MAX_UDP_RESPONSE)).unwrap_or(512) as usize;
+                if let Some(resp) = handle_query(&config, &routes, msg).await {
+                    let mut final_resp = resp;
+                    if let Err(e) = wire::truncate(&mut final_resp, max_size) {
+                        // 无法按记录边界裁剪时只保留报文头，打上 TC(Truncated) 引导客户端改走 TCP
+                        tracing::debug!("Failed to truncate response for {}: {}", src, e);
+                        final_resp.resize(12, 0);
+                        final_resp[4..12].fill(0);
+                        final_resp[2] |= 0x02;
                     }
+                    let _ = socket.send_to(&final_resp, src).await;
                 }
             });
-            tasks.push(tcp_task);
         }
+    }
+}
 
-        // ==============================
-        // 分支 3: 监听热重载与平滑退出
-        // ==============================
-        tokio::select! {
-            _ = reload_rx.changed() => {
-                // 如果收到重载信号，立刻取消当前所有端口的监听任务，释放端口
-                for task in tasks {
-                    task.abort();
+// ==============================
+// TCP / DoT 协议处理流水线
+// ==============================
+async fn serve_tcp(listener: TcpListener, acceptor: Option<TlsAcceptor>, port: u16, config: Arc<Config>, routes: ZoneRoutes) {
+    loop {
+        if let Ok((stream, src)) = listener.accept().await {
+            let config = config.clone();
+            let routes = routes.clone();
+
+            match acceptor.clone() {
+                None => { tokio::spawn(serve_stream(stream, src, "tcp", port, config, routes)); }
+                Some(acceptor) => {
+                    tokio::spawn(async move {
+                        match timeout(TCP_READ_TIMEOUT, acceptor.accept(stream)).await {
+                            Ok(Ok(tls_stream)) => serve_stream(tls_stream, src, "tls", port, config, routes).await,
+                            Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", src, e),
+                            Err(_) => tracing::debug!("TLS handshake with {} timed out", src),
+                        }
+                    });
                 }
-                return Ok(true);
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// ==============================
+// DoH (RFC 8484) 协议处理流水线
+// ==============================
+async fn serve_https(listener: TcpListener, acceptor: TlsAcceptor, port: u16, config: Arc<Config>, routes: ZoneRoutes) {
+    loop {
+        let Ok((stream, src)) = listener.accept().await else { continue };
+        let (acceptor, config, routes) = (acceptor.clone(), config.clone(), routes.clone());
+
+        tokio::spawn(async move {
+            let tls_stream = match timeout(TCP_READ_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(s)) => s,
+                Ok(Err(e)) => { tracing::debug!("TLS handshake with {} failed: {}", src, e); return; }
+                Err(_) => { tracing::debug!("TLS handshake with {} timed out", src); return; }
+            };
+            // ALPN 协商出 h2 时直接走 HTTP/2，否则按 HTTP/1.1 处理
+            let is_h2 = tls_stream.get_ref().1.alpn_protocol() == Some(b"h2");
+
+            let service = service_fn(move |req| {
+                let (config, routes) = (config.clone(), routes.clone());
+                async move { Ok::<_, Infallible>(handle_doh(req, src, port, &config, &routes).await) }
+            });
+            let mut http = Http::new();
+            http.http2_only(is_h2);
+            if let Err(e) = http.serve_connection(tls_stream, service).await {
+                tracing::debug!("DoH connection from {} closed with error: {}", src, e);
+            }
+        });
+    }
+}
+
+async fn handle_doh(req: Request<Body>, src: SocketAddr, port: u16, config: &Config, routes: &[(String, usize)]) -> Response<Body> {
+    let server_label = format!("https://:{}", port);
+    let start = std::time::Instant::now();
+    DOH_REQUESTS_TOTAL.with_label_values(&[&server_label, req.method().as_str()]).inc();
+
+    let resp = match read_doh_query(req).await {
+        Ok(query) => match parse_query(query, src, "https", port) {
+            Ok(msg) => {
+                // 插件链没有给出应答时，DoH 不能像 UDP 那样静默丢弃，回 SERVFAIL
+                let mut servfail = msg.reply();
+                servfail.header.flags.rcode = 2;
+                let answer = handle_query(config, routes, msg).await.or_else(|| servfail.to_wire().ok()).unwrap_or_default();
+                let max_age = wire::min_ttl(&answer).unwrap_or(0);
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, DOH_MEDIA_TYPE)
+                    .header(header::CACHE_CONTROL, format!("max-age={}", max_age))
+                    .body(Body::from(answer))
+            }
+            Err(_) => Response::builder().status(StatusCode::BAD_REQUEST).body(Body::empty()),
+        },
+        Err(status) => Response::builder().status(status).body(Body::empty()),
+    }
+    .unwrap_or_else(|_| Response::new(Body::empty()));
+
+    DOH_RESPONSES_TOTAL.with_label_values(&[&server_label, resp.status().as_str()]).inc();
+    DOH_REQUEST_DURATION.with_label_values(&[&server_label]).observe(start.elapsed().as_secs_f64());
+    resp
+}
+
+/// 从 GET ?dns=<base64url> 或 POST application/dns-message 中取出 DNS 报文
+async fn read_doh_query(req: Request<Body>) -> std::result::Result<Vec<u8>, StatusCode> {
+    if req.uri().path() != DOH_PATH { return Err(StatusCode::NOT_FOUND); }
+
+    match *req.method() {
+        Method::GET => {
+            let param = req.uri().query().unwrap_or("").split('&').find_map(|kv| kv.strip_prefix("dns=")).ok_or(StatusCode::BAD_REQUEST)?;
+            URL_SAFE_NO_PAD.decode(param.trim_end_matches('=')).map_err(|_| StatusCode::BAD_REQUEST)
+        }
+        Method::POST => {
+            let content_type = req.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("");
+            if content_type != DOH_MEDIA_TYPE { return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE); }
+            if req.body().size_hint().lower() > u16::MAX as u64 { return Err(StatusCode::PAYLOAD_TOO_LARGE); }
+            let body = hyper::body::to_bytes(req.into_body()).await.map_err(|_| StatusCode::BAD_REQUEST)?;
+            if body.len() > u16::MAX as usize { return Err(StatusCode::PAYLOAD_TOO_LARGE); }
+            Ok(body.to_vec())
+        }
+        _ => Err(StatusCode::METHOD_NOT_ALLOWED),
+    }
+}
+
+// ==============================
+// DoQ (RFC 9250) 协议处理流水线
+// ==============================
+async fn serve_quic(endpoint: quinn::Endpoint, port: u16, config: Arc<Config>, routes: ZoneRoutes) {
+    while let Some(connecting) = endpoint.accept().await {
+        let (config, routes) = (config.clone(), routes.clone());
+        tokio::spawn(async move {
+            let conn = match timeout(TCP_READ_TIMEOUT, connecting).await {
+                Ok(Ok(c)) => c,
+                Ok(Err(e)) => { tracing::debug!("QUIC handshake failed: {}", e); return; }
+                Err(_) => { tracing::debug!("QUIC handshake timed out"); return; }
+            };
+            let src = conn.remote_address();
+
+            // 在途查询数由 max_concurrent_bidi_streams 限制，空闲连接由 max_idle_timeout 回收
+            while let Ok((send, recv)) = conn.accept_bi().await {
+                let (conn, config, routes) = (conn.clone(), config.clone(), routes.clone());
+                tokio::spawn(async move {
+                    if let Err(code) = serve_quic_stream(send, recv, src, port, &config, &routes).await {
+                        conn.close(code.into(), b"");
+                    }
+                });
+            }
+        });
+    }
+}
+
+/// 重载时旧端点的驱动任务在最后一个句柄释放后才异步关闭 socket，端口被占用时短暂重试
+async fn bind_quic(server_config: quinn::ServerConfig, bind_addr: &str) -> Result<quinn::Endpoint> {
+    let addr: SocketAddr = bind_addr.parse()?;
+    let mut retries = 20;
+    loop {
+        match quinn::Endpoint::server(server_config.clone(), addr) {
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && retries > 0 => {
+                retries -= 1;
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            other => return Ok(other?),
+        }
+    }
+}
+
+/// 每条双向流承载一个查询：2 字节长度前缀 + 报文，客户端发完即 FIN；AXFR / IXFR 的多个应答在同一条流上写出；协议错误时返回需要关闭连接的错误码
+async fn serve_quic_stream(mut send: quinn::SendStream, mut recv: quinn::RecvStream, src: SocketAddr, port: u16, config: &Config, routes: &[(String, usize)]) -> std::result::Result<(), u32> {
+    let Ok(Ok(buf)) = timeout(TCP_READ_TIMEOUT, recv.read_to_end(u16::MAX as usize + 2)).await else { return Ok(()) };
+    if buf.len() < 2 || u16::from_be_bytes([buf[0], buf[1]]) as usize != buf.len() - 2 { return Err(DOQ_PROTOCOL_ERROR); }
+    // RFC 9250 4.2.1: DoQ 报文 ID 必须为 0
+    if buf.len() >= 4 && buf[2..4] != [0, 0] { return Err(DOQ_PROTOCOL_ERROR); }
+
+    let messages = match parse_query(buf[2..].to_vec(), src, "quic", port) {
+        Ok(msg) => handle_query_stream(config, routes, msg).await,
+        Err(formerr) => formerr.into_iter().collect(),
+    };
+    if messages.is_empty() {
+        let _ = send.reset(DOQ_INTERNAL_ERROR.into());
+        return Ok(());
+    }
+    // RFC 9250 4.2: 区传送的多个应答报文在同一条流上依次写出
+    for resp in messages {
+        if send.write_all(&length_prefixed(resp)).await.is_err() { return Ok(()); }
+    }
+    let _ = send.finish().await;
+    Ok(())
+}
+
+/// RFC 7766: 在一条流式连接上循环读取多个查询，并发处理，按完成顺序写回
+async fn serve_stream<S>(stream: S, src: SocketAddr, protocol: &'static str, port: u16, config: Arc<Config>, routes: ZoneRoutes)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    // 每次发送一组应答报文：区传送的多个报文必须连续写出，不能与其他应答交错
+    let (tx, mut rx) = mpsc::channel::<Vec<Vec<u8>>>(TCP_MAX_INFLIGHT);
+
+    let writer_task = tokio::spawn(async move {
+        'conn: while let Some(messages) = rx.recv().await {
+            for resp in messages {
+                if writer.write_all(&length_prefixed(resp)).await.is_err() { break 'conn; }
+            }
+        }
+        let _ = writer.shutdown().await;
+    });
+
+    let inflight = Arc::new(Semaphore::new(TCP_MAX_INFLIGHT));
+    loop {
+        // 空闲超时：客户端长时间不发新查询就回收连接
+        let mut len_buf = [0u8; 2];
+        if !matches!(timeout(TCP_IDLE_TIMEOUT, reader.read_exact(&mut len_buf)).await, Ok(Ok(_))) { break; }
+        let len = u16::from_be_bytes(len_buf) as usize;
+
+        let mut query = vec![0u8; len];
+        if !matches!(timeout(TCP_READ_TIMEOUT, reader.read_exact(&mut query)).await, Ok(Ok(_))) {
+            tracing::debug!("Read timeout or error on {} connection from {}", protocol, src);
+            break;
+        }
+
+        // 单连接在途查询达到上限时暂停读取，形成背压
+        let Ok(permit) = inflight.clone().acquire_owned().await else { break };
+        let (tx, config, routes) = (tx.clone(), config.clone(), routes.clone());
+        tokio::spawn(async move {
+            let _permit = permit;
+            let messages = match parse_query(query, src, protocol, port) {
+                Ok(msg) => handle_query_stream(&config, &routes, msg).await,
+                Err(formerr) => formerr.into_iter().collect(),
+            };
+            if !messages.is_empty() { let _ = tx.send(messages).await; }
+        });
+    }
+
+    // 读端结束后，等仍在处理中的查询全部写回再关闭连接
+    drop(tx);
+    let _ = writer_task.await;
+}
+
+/// 加上 2 字节长度前缀；超过 65535 字节的应答按记录边界裁剪并置 TC，无法裁剪时改为 SERVFAIL
+fn length_prefixed(mut resp: Vec<u8>) -> Vec<u8> {
+    if resp.len() > u16::MAX as usize {
+        if let Err(e) = wire::truncate(&mut resp, u16::MAX as usize) {
+            tracing::debug!("Failed to truncate oversized response: {}", e);
+            resp.resize(12, 0);
+            resp[4..12].fill(0);
+            resp[3] = (resp[3] & 0xF0) | 0x02;
+        }
+    }
+    let mut frame = Vec::with_capacity(resp.len() + 2);
+    frame.extend_from_slice(&(resp.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&resp);
+    frame
+}
+
+/// 按 CoreDNS 语义选择 Zone：取 QNAME 的最长后缀匹配，"." 兜底，均不匹配返回 None
+fn select_zone(routes: &[(String, usize)], qname: &str) -> Option<usize> {
+    let qname = qname.trim_end_matches('.').to_ascii_lowercase();
+    routes
+        .iter()
+        .filter(|(origin, _)| {
+            origin == "." || qname == *origin || (qname.len() > origin.len() && qname.ends_with(origin.as_str()) && qname.as_bytes()[qname.len() - origin.len() - 1] == b'.')
+        })
+        .max_by_key(|(origin, _)| if origin == "." { 0 } else { origin.len() + 1 })
+        .map(|(_, idx)| *idx)
+}
+
+/// 路由到目标 Zone 并完整驱动其插件链 (process 正向 + post_process 逆向)，返回最终应答
+async fn handle_query(config: &Config, routes: &[(String, usize)], msg: DnsMessage) -> Option<Vec<u8>> {
+    handle_query_stream(config, routes, msg).await.into_iter().next()
+}
+
+/// 同 handle_query，但保留插件给出的后续报文 (AXFR / IXFR)，供流式连接依次写出
+async fn handle_query_stream(config: &Config, routes: &[(String, usize)], msg: DnsMessage) -> Vec<Vec<u8>> {
+    let query_edns = msg.edns.clone();
+    if let Some(edns) = &query_edns {
+        if edns.version > 0 {
+            // RFC 6891 6.1.3: 不支持的 EDNS 版本返回 BADVERS (扩展 RCODE 16)
+            let mut badvers = msg.reply();
+            badvers.edns = Some(Edns { extended_rcode: 1, ..Edns::new(SERVER_UDP_PAYLOAD) });
+            return badvers.to_wire().into_iter().collect();
+        }
+    }
+
+    let qname = msg.question().map(|q| q.name.as_str()).unwrap_or(".");
+    let Some(zone_idx) = select_zone(routes, qname) else {
+        tracing::debug!("No server block serves '{}', answering REFUSED", qname);
+        let mut refused = msg.reply();
+        refused.header.flags.ra = false;
+        refused.header.flags.rcode = 5;
+        refused.edns = query_edns.as_ref().map(|q| Edns { dnssec_ok: q.dnssec_ok, ..Edns::new(SERVER_UDP_PAYLOAD) });
+        return refused.to_wire().into_iter().collect();
+    };
+
+    let mut msg = msg;
+    msg.zone_index = Some(zone_idx);
+    let plugins: Vec<&dyn Plugin> = config.zones[zone_idx].plugins.iter().map(|p| p.as_ref()).collect();
+    let final_msg = drive_chain(&plugins, msg).await;
+
+    let Some(mut resp) = final_msg.raw_response else { return Vec::new(); };
+    finalize_edns(&mut resp, query_edns.as_ref());
+    std::iter::once(resp).chain(final_msg.raw_continuation).collect()
+}
+
+/// 不经客户端，只驱动 Zone 中优先级低于 `below` 的插件 (如 cache 的预取与过期刷新)，返回原始应答
+pub async fn resolve_downstream(config: &Config, zone_idx: usize, below: u8, msg: DnsMessage) -> Option<Vec<u8>> {
+    let plugins: Vec<&dyn Plugin> = config.zones.get(zone_idx)?.plugins.iter().map(|p| p.as_ref()).filter(|p| p.priority() < below).collect();
+    drive_chain(&plugins, msg).await.raw_response
+}
+
+/// process 按优先级正向执行直到有插件截断，post_process 逆向全部执行
+async fn drive_chain(plugins: &[&dyn Plugin], mut msg: DnsMessage) -> DnsMessage {
+    for plugin in plugins {
+        if msg.halt_chain { break; }
+        if let Ok(new_msg) = plugin.process(&mut msg).await { msg = new_msg; }
+    }
+    for plugin in plugins.iter().rev() {
+        let _ = plugin.post_process(&mut msg).await;
+    }
+    msg
+}
+
+/// 应答中的 OPT 只在客户端带了 EDNS0 时出现：统一替换为本端的 OPT，保留上游的扩展 RCODE、EDE 与客户端的 DO 位
+fn finalize_edns(resp: &mut Vec<u8>, query_edns: Option<&Edns>) {
+    let edns = query_edns.map(|q| {
+        let upstream = wire::read_opt(resp).ok().flatten();
+        Edns {
+            extended_rcode: upstream.as_ref().map(|u| u.extended_rcode).unwrap_or(0),
+            dnssec_ok: q.dnssec_ok,
+            options: upstream.map(|u| u.options.into_iter().filter(|(code, _)| *code == EDNS_OPTION_EDE).collect()).unwrap_or_default(),
+            ..Edns::new(SERVER_UDP_PAYLOAD)
+        }
+    });
+    if let Err(e) = wire::set_opt(resp, edns.as_ref()) {
+        tracing::debug!("Failed to rewrite OPT record in response: {}", e);
+    }
+}
+
+/// 解析入站报文并挂载连接上下文；无法解析时返回可选的 FORMERR 应答
+fn parse_query(query: Vec<u8>, src: SocketAddr, protocol: &str, port: u16) -> std::result::Result<DnsMessage, Option<Vec<u8>>> {
+    // QR=1 的报文是应答而不是查询，回复它可能形成两台服务器之间的应答循环，直接丢弃
+    if query.len() >= 3 && query[2] & 0x80 != 0 {
+        tracing::debug!("Dropping response message from {}", src);
+        return Err(None);
+    }
+    match DnsMessage::from_wire(&query) {
+        Ok(parsed) => Ok(DnsMessage {
+            raw_query: query,
+            client_addr: Some(src),
+            protocol: protocol.to_string(),
+            server_port: Some(port),
+            ..parsed
+        }),
+        Err(e) => {
+            tracing::debug!("Malformed query from {}: {}", src, e);
+            if query.len() < 12 { return Err(None); }
+            let mut resp = query[..12].to_vec();
+            resp[2] = (resp[2] & 0x78) | 0x80;
+            resp[3] = 0x01;
+            resp[4..12].fill(0);
+            Err(Some(resp))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{PluginConfig, ZoneConfig};
+    use crate::types::{DnsQuestion, QClass, QType, Record, ResourceRecord};
+
+    /// 测试用插件：对所有查询给出 A 192.0.2.1，名字以 slow 开头的查询延迟 300ms 才应答
+    struct Answer;
+
+    #[async_trait::async_trait]
+    impl Plugin for Answer {
+        fn name(&self) -> &str { "answer" }
+        fn from_config(_config: &PluginConfig, _shared: Arc<SharedState>) -> Result<Self> { anyhow::bail!("test only") }
+        async fn process(&self, msg: &mut DnsMessage) -> Result<DnsMessage> {
+            let name = msg.question().map(|q| q.name.clone()).unwrap_or_default();
+            if name.starts_with("slow") { tokio::time::sleep(Duration::from_millis(300)).await; }
+            let mut resp = msg.reply();
+            resp.answers.push(ResourceRecord { name, rclass: QClass::IN, ttl: 60, data: Record::A { addr: [192, 0, 2, 1].into() } });
+            msg.raw_response = Some(resp.to_wire()?);
+            msg.halt_chain = true;
+            Ok(msg.clone())
+        }
+        fn priority(&self) -> u8 { 100 }
+    }
+
+    fn answer_config() -> Arc<Config> {
+        Arc::new(Config { zones: vec![ZoneConfig { name: ".:53".to_string(), plugins: vec![Box::new(Answer)], tls: None }] })
+    }
+
+    #[test]
+    fn oversized_stream_responses_are_truncated() {
+        let mut resp = DnsMessage::default();
+        resp.header.flags.qr = true;
+        resp.questions.push(DnsQuestion { name: "big.example".to_string(), qtype: QType::TXT, qclass: QClass::IN });
+        let text = vec![vec![b'x'; 255]; 4];
+        resp.answers = (0..100).map(|_| ResourceRecord { name: "big.example".to_string(), rclass: QClass::IN, ttl: 60, data: Record::TXT { text: text.clone() } }).collect();
+        let wire = resp.to_wire().unwrap();
+        assert!(wire.len() > u16::MAX as usize);
+
+        let frame = length_prefixed(wire);
+        let len = u16::from_be_bytes([frame[0], frame[1]]) as usize;
+        assert_eq!(len, frame.len() - 2);
+        let decoded = DnsMessage::from_wire(&frame[2..]).unwrap();
+        assert!(decoded.header.flags.tc);
+        assert!(!decoded.answers.is_empty() && decoded.answers.len() < 100);
+
+        // 记录边界无法解析时只留报文头并改为 SERVFAIL
+        let mut garbage = resp.to_wire().unwrap()[..12].to_vec();
+        garbage.resize(u16::MAX as usize + 100, 0xFF);
+        let frame = length_prefixed(garbage);
+        assert_eq!(frame.len(), 14);
+        assert_eq!(frame[5] & 0x0F, 2);
+        assert_eq!(&frame[6..14], &[0; 8]);
+    }
+
+    #[test]
+    fn selects_the_longest_matching_zone() {
+        let routes = vec![(".".to_string(), 0), ("example.com".to_string(), 1), ("sub.example.com".to_string(), 2), ("other.org".to_string(), 3)];
+        assert_eq!(select_zone(&routes, "www.sub.example.com"), Some(2));
+        assert_eq!(select_zone(&routes, "Sub.Example.COM."), Some(2));
+        assert_eq!(select_zone(&routes, "www.example.com"), Some(1));
+        assert_eq!(select_zone(&routes, "example.com"), Some(1));
+        // 后缀只在标签边界处匹配，其余名字落到根
+        assert_eq!(select_zone(&routes, "badexample.com"), Some(0));
+        assert_eq!(select_zone(&routes, "notsub.example.com"), Some(1));
+        assert_eq!(select_zone(&routes, "."), Some(0));
+        // 路由表的顺序不影响结果
+        let reversed: Vec<_> = routes.iter().rev().cloned().collect();
+        assert_eq!(select_zone(&reversed, "a.sub.example.com"), Some(2));
+        assert_eq!(select_zone(&reversed, "a.test"), Some(0));
+        // 没有根 zone 时不匹配的名字无处可去
+        assert_eq!(select_zone(&routes[1..], "a.test"), None);
+        assert_eq!(select_zone(&routes[1..], "www.other.org"), Some(3));
+    }
+
+    fn query(name: &str, edns: Option<Edns>) -> DnsMessage {
+        let mut msg = DnsMessage::default();
+        msg.header.id = 0x4242;
+        msg.header.flags.rd = true;
+        msg.questions.push(DnsQuestion { name: name.to_string(), qtype: QType::A, qclass: QClass::IN });
+        msg.edns = edns;
+        msg.raw_query = msg.to_wire().unwrap();
+        msg
+    }
+
+    #[tokio::test]
+    async fn answers_badvers_for_unknown_edns_versions() {
+        let config = Config { zones: Vec::new() };
+        let routes = vec![(".".to_string(), 0)];
+        let resp = handle_query(&config, &routes, query("a.test", Some(Edns { version: 1, ..Edns::new(4096) }))).await.unwrap();
+        let resp = DnsMessage::from_wire(&resp).unwrap();
+        let edns = resp.edns.unwrap();
+        // 扩展 RCODE 16 = (1 << 4) | 0
+        assert_eq!((edns.extended_rcode, resp.header.flags.rcode), (1, 0));
+        assert_eq!((edns.version, edns.udp_payload_size), (0, SERVER_UDP_PAYLOAD));
+        assert_eq!(resp.header.id, 0x4242);
+
+        // 没有 zone 可以服务时为 REFUSED，OPT 只回给带了 EDNS 的客户端
+        let resp = DnsMessage::from_wire(&handle_query(&config, &routes[..0], query("a.test", Some(Edns { dnssec_ok: true, ..Edns::new(4096) }))).await.unwrap()).unwrap();
+        assert_eq!(resp.header.flags.rcode, 5);
+        assert!(resp.edns.is_some_and(|e| e.dnssec_ok && e.udp_payload_size == SERVER_UDP_PAYLOAD));
+        let resp = DnsMessage::from_wire(&handle_query(&config, &routes[..0], query("a.test", None)).await.unwrap()).unwrap();
+        assert!(resp.edns.is_none());
+    }
+
+    #[test]
+    fn finalize_edns_negotiates_the_opt_record() {
+        let mut upstream = query("a.test", None).reply();
+        upstream.edns = Some(Edns { extended_rcode: 1, dnssec_ok: true, options: vec![(8, vec![0, 1, 24, 0, 192, 0, 2]), (EDNS_OPTION_EDE, vec![0, 5])], ..Edns::new(4096) });
+        let upstream = upstream.to_wire().unwrap();
+
+        // 客户端带 EDNS：换成本端的载荷大小与客户端的 DO 位，只保留上游的扩展 RCODE 与 EDE
+        let mut resp = upstream.clone();
+        finalize_edns(&mut resp, Some(&Edns::new(512)));
+        let edns = DnsMessage::from_wire(&resp).unwrap().edns.unwrap();
+        assert_eq!(edns.udp_payload_size, SERVER_UDP_PAYLOAD);
+        assert!(!edns.dnssec_ok);
+        assert_eq!(edns.extended_rcode, 1);
+        assert_eq!(edns.options, vec![(EDNS_OPTION_EDE, vec![0, 5])]);
+
+        // 上游没有 OPT 时补上本端的
+        let mut resp = query("a.test", None).reply().to_wire().unwrap();
+        finalize_edns(&mut resp, Some(&Edns { dnssec_ok: true, ..Edns::new(1232) }));
+        let edns = DnsMessage::from_wire(&resp).unwrap().edns.unwrap();
+        assert!(edns.dnssec_ok && edns.options.is_empty() && edns.extended_rcode == 0);
+
+        // 客户端没有 EDNS：去掉上游的 OPT
+        let mut resp = upstream;
+        finalize_edns(&mut resp, None);
+        let resp = DnsMessage::from_wire(&resp).unwrap();
+        assert!(resp.edns.is_none() && resp.additional.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pipelined_stream_queries_are_answered_as_they_complete() {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let src: SocketAddr = "127.0.0.1:5353".parse().unwrap();
+        let serving = tokio::spawn(serve_stream(server, src, "tcp", 53, answer_config(), Arc::new(vec![(".".to_string(), 0)])));
+
+        // 先发慢查询，再发两个快查询，三个都在同一连接上连续写出
+        for (id, name) in [(1u16, "slow.test"), (2, "fast.test"), (3, "quick.test")] {
+            let mut msg = query(name, None);
+            msg.header.id = id;
+            client.write_all(&length_prefixed(msg.to_wire().unwrap())).await.unwrap();
+        }
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let mut len = [0u8; 2];
+            client.read_exact(&mut len).await.unwrap();
+            let mut resp = vec![0u8; u16::from_be_bytes(len) as usize];
+            client.read_exact(&mut resp).await.unwrap();
+            let resp = DnsMessage::from_wire(&resp).unwrap();
+            assert_eq!(resp.answers.len(), 1);
+            ids.push(resp.header.id);
+        }
+        // 快查询不必等前面的慢查询
+        assert_eq!(ids[2], 1);
+        ids.sort_unstable();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        // 客户端关闭后连接随之结束
+        drop(client);
+        assert!(timeout(Duration::from_secs(1), serving).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn doh_accepts_get_and_post() {
+        let (config, routes) = (answer_config(), vec![(".".to_string(), 0)]);
+        let src: SocketAddr = "127.0.0.1:5353".parse().unwrap();
+        let wire = query("doh.test", None).to_wire().unwrap();
+        let send = |req: Request<Body>| {
+            let (config, routes) = (config.clone(), routes.clone());
+            async move {
+                let resp = handle_doh(req, src, 443, &config, &routes).await;
+                let (status, content_type) = (resp.status(), resp.headers().get(header::CONTENT_TYPE).cloned());
+                let cache_control = resp.headers().get(header::CACHE_CONTROL).cloned();
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                (status, content_type, cache_control, body.to_vec())
+            }
+        };
+        let get = |query: String| Request::get(format!("{}?{}", DOH_PATH, query)).body(Body::empty()).unwrap();
+        let post = |content_type: &str, body: Vec<u8>| Request::post(DOH_PATH).header(header::CONTENT_TYPE, content_type).body(Body::from(body)).unwrap();
+
+        // GET 的 dns 参数是无填充的 base64url，带填充的也接受
+        for req in [get(format!("ct=x&dns={}", URL_SAFE_NO_PAD.encode(&wire))), get(format!("dns={}==", URL_SAFE_NO_PAD.encode(&wire))), post(DOH_MEDIA_TYPE, wire.clone())] {
+            let (status, content_type, cache_control, body) = send(req).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(content_type.unwrap(), DOH_MEDIA_TYPE);
+            assert_eq!(cache_control.unwrap(), "max-age=60");
+            let resp = DnsMessage::from_wire(&body).unwrap();
+            assert_eq!((resp.header.id, resp.answers.len()), (0x4242, 1));
+        }
+
+        assert_eq!(send(post("application/json", wire.clone())).await.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(send(get("name=doh.test".to_string())).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(send(get("dns=!!!".to_string())).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(send(get(format!("dns={}", URL_SAFE_NO_PAD.encode(&wire[..5])))).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(send(Request::get(format!("/other?dns={}", URL_SAFE_NO_PAD.encode(&wire))).body(Body::empty()).unwrap()).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(send(Request::put(DOH_PATH).body(Body::from(wire)).unwrap()).await.0, StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
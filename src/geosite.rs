@@ -0,0 +1,324 @@
+//! Domain rule lists in the v2ray / Xray style
+//!
+//! Rules are `domain:` (the name and its subdomains), `full:` (exact name), `keyword:` (substring)
+//! and `regexp:`. They come from inline arguments, plain text files with one rule per line
+//! (a bare name or a dnsmasq `server=/NAME/IP` line is a `domain:` rule), or `geosite:NAME[@ATTR]`
+//! entries of a `geosite.dat` file.
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+
+/// 一条命中的规则：来源 (内联规则本身、文件路径或 `geosite:NAME`) 与规则文本
+pub struct Match<'a> {
+    pub list: &'a str,
+    pub rule: String,
+}
+
+#[derive(Default)]
+pub struct DomainMatcher {
+    // 来源标签，规则中保存其下标
+    lists: Vec<String>,
+    full: HashMap<String, usize>,
+    domain: HashMap<String, usize>,
+    keyword: Vec<(String, usize)>,
+    regexp: Vec<(Regex, usize)>,
+}
+
+impl DomainMatcher {
+    /// 每个参数是一条规则、`geosite:NAME[@ATTR]` 或规则文件路径；geosite 条目从 `geosite` 文件读取
+    pub fn from_args(args: &[String], geosite: Option<&str>) -> Result<Self> {
+        let mut matcher = Self::default();
+        let mut wanted: Vec<(String, Option<String>)> = Vec::new();
+        for arg in args {
+            if let Some(name) = arg.strip_prefix("geosite:") {
+                let (code, attr) = name.split_once('@').map_or((name, None), |(c, a)| (c, Some(a.to_ascii_lowercase())));
+                wanted.push((code.to_ascii_uppercase(), attr));
+            } else if parse_rule(arg).is_some() {
+                let list = matcher.list(arg);
+                matcher.add(arg, list)?;
+            } else {
+                let content = std::fs::read_to_string(arg).with_context(|| format!("failed to read domain list {}", arg))?;
+                let list = matcher.list(arg);
+                for (no, line) in content.lines().enumerate() {
+                    // 忽略注释与 domain-list-community 源文件中规则后的 `@attr`；dnsmasq 的 `server=/DOMAIN/IP` 行取其中的域名
+                    let Some(line) = line.split('#').next().unwrap_or_default().split_whitespace().next() else { continue };
+                    let line = line.strip_prefix("server=/").and_then(|rest| rest.split('/').next()).unwrap_or(line);
+                    if line.is_empty() { continue; }
+                    matcher.add(line, list).with_context(|| format!("{}:{}", arg, no + 1))?;
+                }
+            }
+        }
+        if !wanted.is_empty() {
+            let Some(path) = geosite else { bail!("geosite:{} requires a 'geosite FILE' option", wanted[0].0.to_ascii_lowercase()); };
+            matcher.load_geosite(path, &wanted)?;
+        }
+        Ok(matcher)
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.full.len() + self.domain.len() + self.keyword.len() + self.regexp.len()
+    }
+
+    /// 按 full、domain、keyword、regexp 的顺序查找命中的规则；`name` 为小写、无尾点
+    pub fn find(&self, name: &str) -> Option<Match<'_>> {
+        let hit = |list: usize, kind: &str, value: &str| Some(Match { list: &self.lists[list], rule: format!("{}:{}", kind, value) });
+        if let Some(&list) = self.full.get(name) { return hit(list, "full", name); }
+        let mut suffix = name;
+        loop {
+            if let Some(&list) = self.domain.get(suffix) { return hit(list, "domain", suffix); }
+            match suffix.split_once('.') {
+                Some((_, rest)) => suffix = rest,
+                None => break,
+            }
+        }
+        if let Some((keyword, list)) = self.keyword.iter().find(|(k, _)| name.contains(k.as_str())) { return hit(*list, "keyword", keyword); }
+        if let Some((re, list)) = self.regexp.iter().find(|(re, _)| re.is_match(name)) { return hit(*list, "regexp", re.as_str()); }
+        None
+    }
+
+    fn list(&mut self, label: &str) -> usize {
+        self.lists.push(label.to_string());
+        self.lists.len() - 1
+    }
+
+    fn add(&mut self, rule: &str, list: usize) -> Result<()> {
+        let (kind, value) = parse_rule(rule).unwrap_or((Kind::Domain, rule));
+        self.insert(kind, value, list)
+    }
+
+    fn insert(&mut self, kind: Kind, value: &str, list: usize) -> Result<()> {
+        // 同一条规则出现在多个来源时记在第一个来源名下
+        match kind {
+            Kind::Full => { self.full.entry(value.trim_end_matches('.').to_ascii_lowercase()).or_insert(list); }
+            Kind::Domain => { self.domain.entry(value.trim_end_matches('.').to_ascii_lowercase()).or_insert(list); }
+            Kind::Keyword => self.keyword.push((value.to_ascii_lowercase(), list)),
+            Kind::Regexp => {
+                let re = regex::RegexBuilder::new(value).case_insensitive(true).build().with_context(|| format!("invalid regexp {}", value))?;
+                self.regexp.push((re, list));
+            }
+        }
+        Ok(())
+    }
+
+    /// 读取 geosite.dat 中指定的条目；attr 不为空时只取带有该属性的域名
+    fn load_geosite(&mut self, path: &str, wanted: &[(String, Option<String>)]) -> Result<()> {
+        let data = std::fs::read(path).with_context(|| format!("failed to read geosite file {}", path))?;
+        let mut found = vec![false; wanted.len()];
+        // GeoSiteList { repeated GeoSite entry = 1; }
+        for (tag, field) in fields(&data).with_context(|| format!("malformed geosite file {}", path))? {
+            let (1, Field::Bytes(site)) = (tag, field) else { continue };
+            // GeoSite { string country_code = 1; repeated Domain domain = 2; }
+            let site = fields(site)?;
+            let code = site.iter().find_map(|(tag, f)| match (tag, f) { (1, Field::Bytes(code)) => Some(String::from_utf8_lossy(code).to_ascii_uppercase()), _ => None });
+            let Some(code) = code else { continue };
+            for (i, (want, attr)) in wanted.iter().enumerate() {
+                if *want != code { continue; }
+                found[i] = true;
+                let label = match attr { Some(attr) => format!("geosite:{}@{}", want.to_ascii_lowercase(), attr), None => format!("geosite:{}", want.to_ascii_lowercase()) };
+                let list = self.list(&label);
+                for (_, domain) in site.iter().filter(|(tag, _)| *tag == 2) {
+                    let Field::Bytes(domain) = domain else { continue };
+                    let Some((kind, value, attrs)) = parse_geosite_domain(domain)? else { continue };
+                    if attr.as_ref().is_some_and(|a| !attrs.contains(a)) { continue; }
+                    // geosite 中的正则为 RE2 语法，个别写法不受支持时跳过该条
+                    if let Err(e) = self.insert(kind, &value, list) { tracing::warn!("[geosite] Skipping rule in {}: {:#}", label, e); }
+                }
+            }
+        }
+        if let Some(i) = found.iter().position(|f| !f) { bail!("geosite:{} not found in {}", wanted[i].0.to_ascii_lowercase(), path); }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind { Full, Domain, Keyword, Regexp }
+
+fn parse_rule(rule: &str) -> Option<(Kind, &str)> {
+    let (kind, value) = rule.split_once(':')?;
+    let kind = match kind {
+        "full" => Kind::Full,
+        "domain" => Kind::Domain,
+        "keyword" => Kind::Keyword,
+        "regexp" => Kind::Regexp,
+        _ => return None,
+    };
+    Some((kind, value))
+}
+
+/// Domain { Type type = 1; string value = 2; repeated Attribute attribute = 3; }
+/// Type: Plain (关键字) = 0, Regex = 1, Domain = 2, Full = 3；Attribute { string key = 1; ... }
+fn parse_geosite_domain(buf: &[u8]) -> Result<Option<(Kind, String, Vec<String>)>> {
+    let mut kind = Kind::Keyword;
+    let mut value = String::new();
+    let mut attrs = Vec::new();
+    for (tag, field) in fields(buf)? {
+        match (tag, field) {
+            (1, Field::Varint(t)) => kind = match t { 0 => Kind::Keyword, 1 => Kind::Regexp, 2 => Kind::Domain, 3 => Kind::Full, _ => return Ok(None) },
+            (2, Field::Bytes(v)) => value = String::from_utf8_lossy(v).into_owned(),
+            (3, Field::Bytes(attr)) => {
+                for (tag, field) in fields(attr)? {
+                    if let (1, Field::Bytes(key)) = (tag, field) { attrs.push(String::from_utf8_lossy(key).to_ascii_lowercase()); }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok((!value.is_empty()).then_some((kind, value, attrs)))
+}
+
+/// protobuf 字段值：geosite.dat 只用到 varint 与 length-delimited，定长字段读出后丢弃
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// 把一个 protobuf 消息拆成 (字段号, 值) 序列
+fn fields(buf: &[u8]) -> Result<Vec<(u64, Field<'_>)>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < buf.len() {
+        let key = varint(buf, &mut pos)?;
+        let field = match key & 7 {
+            0 => Field::Varint(varint(buf, &mut pos)?),
+            1 | 5 => {
+                pos += if key & 7 == 1 { 8 } else { 4 };
+                Field::Fixed
+            }
+            2 => {
+                let len = varint(buf, &mut pos)? as usize;
+                let end = pos.checked_add(len).filter(|&end| end <= buf.len()).context("truncated field")?;
+                let bytes = &buf[pos..end];
+                pos = end;
+                Field::Bytes(bytes)
+            }
+            other => bail!("unsupported wire type {}", other),
+        };
+        if pos > buf.len() { bail!("truncated field"); }
+        out.push((key >> 3, field));
+    }
+    Ok(out)
+}
+
+fn varint(buf: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf.get(*pos).context("truncated varint")?;
+        *pos += 1;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 { return Ok(value); }
+    }
+    bail!("varint too long")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 手工编码的 protobuf：length-delimited 字段与 varint 字段 (测试数据都短于 128 字节)
+    fn bytes(tag: u8, value: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag << 3 | 2, value.len() as u8];
+        out.extend_from_slice(value);
+        out
+    }
+
+    fn domain(kind: u8, value: &str, attrs: &[&str]) -> Vec<u8> {
+        let mut out = vec![1 << 3, kind];
+        out.extend(bytes(2, value.as_bytes()));
+        for attr in attrs {
+            out.extend(bytes(3, &bytes(1, attr.as_bytes())));
+        }
+        out
+    }
+
+    fn site(code: &str, domains: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = bytes(1, code.as_bytes());
+        for d in domains {
+            out.extend(bytes(2, d));
+        }
+        bytes(1, &out)
+    }
+
+    fn args(rules: &[&str]) -> Vec<String> {
+        rules.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn decodes_varints_and_fields() {
+        let mut pos = 0;
+        assert_eq!(varint(&[0xAC, 0x02, 0x01], &mut pos).unwrap(), 300);
+        assert_eq!(pos, 2);
+        assert!(varint(&[0x80, 0x80], &mut 0).err().unwrap().to_string().contains("truncated varint"));
+        assert!(varint(&[0xFF; 11], &mut 0).is_err());
+
+        // 定长字段被跳过，未知字段号原样保留
+        let mut buf = vec![1 << 3 | 1];
+        buf.extend([0; 8]);
+        buf.extend([7 << 3, 0x05]);
+        buf.extend(bytes(2, b"abc"));
+        let parsed = fields(&buf).unwrap();
+        assert_eq!(parsed.len(), 3);
+        assert!(matches!(parsed[0], (1, Field::Fixed)));
+        assert!(matches!(parsed[1], (7, Field::Varint(5))));
+        assert!(matches!(parsed[2], (2, Field::Bytes(b"abc"))));
+
+        assert!(fields(&[2 << 3 | 2, 5, b'a']).err().unwrap().to_string().contains("truncated field"));
+        assert!(fields(&[1 << 3 | 5, 0, 0]).err().unwrap().to_string().contains("truncated field"));
+        assert!(fields(&[1 << 3 | 2]).err().unwrap().to_string().contains("truncated varint"));
+        assert!(fields(&[1 << 3 | 3]).is_err());
+    }
+
+    #[test]
+    fn parses_geosite_domains() {
+        let (kind, value, attrs) = parse_geosite_domain(&domain(2, "example.com", &["CN", "ads"])).unwrap().unwrap();
+        assert!(matches!(kind, Kind::Domain));
+        assert_eq!(value, "example.com");
+        assert_eq!(attrs, vec!["cn", "ads"]);
+        assert!(matches!(parse_geosite_domain(&domain(3, "a.test", &[])).unwrap().unwrap().0, Kind::Full));
+        // 未知类型与空值的条目被忽略
+        assert!(parse_geosite_domain(&domain(9, "a.test", &[])).unwrap().is_none());
+        assert!(parse_geosite_domain(&domain(2, "", &[])).unwrap().is_none());
+    }
+
+    #[test]
+    fn matches_in_precedence_order() {
+        let matcher = DomainMatcher::from_args(&args(&["regexp:^www\\.", "keyword:exam", "domain:example.com", "full:www.example.com"]), None).unwrap();
+        assert_eq!(matcher.rule_count(), 4);
+        assert_eq!(matcher.find("www.example.com").unwrap().rule, "full:www.example.com");
+        assert_eq!(matcher.find("a.www.example.com").unwrap().rule, "domain:example.com");
+        assert_eq!(matcher.find("example.org").unwrap().rule, "keyword:exam");
+        assert_eq!(matcher.find("www.test").unwrap().rule, "regexp:^www\\.");
+        assert!(matcher.find("test").is_none());
+        // domain: 只在标签边界处匹配
+        let matcher = DomainMatcher::from_args(&args(&["domain:Example.COM."]), None).unwrap();
+        assert!(matcher.find("example.com").is_some());
+        assert!(matcher.find("badexample.com").is_none());
+    }
+
+    #[test]
+    fn loads_geosite_entries_with_attribute_filter() {
+        let mut data = site("cn", &[domain(2, "example.cn", &[]), domain(3, "ads.example.cn", &["ads"]), domain(0, "tracker", &["ads"])]);
+        data.extend(site("GOOGLE", &[domain(2, "google.com", &[])]));
+        let path = std::env::temp_dir().join(format!("coredns-geosite-{}.dat", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        let path = path.to_str().unwrap();
+
+        let all = DomainMatcher::from_args(&args(&["geosite:CN", "geosite:google"]), Some(path)).unwrap();
+        assert_eq!(all.rule_count(), 4);
+        let hit = all.find("www.google.com").unwrap();
+        assert_eq!((hit.list, hit.rule.as_str()), ("geosite:google", "domain:google.com"));
+
+        let ads = DomainMatcher::from_args(&args(&["geosite:cn@ADS"]), Some(path)).unwrap();
+        assert_eq!(ads.rule_count(), 2);
+        assert_eq!(ads.find("ads.example.cn").unwrap().list, "geosite:cn@ads");
+        assert!(ads.find("www.example.cn").is_none());
+        assert!(ads.find("tracker.test").is_some());
+
+        assert!(DomainMatcher::from_args(&args(&["geosite:cn"]), None).is_err());
+        assert!(DomainMatcher::from_args(&args(&["geosite:missing"]), Some(path)).err().unwrap().to_string().contains("not found"));
+        std::fs::write(path, &data[..data.len() - 3]).unwrap();
+        assert!(DomainMatcher::from_args(&args(&["geosite:cn"]), Some(path)).is_err());
+        let _ = std::fs::remove_file(path);
+    }
+}
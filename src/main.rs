@@ -1,14 +1,19 @@
 //! CoreDNS Rust - A DNS server written in Rust
 
+pub mod cidr;
 pub mod config;
 pub mod dns_server;
+pub mod geosite;
 pub mod plugin;
+pub mod tls;
 pub mod types;
+pub mod wire;
+pub mod zone;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
 use std::sync::Arc;
-use tracing::info;
+use tracing::{error, info};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 use chrono::Local;
 use rolling_file::{RollingConditionBasic, RollingFileAppender};
@@ -84,11 +89,31 @@ async fn async_main(cores: usize) -> Result<()> {
     // Moka LRU 缓存池初始化在此处，使得热重载时能够无损继承原有的 DNS 解析缓存
     let cache_preserve = Arc::new(plugin::cache::CacheStore::new());
 
+    // 最近一次成功加载的 Corefile 内容：热重载时新配置有误就沿用它，而不是退出
+    let mut last_good: Option<String> = None;
+
     // 核心热重载事件循环
     loop {
         info!("--- Starting/Reloading CoreDNS configuration ---");
-        let shared = Arc::new(plugin::SharedState::new_with_cache(cache_preserve.clone(), abs_path.clone()));
-        let cfg = config::Config::load(&abs_path, shared.clone())?;
+        let mut shared = Arc::new(plugin::SharedState::new_with_cache(cache_preserve.clone(), abs_path.clone()));
+        let loaded = std::fs::read_to_string(&abs_path)
+            .with_context(|| format!("Failed to read config file '{}'", abs_path))
+            .and_then(|content| Ok((config::Config::parse(&content, shared.clone())?, content)));
+        let cfg = match loaded {
+            Ok((cfg, content)) => {
+                last_good = Some(content);
+                cfg
+            }
+            Err(e) => {
+                let Some(previous) = &last_good else { return Err(e) };
+                error!("Failed to load new configuration, keeping the previous one: {:#}", e);
+                shared = Arc::new(plugin::SharedState::new_with_cache(cache_preserve.clone(), abs_path.clone()));
+                config::Config::parse(previous, shared.clone())?
+            }
+        };
+        // 新配置里已不存在或不再使用 cache 的 server block 不再占用缓存
+        let live = cfg.zones.iter().filter(|z| z.plugins.iter().any(|p| p.name() == "cache")).map(|z| z.key()).collect();
+        cache_preserve.prune(&live);
 
         for zone_config in &cfg.zones {
             info!("Zone: {} loaded with {} root plugins", zone_config.name, zone_config.plugins.len());
@@ -113,5 +138,9 @@ async fn async_main(cores: usize) -> Result<()> {
         info!("Hot reload triggered, rebuilding server instances...");
     }
 
+    // 退出前把配置了 persist 的缓存落盘，下次启动即可热启动
+    cache_preserve.persist_all();
+    info!("CoreDNS Rust stopped");
+
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,508 @@
+use crate::plugin::{file_stamp, Plugin, SharedState};
+use crate::config::{parse_duration, PluginConfig};
+use crate::types::{DnsMessage, QClass, QType, Record, ResourceRecord};
+use crate::plugin::prometheus::{BLOCK_ALLOWED_TOTAL, BLOCK_BLOCKED_TOTAL, BLOCK_LIST_ENTRIES};
+use crate::zone::{in_zone, normalize};
+use anyhow::{bail, Context, Result};
+use hyper::body::HttpBody;
+use hyper::{Body, Request, Uri};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+/// 下载远程列表的超时与大小上限
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_LIST_SIZE: usize = 64 * 1024 * 1024;
+const MAX_REDIRECTS: usize = 5;
+/// hosts 格式列表中常见的本机条目，不当作拦截规则
+const LOCAL_NAMES: &[&str] = &["localhost", "localhost.localdomain", "local", "broadcasthost", "ip6-localhost", "ip6-loopback", "0.0.0.0"];
+
+/// 规则作用的范围
+#[derive(Clone, Copy, PartialEq)]
+enum Scope {
+    /// hosts 条目或纯域名：只有该名字本身
+    Exact,
+    /// `*.example.com`：只有子域名
+    Below,
+    /// `||example.com^`：该名字及全部子域名
+    Both,
+}
+
+/// 按反转标签组织的后缀树；子节点按标签排序存放，查找时二分
+#[derive(Default)]
+struct Node {
+    children: Vec<(Box<str>, Node)>,
+    // 命中的列表下标 + 1，0 表示没有规则
+    exact: u16,
+    below: u16,
+}
+
+impl Node {
+    /// 由 (域名, 范围, 列表) 构建：按反转后的标签排序，有序插入时新子节点总在末尾，无需移动
+    fn build(mut rules: Vec<(String, Scope, u16)>) -> Self {
+        rules.sort_by(|a, b| a.0.rsplit('.').cmp(b.0.rsplit('.')));
+        let mut root = Node::default();
+        for (domain, scope, list) in rules {
+            let mut node = &mut root;
+            for label in domain.rsplit('.') {
+                if node.children.last().map(|(l, _)| l.as_ref()) != Some(label) {
+                    node.children.push((label.into(), Node::default()));
+                }
+                node = &mut node.children.last_mut().expect("child just pushed").1;
+            }
+            // 同一名字出现在多个列表中时记在第一个列表名下
+            let tag = list + 1;
+            if scope != Scope::Below && node.exact == 0 { node.exact = tag; }
+            if scope != Scope::Exact && node.below == 0 { node.below = tag; }
+        }
+        root.shrink();
+        root
+    }
+
+    fn shrink(&mut self) {
+        self.children.shrink_to_fit();
+        for (_, child) in &mut self.children { child.shrink(); }
+    }
+
+    /// 返回命中规则所属的列表下标：名字本身的精确规则优先，其次为最近一级祖先的子域名规则
+    fn lookup(&self, name: &str) -> Option<usize> {
+        let mut node = self;
+        let mut hit = 0;
+        for label in name.rsplit('.') {
+            if node.below != 0 { hit = node.below; }
+            match node.children.binary_search_by(|(l, _)| l.as_ref().cmp(label)) {
+                Ok(i) => node = &node.children[i].1,
+                Err(_) => return (hit != 0).then(|| hit as usize - 1),
+            }
+        }
+        let tag = if node.exact != 0 { node.exact } else { hit };
+        (tag != 0).then(|| tag as usize - 1)
+    }
+}
+
+/// 当前生效的拦截表与放行表
+#[derive(Default)]
+struct Lists {
+    block: Node,
+    allow: Node,
+}
+
+/// 一个列表来源：本地文件或 http(s) URL
+struct Source {
+    location: String,
+    label: String,
+    remote: bool,
+    // allowlist 来源中的所有规则都进放行表
+    allow: bool,
+}
+
+/// 列表内容的缓存与变化检测，只在后台任务中使用
+struct Loader {
+    server: String,
+    sources: Vec<Source>,
+    inline_allow: Vec<String>,
+    contents: Vec<Option<Arc<str>>>,
+    stamps: Vec<Option<(SystemTime, u64)>>,
+    fetched: Vec<Option<Instant>>,
+}
+
+#[derive(Clone, Copy)]
+enum Response {
+    NxDomain,
+    Refused,
+    /// A 应答 0.0.0.0，AAAA 应答 ::，其余类型为空应答
+    Zero,
+    /// A / AAAA 应答指定地址，未配置的族为空应答
+    Sinkhole { v4: Option<Ipv4Addr>, v6: Option<Ipv6Addr> },
+}
+
+pub struct BlockPlugin {
+    server: String,
+    zones: Vec<String>,
+    response: Response,
+    ttl: u32,
+    // 列表下标 -> 指标标签
+    labels: Vec<String>,
+    lists: Arc<RwLock<Arc<Lists>>>,
+    // 检查本地文件变化与刷新远程列表的任务，插件销毁 (热重载) 时取消
+    reload_task: Option<tokio::task::JoinHandle<()>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for BlockPlugin {
+    fn name(&self) -> &str { "block" }
+
+    fn from_config(config: &PluginConfig, _shared: Arc<SharedState>) -> Result<Self> {
+        // block [ZONES...]
+        let mut zones: Vec<String> = config.args.iter().map(|z| normalize(z)).collect();
+        if zones.is_empty() { zones.push(config.origin()); }
+
+        let mut sources = Vec::new();
+        let mut inline_allow = Vec::new();
+        let mut response = Response::NxDomain;
+        let mut ttl = 60;
+        let mut reload = Duration::from_secs(60);
+        let mut refresh = Duration::from_secs(24 * 3600);
+
+        for sub in &config.block {
+            match sub.name.as_str() {
+                // list PATH|URL [NAME] / allowlist PATH|URL [NAME]
+                "list" | "allowlist" => {
+                    let Some(location) = sub.args.first() else { bail!("{} requires a file or URL", sub.name); };
+                    let remote = location.starts_with("http://") || location.starts_with("https://");
+                    let label = sub.args.get(1).cloned().unwrap_or_else(|| location.clone());
+                    sources.push(Source { location: location.clone(), label, remote, allow: sub.name == "allowlist" });
+                }
+                "allow" => inline_allow.extend(sub.args.iter().cloned()),
+                "response" => response = parse_response(&sub.args)?,
+                "ttl" => {
+                    let Some(value) = sub.args.first() else { bail!("ttl requires a value"); };
+                    ttl = value.parse().with_context(|| format!("invalid block ttl {}", value))?;
+                }
+                "reload" => {
+                    let Some(value) = sub.args.first() else { bail!("reload requires a duration"); };
+                    reload = parse_duration(value)?;
+                }
+                "refresh" => {
+                    let Some(value) = sub.args.first() else { bail!("refresh requires a duration"); };
+                    refresh = parse_duration(value)?.max(Duration::from_secs(60));
+                }
+                other => bail!("unknown block option {}", other),
+            }
+        }
+        if !sources.iter().any(|s| !s.allow) { bail!("block requires at least one 'list'"); }
+
+        // 本地列表同步加载，远程列表由后台任务在启动后立即下载
+        let mut loader = Loader {
+            server: config.zone.clone(),
+            contents: sources.iter().map(|s| (!s.remote).then(|| read_local(&s.location)).flatten()).collect(),
+            stamps: sources.iter().map(|s| if s.remote { None } else { file_stamp(&s.location) }).collect(),
+            fetched: sources.iter().map(|_| None).collect(),
+            sources, inline_allow,
+        };
+        let labels = loader.sources.iter().map(|s| s.label.clone()).collect();
+        let lists = Arc::new(RwLock::new(Arc::new(loader.build())));
+
+        let has_remote = loader.sources.iter().any(|s| s.remote);
+        let reload_task = (has_remote || !reload.is_zero()).then(|| {
+            let lists = lists.clone();
+            // reload 0 时不监视本地文件，只按 refresh 刷新远程列表
+            let tick = if reload.is_zero() { refresh } else { reload };
+            tokio::spawn(async move {
+                loop {
+                    if loader.poll(!reload.is_zero(), refresh).await {
+                        let (contents, fresh) = (loader.contents.clone(), loader.snapshot());
+                        let Ok(built) = tokio::task::spawn_blocking(move || fresh.build_from(&contents)).await else { continue };
+                        *lists.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(built);
+                    }
+                    tokio::time::sleep(tick).await;
+                }
+            })
+        });
+
+        Ok(Self { server: config.zone.clone(), zones, response, ttl, labels, lists, reload_task })
+    }
+
+    async fn process(&self, msg: &mut DnsMessage) -> Result<DnsMessage> {
+        if msg.halt_chain { return Ok(msg.clone()); }
+        let Some(question) = msg.question().cloned() else { return Ok(msg.clone()); };
+        let qname = question.name.trim_end_matches('.').to_ascii_lowercase();
+        if qname == "." || !self.zones.iter().any(|z| in_zone(&qname, z)) { return Ok(msg.clone()); }
+
+        let lists = self.lists.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let Some(list) = lists.block.lookup(&qname) else { return Ok(msg.clone()); };
+        if lists.allow.lookup(&qname).is_some() {
+            BLOCK_ALLOWED_TOTAL.with_label_values(&[&self.server]).inc();
+            tracing::debug!("TxID: {:#06x} -> [block] '{}' is listed but allowed", msg.header.id, question.name);
+            return Ok(msg.clone());
+        }
+        let label = self.labels.get(list).map(String::as_str).unwrap_or_default();
+        BLOCK_BLOCKED_TOTAL.with_label_values(&[&self.server, label]).inc();
+
+        let mut resp = msg.reply();
+        let addr = match (self.response, question.qtype) {
+            (Response::NxDomain, _) => { resp.header.flags.rcode = 3; None }
+            (Response::Refused, _) => { resp.header.flags.rcode = 5; None }
+            (Response::Zero, QType::A) => Some(Record::A { addr: Ipv4Addr::UNSPECIFIED }),
+            (Response::Zero, QType::AAAA) => Some(Record::AAAA { addr: Ipv6Addr::UNSPECIFIED }),
+            (Response::Sinkhole { v4: Some(addr), .. }, QType::A) => Some(Record::A { addr }),
+            (Response::Sinkhole { v6: Some(addr), .. }, QType::AAAA) => Some(Record::AAAA { addr }),
+            _ => None,
+        };
+        if let Some(data) = addr {
+            resp.answers.push(ResourceRecord { name: question.name.clone(), rclass: QClass::IN, ttl: self.ttl, data });
+        }
+
+        tracing::debug!("TxID: {:#06x} -> [block] Blocked '{}' (list: {})", msg.header.id, question.name, label);
+        msg.raw_response = Some(resp.to_wire()?);
+        msg.answered_by = "block".to_string();
+        msg.halt_chain = true;
+        Ok(msg.clone())
+    }
+
+    fn priority(&self) -> u8 { 125 }
+}
+
+impl Drop for BlockPlugin {
+    fn drop(&mut self) {
+        if let Some(task) = &self.reload_task { task.abort(); }
+    }
+}
+
+/// 构建拦截表所需的来源信息，可移入 spawn_blocking
+struct Snapshot {
+    server: String,
+    sources: Vec<(String, bool)>,
+    inline_allow: Vec<String>,
+}
+
+impl Snapshot {
+    fn build_from(&self, contents: &[Option<Arc<str>>]) -> Lists {
+        let mut block = Vec::new();
+        let mut allow: Vec<(String, Scope, u16)> = self.inline_allow.iter().flat_map(|line| parse_line(line)).map(|(domain, scope, _)| (domain, scope, 0)).collect();
+        for (i, ((label, is_allow), content)) in self.sources.iter().zip(contents).enumerate() {
+            let mut count = 0;
+            for line in content.as_deref().unwrap_or_default().lines() {
+                for (domain, scope, exception) in parse_line(line) {
+                    if *is_allow || exception { allow.push((domain, scope, i as u16)); }
+                    else { block.push((domain, scope, i as u16)); count += 1; }
+                }
+            }
+            if !is_allow { BLOCK_LIST_ENTRIES.with_label_values(&[&self.server, label]).set(count as f64); }
+        }
+        tracing::info!("[block] Loaded {} blocked and {} allowed domains from {} list(s)", block.len(), allow.len(), self.sources.len());
+        Lists { block: Node::build(block), allow: Node::build(allow) }
+    }
+}
+
+impl Loader {
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            server: self.server.clone(),
+            sources: self.sources.iter().map(|s| (s.label.clone(), s.allow)).collect(),
+            inline_allow: self.inline_allow.clone(),
+        }
+    }
+
+    fn build(&self) -> Lists {
+        self.snapshot().build_from(&self.contents)
+    }
+
+    /// 重新读取变化的本地文件、下载到期的远程列表；返回内容是否有变化
+    async fn poll(&mut self, watch_local: bool, refresh: Duration) -> bool {
+        let mut changed = false;
+        for (i, source) in self.sources.iter().enumerate() {
+            if source.remote {
+                if self.fetched[i].is_some_and(|at| at.elapsed() < refresh) { continue; }
+                // 下载失败时保留旧内容，下一轮重试
+                match fetch(&source.location).await {
+                    Ok(body) => {
+                        self.fetched[i] = Some(Instant::now());
+                        if self.contents[i].as_deref() != Some(body.as_str()) {
+                            tracing::info!("[block] Downloaded {} ({} bytes)", source.location, body.len());
+                            self.contents[i] = Some(body.into());
+                            changed = true;
+                        }
+                    }
+                    Err(e) => tracing::warn!("[block] Failed to download {}: {:#}", source.location, e),
+                }
+            } else if watch_local {
+                let stamp = file_stamp(&source.location);
+                if stamp == self.stamps[i] { continue; }
+                self.stamps[i] = stamp;
+                // 读取失败 (如文件被删除) 时同样保留旧内容
+                let Some(content) = read_local(&source.location) else { continue };
+                tracing::info!("[block] {} changed, reloading", source.location);
+                self.contents[i] = Some(content);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// 解析列表中的一行，格式按行识别：hosts (`0.0.0.0 a.com b.com`)、纯域名、`*.a.com`、
+/// AdGuard / ABP 的 `||a.com^` 与例外规则 `@@||a.com^`；返回 (域名, 范围, 是否为例外)
+fn parse_line(line: &str) -> Vec<(String, Scope, bool)> {
+    let line = line.trim();
+    // 注释与浏览器的元素隐藏规则
+    if line.is_empty() || line.starts_with(['#', '!', '[']) || line.contains("##") || line.contains("#@#") { return Vec::new(); }
+    let (line, exception) = match line.strip_prefix("@@") { Some(rest) => (rest, true), None => (line, false) };
+
+    if let Some(rule) = line.strip_prefix("||") {
+        let (domain, tail) = rule.split_once('^').unwrap_or((rule, ""));
+        // 带路径或除 $important 外其他修饰符的规则只对浏览器有意义
+        let options = tail.strip_prefix('$').unwrap_or(tail);
+        if !(options.is_empty() || options == "important") { return Vec::new(); }
+        return domain_name(domain).map(|domain| vec![(domain, Scope::Both, exception)]).unwrap_or_default();
+    }
+
+    let line = line.split('#').next().unwrap_or_default();
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+        [addr, names @ ..] if !names.is_empty() && addr.parse::<IpAddr>().is_ok() => names.iter()
+            .filter(|n| !LOCAL_NAMES.contains(&n.to_ascii_lowercase().as_str()))
+            .filter_map(|n| domain_name(n))
+            .map(|domain| (domain, Scope::Exact, exception))
+            .collect(),
+        [domain] => match domain.strip_prefix("*.") {
+            Some(rest) => domain_name(rest).map(|domain| vec![(domain, Scope::Below, exception)]).unwrap_or_default(),
+            None => domain_name(domain).map(|domain| vec![(domain, Scope::Exact, exception)]).unwrap_or_default(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// 校验并规范化域名：小写、无尾点、没有空标签
+fn domain_name(domain: &str) -> Option<String> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let valid = domain.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.');
+    (valid && !domain.split('.').any(str::is_empty)).then_some(domain)
+}
+
+/// response nxdomain|refused|zero|sinkhole IP [IP]
+fn parse_response(args: &[String]) -> Result<Response> {
+    let Some((mode, addrs)) = args.split_first() else { bail!("response requires a mode"); };
+    Ok(match mode.as_str() {
+        "nxdomain" => Response::NxDomain,
+        "refused" => Response::Refused,
+        "zero" | "null" => Response::Zero,
+        "sinkhole" => {
+            let (mut v4, mut v6) = (None, None);
+            for addr in addrs {
+                match addr.parse::<IpAddr>().with_context(|| format!("invalid sinkhole address {}", addr))? {
+                    IpAddr::V4(ip) => v4 = Some(ip),
+                    IpAddr::V6(ip) => v6 = Some(ip),
+                }
+            }
+            if v4.is_none() && v6.is_none() { bail!("sinkhole requires an address"); }
+            Response::Sinkhole { v4, v6 }
+        }
+        other => bail!("unknown block response {}", other),
+    })
+}
+
+fn read_local(path: &str) -> Option<Arc<str>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Some(content.into()),
+        Err(e) => { tracing::warn!("[block] Failed to read {}: {}", path, e); None }
+    }
+}
+
+/// HTTP/1.1 GET，跟随重定向
+async fn fetch(url: &str) -> Result<String> {
+    let mut uri: Uri = url.parse().context("invalid URL")?;
+    for _ in 0..=MAX_REDIRECTS {
+        let response = timeout(FETCH_TIMEOUT, get(&uri)).await.context("download timed out")??;
+        match response {
+            Fetched::Body(body) => return String::from_utf8(body).context("list is not valid UTF-8"),
+            Fetched::Redirect(location) => uri = redirect_target(&uri, &location)?,
+        }
+    }
+    bail!("too many redirects")
+}
+
+/// 重定向的目标地址：相对地址沿用当前的 scheme 与 host；不允许从 https 降级到 http，也不跟随其他 scheme
+fn redirect_target(uri: &Uri, location: &str) -> Result<Uri> {
+    let next = match location.parse::<Uri>() {
+        Ok(next) if next.scheme().is_some() => next,
+        _ => format!("{}://{}{}", uri.scheme_str().unwrap_or("https"), uri.authority().map(|a| a.as_str()).unwrap_or_default(), location).parse()?,
+    };
+    match (uri.scheme_str(), next.scheme_str()) {
+        (Some("https"), Some("https")) | (Some("http"), Some("http" | "https")) => Ok(next),
+        _ => bail!("refusing redirect from {} to {}", uri, next),
+    }
+}
+
+enum Fetched {
+    Body(Vec<u8>),
+    Redirect(String),
+}
+
+async fn get(uri: &Uri) -> Result<Fetched> {
+    let host = uri.host().context("URL without host")?.trim_matches(|c| c == '[' || c == ']').to_string();
+    let https = uri.scheme_str() == Some("https");
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+    let tcp = TcpStream::connect((host.as_str(), port)).await?;
+    if !https { return request(tcp, uri, &host).await; }
+
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+    }));
+    let config = ClientConfig::builder().with_safe_defaults().with_root_certificates(roots).with_no_client_auth();
+    let domain = ServerName::try_from(host.as_str()).map_err(|_| anyhow::anyhow!("invalid server name {}", host))?;
+    let tls = TlsConnector::from(Arc::new(config)).connect(domain, tcp).await?;
+    request(tls, uri, &host).await
+}
+
+async fn request<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(stream: S, uri: &Uri, host: &str) -> Result<Fetched> {
+    let (mut sender, connection) = hyper::client::conn::handshake(stream).await?;
+    tokio::spawn(async move { let _ = connection.await; });
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let req = Request::get(path)
+        .header(hyper::header::HOST, uri.authority().map(|a| a.as_str()).unwrap_or(host))
+        .header(hyper::header::USER_AGENT, concat!("coredns-rust/", env!("CARGO_PKG_VERSION")))
+        .body(Body::empty())?;
+    let resp = sender.send_request(req).await?;
+
+    let status = resp.status();
+    if status.is_redirection() {
+        let location = resp.headers().get(hyper::header::LOCATION).and_then(|l| l.to_str().ok()).context("redirect without Location")?;
+        return Ok(Fetched::Redirect(location.to_string()));
+    }
+    if !status.is_success() { bail!("HTTP {}", status); }
+
+    let mut body = resp.into_body();
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        data.extend_from_slice(&chunk?);
+        if data.len() > MAX_LIST_SIZE { bail!("list larger than {} bytes", MAX_LIST_SIZE); }
+    }
+    Ok(Fetched::Body(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects_never_downgrade() {
+        let https: Uri = "https://lists.example/hosts".parse().unwrap();
+        let http: Uri = "http://lists.example/hosts".parse().unwrap();
+        assert_eq!(redirect_target(&https, "/v2/hosts").unwrap().to_string(), "https://lists.example/v2/hosts");
+        assert_eq!(redirect_target(&https, "https://cdn.example/hosts").unwrap().to_string(), "https://cdn.example/hosts");
+        assert_eq!(redirect_target(&http, "https://cdn.example/hosts").unwrap().to_string(), "https://cdn.example/hosts");
+        assert_eq!(redirect_target(&http, "/v2/hosts").unwrap().to_string(), "http://lists.example/v2/hosts");
+        assert!(redirect_target(&https, "http://cdn.example/hosts").is_err());
+        assert!(redirect_target(&https, "ftp://cdn.example/hosts").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        let dir = std::env::temp_dir().join(format!("coredns-block-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let list = dir.join("hosts.txt");
+        std::fs::write(&list, "0.0.0.0 ads.example\n").unwrap();
+
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        let block = |option: Option<(&str, &str)>| {
+            let sub = |name: &str, arg: &str| PluginConfig { name: name.to_string(), args: vec![arg.to_string()], block: Vec::new(), zone: String::new() };
+            let mut block = vec![sub("list", list.to_str().unwrap()), sub("reload", "0")];
+            block.extend(option.map(|(name, arg)| sub(name, arg)));
+            let config = PluginConfig { name: "block".to_string(), args: Vec::new(), block, zone: "dns://.:53".to_string() };
+            BlockPlugin::from_config(&config, shared.clone())
+        };
+        assert!(block(None).is_ok());
+        assert!(block(Some(("ttl", "300"))).is_ok());
+        for (name, arg) in [("ttl", "-1"), ("ttl", "5m"), ("reload", "soon"), ("refresh", "1d")] {
+            assert!(block(Some((name, arg))).is_err(), "{} {}", name, arg);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
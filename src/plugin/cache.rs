@@ -1,38 +1,233 @@
 use crate::plugin::{Plugin, SharedState};
-use crate::config::PluginConfig;
-use crate::types::DnsMessage;
-use crate::plugin::prometheus::{CACHE_REQUESTS_TOTAL, CACHE_HITS_TOTAL, CACHE_MISSES_TOTAL, CACHE_ENTRIES};
+use crate::config::{parse_duration, PluginConfig};
+use crate::types::{DnsMessage, QType};
+use crate::wire;
+use crate::plugin::prometheus::{CACHE_REQUESTS_TOTAL, CACHE_HITS_TOTAL, CACHE_MISSES_TOTAL, CACHE_ENTRIES, CACHE_SERVED_STALE_TOTAL, CACHE_PREFETCH_TOTAL};
 use anyhow::Result;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use moka::sync::Cache;
 
+/// RFC 8767 建议的过期应答 TTL
+const STALE_TTL: u32 = 30;
+
+/// 缓存快照文件头 (格式版本 1)
+const SNAPSHOT_MAGIC: &[u8; 5] = b"CDRC\x01";
+
 #[derive(Clone)]
 pub struct CachedItem {
     pub response: Vec<u8>,
+    pub stored_at: Instant,
     pub expires_at: Instant,
+    /// 命中次数，所有克隆共享，用于判断是否值得预取
+    pub hits: Arc<AtomicU32>,
 }
 
-pub struct CacheStore {
+/// 单个 server block 独享的缓存空间
+pub struct CacheNamespace {
     pub success: Cache<Vec<u8>, CachedItem>,
     pub denial: Cache<Vec<u8>, CachedItem>,
+    capacity: (u64, u64),
+    /// persist 快照路径；进程生命周期内只从快照恢复一次
+    persist_path: Mutex<Option<String>>,
+    restored: AtomicBool,
 }
 
-impl CacheStore {
-    pub fn new() -> Self {
+impl CacheNamespace {
+    fn new(success_capacity: u64, denial_capacity: u64) -> Self {
         Self {
             // Moka 会使用高效的 W-TinyLFU 算法自动淘汰，无需手动遍历锁
-            success: Cache::builder().max_capacity(50_000).build(),
-            denial: Cache::builder().max_capacity(50_000).build(),
+            success: Cache::builder().max_capacity(success_capacity).build(),
+            denial: Cache::builder().max_capacity(denial_capacity).build(),
+            capacity: (success_capacity, denial_capacity),
+            persist_path: Mutex::new(None),
+            restored: AtomicBool::new(false),
+        }
+    }
+
+    /// 把未过期的条目写入快照：先写临时文件再原子替换，避免中途崩溃留下半个文件。
+    /// 条目格式: 类型(u8) | 写入时刻(u64 ms) | 过期时刻(u64 ms) | 键长(u16) | 键 | 应答长(u16) | 应答，时刻均为 Unix 墙钟
+    pub fn save(&self, path: &str) -> Result<usize> {
+        let (now, now_ms) = (Instant::now(), unix_millis());
+        let mut buf = SNAPSHOT_MAGIC.to_vec();
+        let mut count = 0;
+        for (kind, cache) in [(0u8, &self.success), (1u8, &self.denial)] {
+            for (key, item) in cache.iter() {
+                if item.expires_at <= now || key.len() > u16::MAX as usize || item.response.len() > u16::MAX as usize { continue; }
+                buf.push(kind);
+                buf.extend_from_slice(&now_ms.saturating_sub(now.duration_since(item.stored_at).as_millis() as u64).to_be_bytes());
+                buf.extend_from_slice(&(now_ms + item.expires_at.duration_since(now).as_millis() as u64).to_be_bytes());
+                buf.extend_from_slice(&(key.len() as u16).to_be_bytes());
+                buf.extend_from_slice(&key);
+                buf.extend_from_slice(&(item.response.len() as u16).to_be_bytes());
+                buf.extend_from_slice(&item.response);
+                count += 1;
+            }
+        }
+
+        if let Some(dir) = std::path::Path::new(path).parent() {
+            if !dir.as_os_str().is_empty() { std::fs::create_dir_all(dir)?; }
+        }
+        let tmp = format!("{}.tmp", path);
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(count)
+    }
+
+    /// 从快照恢复条目，按停机期间流逝的墙钟时间扣减寿命，已过期的直接丢弃
+    pub fn load(&self, path: &str) -> Result<usize> {
+        let data = std::fs::read(path)?;
+        if !data.starts_with(SNAPSHOT_MAGIC) { anyhow::bail!("not a cache snapshot (bad header)"); }
+        let (now, now_ms) = (Instant::now(), unix_millis());
+
+        let mut pos = SNAPSHOT_MAGIC.len();
+        let mut count = 0;
+        let take = |pos: &mut usize, n: usize| -> Result<&[u8]> {
+            let bytes = data.get(*pos..*pos + n).ok_or_else(|| anyhow::anyhow!("truncated cache snapshot"))?;
+            *pos += n;
+            Ok(bytes)
+        };
+        while pos < data.len() {
+            let kind = take(&mut pos, 1)?[0];
+            let stored_ms = u64::from_be_bytes(take(&mut pos, 8)?.try_into()?);
+            let expires_ms = u64::from_be_bytes(take(&mut pos, 8)?.try_into()?);
+            let key_len = u16::from_be_bytes(take(&mut pos, 2)?.try_into()?) as usize;
+            let key = take(&mut pos, key_len)?.to_vec();
+            let resp_len = u16::from_be_bytes(take(&mut pos, 2)?.try_into()?) as usize;
+            let response = take(&mut pos, resp_len)?.to_vec();
+
+            if expires_ms <= now_ms { continue; }
+            let item = CachedItem {
+                response,
+                stored_at: now.checked_sub(Duration::from_millis(now_ms.saturating_sub(stored_ms))).unwrap_or(now),
+                expires_at: now + Duration::from_millis(expires_ms - now_ms),
+                hits: Arc::new(AtomicU32::new(0)),
+            };
+            match kind {
+                0 => self.success.insert(key, item),
+                _ => self.denial.insert(key, item),
+            }
+            count += 1;
         }
+        Ok(count)
     }
 }
 
-pub struct CachePlugin {
+/// 跨热重载保留的缓存池，按 server block 划分命名空间，不同 block 的同一问题互不污染
+#[derive(Default)]
+pub struct CacheStore {
+    namespaces: Mutex<HashMap<String, Arc<CacheNamespace>>>,
+}
+
+impl CacheStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 取得 (或创建) server block 的缓存空间；容量变化时按新容量重建并迁移已有条目
+    pub fn namespace(&self, zone: &str, success_capacity: u64, denial_capacity: u64) -> Arc<CacheNamespace> {
+        let mut namespaces = self.namespaces.lock().unwrap_or_else(|e| e.into_inner());
+        let old = match namespaces.get(zone) {
+            Some(ns) if ns.capacity == (success_capacity, denial_capacity) => return ns.clone(),
+            other => other.cloned(),
+        };
+
+        let ns = Arc::new(CacheNamespace::new(success_capacity, denial_capacity));
+        if let Some(old) = old {
+            tracing::info!("[cache] Resizing namespace {} to {}/{} entries", zone, success_capacity, denial_capacity);
+            ns.restored.store(old.restored.load(Ordering::Relaxed), Ordering::Relaxed);
+            for (key, item) in old.success.iter() { ns.success.insert((*key).clone(), item); }
+            for (key, item) in old.denial.iter() { ns.denial.insert((*key).clone(), item); }
+        }
+        namespaces.insert(zone.to_string(), ns.clone());
+        ns
+    }
+
+    /// 进程退出前把所有配置了 persist 的命名空间落盘
+    pub fn persist_all(&self) {
+        let namespaces: Vec<_> = self.namespaces.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect();
+        for ns in namespaces {
+            let Some(path) = ns.persist_path.lock().unwrap_or_else(|e| e.into_inner()).clone() else { continue };
+            match ns.save(&path) {
+                Ok(count) => tracing::info!("[cache] Saved {} entries to {}", count, path),
+                Err(e) => tracing::error!("[cache] Failed to save cache snapshot {}: {}", path, e),
+            }
+        }
+    }
+
+    /// 丢弃新配置中已不使用缓存的 server block 的缓存空间。
+    /// 按配置判断而不是引用计数：旧插件的后台刷新与快照任务在重载后仍可能短暂持有命名空间
+    pub fn prune(&self, live: &HashSet<String>) {
+        let mut namespaces = self.namespaces.lock().unwrap_or_else(|e| e.into_inner());
+        namespaces.retain(|zone, _| live.contains(zone));
+    }
+}
+
+/// 条目寿命策略：记录最小 TTL 夹在 [min, max] 之间
+#[derive(Clone, Copy)]
+struct TtlPolicy {
     success_ttl: Duration,
+    success_min_ttl: Duration,
     denial_ttl: Duration,
+    denial_min_ttl: Duration,
     servfail_ttl: Duration,
-    store: Arc<CacheStore>,
+}
+
+impl TtlPolicy {
+    /// 按 RCODE 把应答写入 success / denial 缓存，不可缓存的应答直接忽略
+    fn store(&self, store: &CacheNamespace, key: Vec<u8>, resp: &[u8], server_label: &str) {
+        // 被截断 (TC) 的应答不完整，不能缓存
+        if resp.len() < 12 || resp[2] & 0x02 != 0 { return; }
+        let rcode = resp[3] & 0x0F;
+        let has_answers = u16::from_be_bytes([resp[6], resp[7]]) > 0;
+        let now = Instant::now();
+        let item = |ttl: Duration| CachedItem { response: resp.to_vec(), stored_at: now, expires_at: now + ttl, hits: Arc::new(AtomicU32::new(0)) };
+
+        match rcode {
+            0 if has_answers => {
+                let ttl = clamp_ttl(wire::min_ttl(resp).unwrap_or(0), self.success_min_ttl, self.success_ttl);
+                store.success.insert(key, item(ttl));
+                CACHE_ENTRIES.with_label_values(&[server_label, "success", "", "."]).set(store.success.entry_count() as f64);
+            }
+            // NXDOMAIN 与 NODATA 按 RFC 2308 取 SOA MINIMUM，没有 SOA 的否定应答不缓存
+            0 | 3 => {
+                let Some(neg_ttl) = wire::negative_ttl(resp) else { return };
+                let ttl = clamp_ttl(neg_ttl, self.denial_min_ttl, self.denial_ttl);
+                store.denial.insert(key, item(ttl));
+                CACHE_ENTRIES.with_label_values(&[server_label, "denial", "", "."]).set(store.denial.entry_count() as f64);
+            }
+            2 if self.servfail_ttl.as_secs() > 0 => {
+                store.denial.insert(key, item(self.servfail_ttl));
+                CACHE_ENTRIES.with_label_values(&[server_label, "denial", "", "."]).set(store.denial.entry_count() as f64);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `prefetch AMOUNT [DURATION] [PERCENTAGE%]`
+struct Prefetch {
+    amount: u32,
+    window: Duration,
+    percent: u32,
+}
+
+pub struct CachePlugin {
+    ttls: TtlPolicy,
+    /// 过期后仍可应答的窗口 (RFC 8767)
+    serve_stale: Option<Duration>,
+    prefetch: Option<Prefetch>,
+    store: Arc<CacheNamespace>,
+    shared: Arc<SharedState>,
+    // 正在后台刷新的键，避免同一条目被重复刷新
+    refreshing: Arc<Mutex<HashSet<Vec<u8>>>>,
+    // 周期性快照任务，插件销毁 (热重载) 时取消
+    persist_task: Option<tokio::task::JoinHandle<()>>,
 }
 
 #[async_trait::async_trait]
@@ -40,58 +235,133 @@ impl Plugin for CachePlugin {
     fn name(&self) -> &str { "cache" }
 
     fn from_config(config: &PluginConfig, shared: Arc<SharedState>) -> Result<Self> {
-        let mut success_ttl = Duration::from_secs(3600);
-        let mut denial_ttl = Duration::from_secs(1800);
-        let mut servfail_ttl = Duration::from_secs(5);
+        let mut ttls = TtlPolicy {
+            success_ttl: Duration::from_secs(3600),
+            success_min_ttl: Duration::from_secs(5),
+            denial_ttl: Duration::from_secs(1800),
+            denial_min_ttl: Duration::from_secs(5),
+            servfail_ttl: Duration::from_secs(5),
+        };
+        let mut serve_stale = None;
+        let mut prefetch = None;
+        let mut success_capacity = 50_000;
+        let mut denial_capacity = 50_000;
+        let mut persist = None;
 
+        // success|denial CAPACITY [TTL] [MINTTL]
         for sub in &config.block {
             match sub.name.as_str() {
-                "success" => { if sub.args.len() > 1 { success_ttl = Duration::from_secs(sub.args[1].parse().unwrap_or(3600)); } }
-                "denial" => { if sub.args.len() > 1 { denial_ttl = Duration::from_secs(sub.args[1].parse().unwrap_or(1800)); } }
-                "servfail" => {
-                    if sub.args.len() > 0 {
-                        let secs = sub.args[0].strip_suffix('s').unwrap_or(&sub.args[0]).parse().unwrap_or(5);
-                        servfail_ttl = Duration::from_secs(secs);
-                    }
+                "success" => {
+                    if let Some(cap) = sub.args.first() { success_capacity = cap.parse().unwrap_or(50_000); }
+                    if let Some(ttl) = sub.args.get(1) { ttls.success_ttl = Duration::from_secs(ttl.parse().unwrap_or(3600)); }
+                    if let Some(min) = sub.args.get(2) { ttls.success_min_ttl = Duration::from_secs(min.parse().unwrap_or(5)); }
+                }
+                "denial" => {
+                    if let Some(cap) = sub.args.first() { denial_capacity = cap.parse().unwrap_or(50_000); }
+                    if let Some(ttl) = sub.args.get(1) { ttls.denial_ttl = Duration::from_secs(ttl.parse().unwrap_or(1800)); }
+                    if let Some(min) = sub.args.get(2) { ttls.denial_min_ttl = Duration::from_secs(min.parse().unwrap_or(5)); }
+                }
+                "servfail" if !sub.args.is_empty() => {
+                    let secs = sub.args[0].strip_suffix('s').unwrap_or(&sub.args[0]).parse().unwrap_or(5);
+                    ttls.servfail_ttl = Duration::from_secs(secs);
+                }
+                "serve_stale" => {
+                    serve_stale = Some(sub.args.first().and_then(|d| parse_duration(d).ok()).unwrap_or(Duration::from_secs(3600)));
+                }
+                // persist PATH [INTERVAL]
+                "persist" if !sub.args.is_empty() => {
+                    let interval = sub.args.get(1).and_then(|d| parse_duration(d).ok()).unwrap_or(Duration::from_secs(300));
+                    persist = Some((sub.args[0].clone(), interval));
+                }
+                "prefetch" => {
+                    prefetch = Some(Prefetch {
+                        amount: sub.args.first().and_then(|a| a.parse().ok()).unwrap_or(1),
+                        window: sub.args.get(1).and_then(|d| parse_duration(d).ok()).unwrap_or(Duration::from_secs(60)),
+                        percent: sub.args.get(2).and_then(|p| p.trim_end_matches('%').parse().ok()).unwrap_or(10),
+                    });
                 }
                 _ => {}
             }
         }
 
-        tracing::info!("[cache] Initialized (Success TTL: {}s, Denial TTL: {}s). Bound to Global LRU Pool.", success_ttl.as_secs(), denial_ttl.as_secs());
+        tracing::info!(
+            "[cache] Initialized for {} (Capacity: {}/{}, Success TTL: {}-{}s, Denial TTL: {}-{}s, Serve stale: {:?}, Prefetch: {}).",
+            config.zone, success_capacity, denial_capacity,
+            ttls.success_min_ttl.as_secs(), ttls.success_ttl.as_secs(), ttls.denial_min_ttl.as_secs(), ttls.denial_ttl.as_secs(),
+            serve_stale, prefetch.is_some()
+        );
+
+        // 两个 server block 写同一个快照文件会互相覆盖
+        if let Some((path, _)) = &persist {
+            let key = std::path::absolute(path).map(|p| p.display().to_string()).unwrap_or_else(|_| path.clone());
+            let mut snapshots = shared.cache_snapshots.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(other) = snapshots.insert(key, config.zone.clone()) {
+                anyhow::bail!("cache persist path {} is already used by {}", path, other);
+            }
+        }
+
+        // 继承全局缓存池中本 server block 的命名空间，无惧热重载！
+        let store = shared.cache_preserve.namespace(&config.zone, success_capacity, denial_capacity);
+        *store.persist_path.lock().unwrap_or_else(|e| e.into_inner()) = persist.as_ref().map(|(path, _)| path.clone());
+
+        let persist_task = persist.map(|(path, interval)| {
+            if !store.restored.swap(true, Ordering::Relaxed) {
+                match store.load(&path) {
+                    Ok(count) => tracing::info!("[cache] Restored {} entries for {} from {}", count, config.zone, path),
+                    Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) => {}
+                    Err(e) => tracing::warn!("[cache] Failed to restore cache snapshot {}: {}", path, e),
+                }
+            }
+            let store = store.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    let (store, path) = (store.clone(), path.clone());
+                    match tokio::task::spawn_blocking(move || store.save(&path)).await {
+                        Ok(Ok(count)) => tracing::debug!("[cache] Snapshot written ({} entries)", count),
+                        Ok(Err(e)) => tracing::error!("[cache] Failed to write cache snapshot: {}", e),
+                        Err(_) => {}
+                    }
+                }
+            })
+        });
 
         Ok(Self {
-            success_ttl, denial_ttl, servfail_ttl,
-            store: shared.cache_preserve.clone(), // 继承全局缓存，无惧热重载！
+            ttls, serve_stale, prefetch, store, shared,
+            refreshing: Arc::new(Mutex::new(HashSet::new())),
+            persist_task,
         })
     }
 
     async fn process(&self, msg: &mut DnsMessage) -> Result<DnsMessage> {
-        if msg.halt_chain || msg.raw_query.len() < 12 { return Ok(msg.clone()); }
+        if msg.halt_chain || msg.questions.is_empty() { return Ok(msg.clone()); }
 
         let server_label = format!("dns://:{}", msg.server_port.unwrap_or(53));
         CACHE_REQUESTS_TOTAL.with_label_values(&[&server_label, "", "."]).inc();
 
-        if let Some(key) = extract_question_bytes(&msg.raw_query) {
+        if let Some(key) = question_key(msg) {
             let now = Instant::now();
             
             // 无锁高并发读取
-            if let Some(item) = self.store.success.get(&key) {
+            for (cache, cache_type) in [(&self.store.success, "success"), (&self.store.denial, "denial")] {
+                let Some(item) = cache.get(&key) else { continue };
                 if item.expires_at > now {
-                    tracing::info!("     |-- [cache] HIT Success! TxID: {:#06x}", msg.header.id);
-                    return Ok(build_cached_response(msg, item, &server_label, "success"));
-                } else {
-                    self.store.success.invalidate(&key);
+                    tracing::info!("     |-- [cache] HIT {}! TxID: {:#06x}", cache_type, msg.header.id);
+                    let hits = item.hits.fetch_add(1, Ordering::Relaxed) + 1;
+                    if self.should_prefetch(&item, hits, now) {
+                        CACHE_PREFETCH_TOTAL.with_label_values(&[&server_label, "", "."]).inc();
+                        self.refresh(msg, key, &server_label);
+                    }
+                    return Ok(build_cached_response(msg, item, &server_label, cache_type, false));
                 }
-            }
-
-            if let Some(item) = self.store.denial.get(&key) {
-                if item.expires_at > now {
-                    tracing::info!("     |-- [cache] HIT Denial! TxID: {:#06x}", msg.header.id);
-                    return Ok(build_cached_response(msg, item, &server_label, "denial"));
-                } else {
-                    self.store.denial.invalidate(&key);
+                // RFC 8767: 过期但仍在 serve_stale 窗口内，先用旧数据应答，同时后台刷新
+                if self.serve_stale.is_some_and(|window| item.expires_at + window > now) {
+                    tracing::info!("     |-- [cache] HIT stale {}! TxID: {:#06x}", cache_type, msg.header.id);
+                    CACHE_SERVED_STALE_TOTAL.with_label_values(&[&server_label, "", "."]).inc();
+                    self.refresh(msg, key, &server_label);
+                    return Ok(build_cached_response(msg, item, &server_label, cache_type, true));
                 }
+                cache.invalidate(&key);
             }
         }
         
@@ -100,22 +370,12 @@ impl Plugin for CachePlugin {
     }
 
     async fn post_process(&self, msg: &mut DnsMessage) -> Result<()> {
-        let server_label = format!("dns://:{}", msg.server_port.unwrap_or(53));
+        // 命中缓存的应答不再回写，否则热点条目会被无限续期
+        if msg.answered_by == "cache" { return Ok(()); }
 
-        if let Some(resp) = &msg.raw_response {
-            if let Some(key) = extract_question_bytes(&msg.raw_query) {
-                let rcode = resp[3] & 0x0F;
-                let now = Instant::now();
-                
-                if rcode == 0 { 
-                    self.store.success.insert(key, CachedItem { response: resp.clone(), expires_at: now + self.success_ttl });
-                    CACHE_ENTRIES.with_label_values(&[&server_label, "success", "", "."]).set(self.store.success.entry_count() as f64);
-                } else if rcode == 3 || (rcode == 2 && self.servfail_ttl.as_secs() > 0) { 
-                    let ttl = if rcode == 3 { self.denial_ttl } else { self.servfail_ttl };
-                    self.store.denial.insert(key, CachedItem { response: resp.clone(), expires_at: now + ttl });
-                    CACHE_ENTRIES.with_label_values(&[&server_label, "denial", "", "."]).set(self.store.denial.entry_count() as f64);
-                }
-            }
+        let server_label = format!("dns://:{}", msg.server_port.unwrap_or(53));
+        if let (Some(resp), Some(key)) = (&msg.raw_response, question_key(msg)) {
+            self.ttls.store(&self.store, key, resp, &server_label);
         }
         Ok(())
     }
@@ -123,10 +383,61 @@ impl Plugin for CachePlugin {
     fn priority(&self) -> u8 { 120 }
 }
 
-fn build_cached_response(msg: &mut DnsMessage, item: CachedItem, server_label: &str, cache_type: &str) -> DnsMessage {
+impl Drop for CachePlugin {
+    fn drop(&mut self) {
+        if let Some(task) = &self.persist_task { task.abort(); }
+    }
+}
+
+impl CachePlugin {
+    /// 热点条目 (按窗口折算的命中数达到 AMOUNT) 剩余寿命不足 PERCENTAGE% 时提前刷新
+    fn should_prefetch(&self, item: &CachedItem, hits: u32, now: Instant) -> bool {
+        let Some(prefetch) = &self.prefetch else { return false };
+        let lifetime = item.expires_at.duration_since(item.stored_at).as_secs_f64();
+        let remaining = item.expires_at.saturating_duration_since(now).as_secs_f64();
+        if remaining * 100.0 > lifetime * prefetch.percent as f64 { return false; }
+
+        let age = now.duration_since(item.stored_at).max(prefetch.window);
+        hits as f64 * prefetch.window.as_secs_f64() / age.as_secs_f64() >= prefetch.amount as f64
+    }
+
+    /// 在后台绕过缓存自身，驱动优先级更低的插件 (如 forward) 重新解析并写回缓存
+    fn refresh(&self, msg: &DnsMessage, key: Vec<u8>, server_label: &str) {
+        let (Some(zone_idx), Some(config)) = (msg.zone_index, self.shared.config()) else { return };
+        if !self.refreshing.lock().map(|mut keys| keys.insert(key.clone())).unwrap_or(false) { return; }
+
+        let mut query = msg.clone();
+        query.raw_response = None;
+        query.halt_chain = false;
+        query.answered_by.clear();
+        let (ttls, store, refreshing) = (self.ttls, self.store.clone(), self.refreshing.clone());
+        let (priority, server_label) = (self.priority(), server_label.to_string());
+
+        tokio::spawn(async move {
+            match crate::dns_server::resolve_downstream(&config, zone_idx, priority, query).await {
+                // 刷新失败 (SERVFAIL) 不覆盖旧数据，继续以过期条目兜底
+                Some(resp) if resp.len() >= 12 && resp[3] & 0x0F != 2 => ttls.store(&store, key.clone(), &resp, &server_label),
+                _ => tracing::debug!("[cache] Background refresh produced no usable answer"),
+            }
+            if let Ok(mut keys) = refreshing.lock() { keys.remove(&key); }
+        });
+    }
+}
+
+fn build_cached_response(msg: &mut DnsMessage, item: CachedItem, server_label: &str, cache_type: &str, stale: bool) -> DnsMessage {
+    let now = Instant::now();
     let mut resp = item.response;
-    resp[0] = msg.raw_query[0]; 
-    resp[1] = msg.raw_query[1];
+    // 沿用本次查询的 ID 与问题段原始大小写 (键已小写化)，兼容 0x20 随机化的客户端
+    wire::adopt_query(&mut resp, &msg.raw_query);
+    // TTL 扣除在缓存中停留的时间，且不超过条目剩余寿命；过期应答统一压到 STALE_TTL
+    let (elapsed, remaining) = if stale {
+        (0, STALE_TTL)
+    } else {
+        (now.duration_since(item.stored_at).as_secs() as u32, item.expires_at.saturating_duration_since(now).as_secs() as u32)
+    };
+    if let Err(e) = wire::decrement_ttls(&mut resp, elapsed, remaining) {
+        tracing::debug!("Failed to age cached response TTLs: {}", e);
+    }
     msg.raw_response = Some(resp);
     msg.halt_chain = true;
     msg.answered_by = "cache".to_string();
@@ -134,15 +445,249 @@ fn build_cached_response(msg: &mut DnsMessage, item: CachedItem, server_label: &
     msg.clone()
 }
 
-fn extract_question_bytes(query: &[u8]) -> Option<Vec<u8>> {
-    if query.len() < 12 { return None; }
-    let mut offset = 12;
-    while offset < query.len() {
-        let len = query[offset] as usize;
-        offset += 1;
-        if len == 0 { break; }
-        offset += len;
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn clamp_ttl(ttl: u32, min: Duration, max: Duration) -> Duration {
+    Duration::from_secs(ttl as u64).max(min).min(max)
+}
+
+/// 缓存键：小写化的 QNAME 线格式 + QTYPE + QCLASS + DO + CD，大小写不同的同一问题共享缓存。
+/// DO 决定应答是否带 RRSIG，CD 决定上游是否做过 DNSSEC 校验，与 forward 的 single-flight 键一致
+fn question_key(msg: &DnsMessage) -> Option<Vec<u8>> {
+    let q = msg.question()?;
+    // 区传送是多报文的整区数据，NOTIFY 等非标准查询要交给对应插件处理，都不进缓存
+    if matches!(q.qtype, QType::AXFR | QType::IXFR) || msg.header.flags.opcode != 0 { return None; }
+    let mut key = crate::wire::name_to_wire(&q.name.to_ascii_lowercase()).ok()?;
+    key.extend_from_slice(&q.qtype.to_u16().to_be_bytes());
+    key.extend_from_slice(&q.qclass.to_u16().to_be_bytes());
+    key.push(msg.edns.as_ref().is_some_and(|e| e.dnssec_ok) as u8);
+    key.push(msg.header.flags.cd as u8);
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::types::{DnsQuestion, Edns, QClass, Record, ResourceRecord};
+    use std::sync::atomic::AtomicUsize;
+    use tokio::net::UdpSocket;
+
+    fn query(name: &str, dnssec_ok: bool, cd: bool) -> DnsMessage {
+        let mut msg = DnsMessage::default();
+        msg.header.id = rand::random();
+        msg.header.flags.rd = true;
+        msg.header.flags.cd = cd;
+        msg.questions.push(DnsQuestion { name: name.to_string(), qtype: QType::A, qclass: QClass::IN });
+        msg.edns = Some(Edns { dnssec_ok, ..Edns::new(1232) });
+        msg.raw_query = msg.to_wire().unwrap();
+        msg
+    }
+
+    /// 对 `msg` 的应答：A 记录 `addr` (为 None 时是带 SOA 的 NXDOMAIN)
+    fn response(msg: &DnsMessage, addr: Option<[u8; 4]>, ttl: u32) -> Vec<u8> {
+        let mut resp = msg.reply();
+        let name = resp.questions[0].name.clone();
+        match addr {
+            Some(addr) => resp.answers.push(ResourceRecord { name, rclass: QClass::IN, ttl, data: Record::A { addr: addr.into() } }),
+            None => {
+                resp.header.flags.rcode = 3;
+                let soa = Record::SOA { mname: "ns.test".to_string(), rname: "admin.test".to_string(), serial: 1, refresh: 3600, retry: 600, expire: 86400, minimum: 60 };
+                resp.authority.push(ResourceRecord { name: "test".to_string(), rclass: QClass::IN, ttl, data: soa });
+            }
+        }
+        resp.to_wire().unwrap()
+    }
+
+    fn item(response: Vec<u8>, age: u64, lifetime: u64) -> CachedItem {
+        let now = Instant::now();
+        let stored_at = now.checked_sub(Duration::from_secs(age)).unwrap();
+        CachedItem { response, stored_at, expires_at: stored_at + Duration::from_secs(lifetime), hits: Arc::new(AtomicU32::new(0)) }
     }
-    if offset + 4 <= query.len() { return Some(query[12..offset+4].to_vec()); }
-    None
-}
\ No newline at end of file
+
+    fn answer_addrs(resp: &[u8]) -> Vec<([u8; 4], u32)> {
+        DnsMessage::from_wire(resp).unwrap().answers.iter().filter_map(|rr| match rr.data { Record::A { addr } => Some((addr.octets(), rr.ttl)), _ => None }).collect()
+    }
+
+    fn lifetime(item: &CachedItem) -> u64 {
+        item.expires_at.duration_since(item.stored_at).as_secs()
+    }
+
+    #[test]
+    fn question_key_separates_do_and_cd() {
+        let plain = question_key(&query("www.example.com", false, false)).unwrap();
+        assert_eq!(question_key(&query("WWW.Example.COM", false, false)).unwrap(), plain);
+        assert_ne!(question_key(&query("www.example.com", true, false)).unwrap(), plain);
+        assert_ne!(question_key(&query("www.example.com", false, true)).unwrap(), plain);
+        assert_ne!(question_key(&query("www.example.com", true, false)), question_key(&query("www.example.com", false, true)));
+
+        let mut notify = query("example.com", false, false);
+        notify.header.flags.opcode = 4;
+        assert!(question_key(&notify).is_none());
+    }
+
+    #[test]
+    fn stores_each_kind_of_response() {
+        let ttls = TtlPolicy {
+            success_ttl: Duration::from_secs(600),
+            success_min_ttl: Duration::from_secs(30),
+            denial_ttl: Duration::from_secs(120),
+            denial_min_ttl: Duration::from_secs(5),
+            servfail_ttl: Duration::from_secs(7),
+        };
+        let ns = CacheNamespace::new(100, 100);
+        let stored = |name: &str, resp: Vec<u8>| {
+            let key = question_key(&query(name, false, false)).unwrap();
+            ttls.store(&ns, key.clone(), &resp, "test");
+            (ns.success.get(&key).map(|i| lifetime(&i)), ns.denial.get(&key).map(|i| lifetime(&i)))
+        };
+
+        // 正常应答按最小 TTL 夹在 [min, max] 之间
+        assert_eq!(stored("ok.test", response(&query("ok.test", false, false), Some([192, 0, 2, 1]), 300)), (Some(300), None));
+        assert_eq!(stored("short.test", response(&query("short.test", false, false), Some([192, 0, 2, 1]), 1)), (Some(30), None));
+        assert_eq!(stored("long.test", response(&query("long.test", false, false), Some([192, 0, 2, 1]), 86400)), (Some(600), None));
+        // 否定应答取 min(SOA TTL, MINIMUM)，没有 SOA 的不缓存
+        assert_eq!(stored("nx.test", response(&query("nx.test", false, false), None, 300)), (None, Some(60)));
+        let nodata = query("nodata.test", false, false).reply().to_wire().unwrap();
+        assert_eq!(stored("nodata.test", nodata), (None, None));
+        // SERVFAIL 按 servfail 时长缓存，截断的应答不缓存
+        let mut servfail = query("servfail.test", false, false).reply();
+        servfail.header.flags.rcode = 2;
+        assert_eq!(stored("servfail.test", servfail.to_wire().unwrap()), (None, Some(7)));
+        let mut truncated = response(&query("tc.test", false, false), Some([192, 0, 2, 1]), 300);
+        truncated[2] |= 0x02;
+        assert_eq!(stored("tc.test", truncated), (None, None));
+    }
+
+    #[test]
+    fn namespaces_are_isolated_resized_and_pruned() {
+        let store = CacheStore::new();
+        let a = store.namespace("dns://a.test:53", 10, 10);
+        let b = store.namespace("dns://b.test:53", 10, 10);
+        let key = question_key(&query("www.test", false, false)).unwrap();
+        a.success.insert(key.clone(), item(vec![0; 12], 0, 60));
+        assert!(b.success.get(&key).is_none());
+        assert!(Arc::ptr_eq(&a, &store.namespace("dns://a.test:53", 10, 10)));
+
+        // 容量变化时重建并迁移条目
+        let resized = store.namespace("dns://a.test:53", 20, 10);
+        assert!(!Arc::ptr_eq(&a, &resized));
+        assert_eq!(resized.capacity, (20, 10));
+        assert!(resized.success.get(&key).is_some());
+
+        // 仍被 (如后台刷新任务) 持有的命名空间也按配置丢弃
+        b.success.insert(key.clone(), item(vec![0; 12], 0, 60));
+        store.prune(&HashSet::from(["dns://a.test:53".to_string()]));
+        assert!(store.namespace("dns://b.test:53", 10, 10).success.get(&key).is_none());
+        assert!(store.namespace("dns://a.test:53", 20, 10).success.get(&key).is_some());
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let path = std::env::temp_dir().join(format!("coredns-cache-{}.snap", std::process::id()));
+        let path = path.to_str().unwrap();
+        let ns = CacheNamespace::new(100, 100);
+        let (fresh, nx, expired) = (query("fresh.test", false, false), query("nx.test", false, false), query("old.test", false, false));
+        ns.success.insert(question_key(&fresh).unwrap(), item(response(&fresh, Some([192, 0, 2, 1]), 300), 100, 300));
+        ns.denial.insert(question_key(&nx).unwrap(), item(response(&nx, None, 60), 0, 60));
+        ns.success.insert(question_key(&expired).unwrap(), item(response(&expired, Some([192, 0, 2, 2]), 60), 120, 60));
+        assert_eq!(ns.save(path).unwrap(), 2);
+
+        let restored = CacheNamespace::new(100, 100);
+        assert_eq!(restored.load(path).unwrap(), 2);
+        let entry = restored.success.get(&question_key(&fresh).unwrap()).unwrap();
+        assert_eq!(answer_addrs(&entry.response), vec![([192, 0, 2, 1], 300)]);
+        // 写入时刻与剩余寿命按墙钟还原
+        assert!((299..=300).contains(&lifetime(&entry)));
+        assert!((199..=200).contains(&entry.expires_at.saturating_duration_since(Instant::now()).as_secs()));
+        assert!(restored.denial.get(&question_key(&nx).unwrap()).is_some());
+        assert!(restored.success.get(&question_key(&expired).unwrap()).is_none());
+
+        let data = std::fs::read(path).unwrap();
+        std::fs::write(path, &data[..data.len() - 1]).unwrap();
+        assert!(restored.load(path).unwrap_err().to_string().contains("truncated"));
+        std::fs::write(path, b"garbage").unwrap();
+        assert!(restored.load(path).unwrap_err().to_string().contains("bad header"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn serves_stale_and_prefetches_in_background() {
+        // 上游每次都给出新地址 192.0.2.2，并记录收到的查询数 (forward 关闭了健康检查)
+        static QUERIES: AtomicUsize = AtomicUsize::new(0);
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+                QUERIES.fetch_add(1, Ordering::SeqCst);
+                let resp = response(&DnsMessage::from_wire(&buf[..len]).unwrap(), Some([192, 0, 2, 2]), 60);
+                let _ = socket.send_to(&resp, src).await;
+            }
+        });
+
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(CacheStore::new()), String::new()));
+        let corefile = format!(".:53 {{\n    cache {{\n        serve_stale 1h\n        prefetch 1 10m 50%\n    }}\n    forward . 127.0.0.1:{} {{\n        max_fails 0\n    }}\n}}\n", port);
+        let config = Arc::new(Config::parse(&corefile, shared.clone()).unwrap());
+        shared.set_config(&config);
+        let cache = &config.zones[0].plugins[0];
+        let ns = shared.cache_preserve.namespace(&config.zones[0].key(), 50_000, 50_000);
+
+        let resolve = |name: &str| {
+            let mut msg = query(name, false, false);
+            msg.zone_index = Some(0);
+            async move {
+                cache.process(&mut msg).await.unwrap();
+                assert_eq!(msg.answered_by, "cache");
+                answer_addrs(msg.raw_response.as_ref().unwrap())
+            }
+        };
+        // 等后台刷新把条目换成上游的新应答
+        let refreshed = |name: &str| {
+            let key = question_key(&query(name, false, false)).unwrap();
+            let ns = ns.clone();
+            async move {
+                for _ in 0..50 {
+                    if ns.success.get(&key).is_some_and(|i| answer_addrs(&i.response)[0].0 == [192, 0, 2, 2]) { return true; }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                false
+            }
+        };
+        let seed = |name: &str, age: u64| {
+            let msg = query(name, false, false);
+            ns.success.insert(question_key(&msg).unwrap(), item(response(&msg, Some([192, 0, 2, 1]), 60), age, 60));
+        };
+
+        // 剩余寿命充足的条目直接命中，不预取
+        seed("cold.test", 5);
+        assert_eq!(resolve("cold.test").await[0].0, [192, 0, 2, 1]);
+
+        // 过期条目以 STALE_TTL 应答，同时后台刷新
+        seed("stale.test", 120);
+        assert_eq!(resolve("stale.test").await, vec![([192, 0, 2, 1], STALE_TTL)]);
+        assert!(refreshed("stale.test").await);
+        assert_eq!(resolve("stale.test").await[0].0, [192, 0, 2, 2]);
+
+        // 剩余寿命不足 50% 的热点条目命中后提前刷新
+        seed("hot.test", 55);
+        assert_eq!(resolve("hot.test").await[0].0, [192, 0, 2, 1]);
+        assert!(refreshed("hot.test").await);
+        assert!(!refreshed("cold.test").await);
+        assert_eq!(QUERIES.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_shared_persist_paths() {
+        let shared = || Arc::new(SharedState::new_with_cache(Arc::new(CacheStore::new()), String::new()));
+        let path = std::env::temp_dir().join(format!("coredns-cache-dup-{}.snap", std::process::id()));
+        let block = |zone: &str, path: &str| format!("{} {{\n    cache {{\n        persist {}\n    }}\n}}\n", zone, path);
+        let same = format!("{}{}", block("a.test:53", path.to_str().unwrap()), block("b.test:53", path.to_str().unwrap()));
+        let err = Config::parse(&same, shared()).err().unwrap();
+        assert!(format!("{:#}", err).contains("already used by dns://a.test:53"));
+        let other = format!("{}{}", block("a.test:53", path.to_str().unwrap()), block("b.test:53", &format!("{}.b", path.display())));
+        assert!(Config::parse(&other, shared()).is_ok());
+    }
+}
@@ -0,0 +1,252 @@
+use crate::plugin::{Plugin, SharedState};
+use crate::plugin::forward::ForwardPlugin;
+use crate::plugin::prometheus::CHINADNS_RESPONSES_TOTAL;
+use crate::config::PluginConfig;
+use crate::cidr::IpSet;
+use crate::geosite::DomainMatcher;
+use crate::types::{DnsMessage, QType, Record};
+use anyhow::{bail, Result};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// 国内组应答的判定结果
+enum Verdict {
+    /// 至少一个地址在中国 IP 段内
+    China,
+    /// 有地址但全部在中国 IP 段之外，可能被污染
+    Foreign,
+    /// 没有 A / AAAA 地址 (NXDOMAIN、NODATA、其他类型的查询)
+    NoIp,
+}
+
+pub struct ChinaDnsPlugin {
+    server: String,
+    domestic: ForwardPlugin,
+    trusted: ForwardPlugin,
+    china_ip: IpSet,
+    china_list: DomainMatcher,
+    gfw_list: DomainMatcher,
+    noip_as_china: bool,
+}
+
+#[async_trait::async_trait]
+impl Plugin for ChinaDnsPlugin {
+    fn name(&self) -> &str { "chinadns" }
+
+    fn from_config(config: &PluginConfig, shared: Arc<SharedState>) -> Result<Self> {
+        // chinadns { domestic UPSTREAMS... {...}  trusted UPSTREAMS... {...}  china_ip ...  china_list ...  gfw_list ... }
+        let mut domestic = None;
+        let mut trusted = None;
+        let mut china_ip = Vec::new();
+        let mut china_list = Vec::new();
+        let mut gfw_list = Vec::new();
+        let mut geosite = None;
+        let mut noip_as_china = false;
+
+        for sub in &config.block {
+            match sub.name.as_str() {
+                // 两个上游组的写法与 forward 完全相同，子块中可使用 forward 的全部选项
+                "domestic" | "trusted" => {
+                    let group = PluginConfig { name: "forward".to_string(), args: sub.args.clone(), block: sub.block.clone(), zone: config.zone.clone() };
+                    let forward = ForwardPlugin::from_config(&group, shared.clone())?;
+                    if forward.upstreams.is_empty() { bail!("chinadns {} requires at least one upstream", sub.name); }
+                    if sub.name == "domestic" { domestic = Some(forward); } else { trusted = Some(forward); }
+                }
+                "china_ip" => china_ip.extend(sub.args.iter().cloned()),
+                "china_list" => china_list.extend(sub.args.iter().cloned()),
+                "gfw_list" => gfw_list.extend(sub.args.iter().cloned()),
+                "geosite" => geosite = sub.args.first().cloned(),
+                "noip_as_china" => noip_as_china = true,
+                other => bail!("unknown chinadns option {}", other),
+            }
+        }
+        let (Some(domestic), Some(trusted)) = (domestic, trusted) else { bail!("chinadns requires both 'domestic' and 'trusted' upstream groups"); };
+        if china_ip.is_empty() { bail!("chinadns requires a 'china_ip' list"); }
+
+        // 列表文件变化时由 reload 插件触发热重载
+        for path in china_ip.iter().chain(&china_list).chain(&gfw_list).chain(&geosite).filter(|arg| std::path::Path::new(arg).is_file()) { shared.watch_file(path); }
+        let china_ip = IpSet::from_args(&china_ip)?;
+        let china_list = DomainMatcher::from_args(&china_list, geosite.as_deref())?;
+        let gfw_list = DomainMatcher::from_args(&gfw_list, geosite.as_deref())?;
+        tracing::info!(
+            "[chinadns] Loaded {} China IP ranges, {} china_list and {} gfw_list rules",
+            china_ip.len(), china_list.rule_count(), gfw_list.rule_count()
+        );
+
+        Ok(Self { server: config.zone.clone(), domestic, trusted, china_ip, china_list, gfw_list, noip_as_china })
+    }
+
+    async fn process(&self, msg: &mut DnsMessage) -> Result<DnsMessage> {
+        if msg.halt_chain || msg.raw_query.is_empty() { return Ok(msg.clone()); }
+        let Some(question) = msg.question() else { return Ok(msg.clone()); };
+        let qname = question.name.trim_end_matches('.').to_ascii_lowercase();
+
+        // 名单优先：只问对应的一组
+        if self.china_list.find(&qname).is_some() {
+            let resp = query(&self.domestic, msg.clone()).await;
+            return Ok(self.answer(msg, resp, "domestic", "china_list"));
+        }
+        if self.gfw_list.find(&qname).is_some() {
+            let resp = query(&self.trusted, msg.clone()).await;
+            return Ok(self.answer(msg, resp, "trusted", "gfw_list"));
+        }
+
+        // 两组同时查询；国内组的应答判定可用时不再等待可信组
+        let (domestic, trusted) = (query(&self.domestic, msg.clone()), query(&self.trusted, msg.clone()));
+        tokio::pin!(domestic, trusted);
+        let mut trusted_resp = None;
+        let domestic_resp = tokio::select! {
+            resp = &mut domestic => resp,
+            resp = &mut trusted => { trusted_resp = Some(resp); domestic.await }
+        };
+
+        let verdict = domestic_resp.as_deref().map(|resp| self.judge(resp));
+        let reason = match verdict {
+            Some(Verdict::China) => return Ok(self.answer(msg, domestic_resp, "domestic", "china_ip")),
+            Some(Verdict::NoIp) if self.noip_as_china => return Ok(self.answer(msg, domestic_resp, "domestic", "no_ip")),
+            Some(Verdict::NoIp) => "no_ip",
+            Some(Verdict::Foreign) => "foreign_ip",
+            None => "fallback",
+        };
+        let trusted_resp = match trusted_resp { Some(resp) => resp, None => trusted.await };
+        match trusted_resp {
+            Some(resp) => Ok(self.answer(msg, Some(resp), "trusted", reason)),
+            // 可信组失败时退回国内组的应答
+            None => Ok(self.answer(msg, domestic_resp, "domestic", "fallback")),
+        }
+    }
+
+    fn priority(&self) -> u8 { 101 }
+}
+
+impl ChinaDnsPlugin {
+    /// 判定国内组的应答：只看应答段中的 A / AAAA 记录
+    fn judge(&self, resp: &[u8]) -> Verdict {
+        let Ok(resp) = DnsMessage::from_wire(resp) else { return Verdict::NoIp; };
+        let mut addrs = resp.answers.iter().filter_map(|rr| match rr.data {
+            Record::A { addr } => Some(IpAddr::V4(addr)),
+            Record::AAAA { addr } => Some(IpAddr::V6(addr)),
+            _ => None,
+        }).peekable();
+        if addrs.peek().is_none() { return Verdict::NoIp; }
+        if addrs.any(|ip| self.china_ip.contains(ip)) { Verdict::China } else { Verdict::Foreign }
+    }
+
+    fn answer(&self, msg: &mut DnsMessage, resp: Option<Vec<u8>>, group: &str, reason: &str) -> DnsMessage {
+        let Some(resp) = resp else { return msg.clone(); };
+        let qname = msg.question().map(|q| q.name.clone()).unwrap_or_default();
+        let qtype = msg.question().map(|q| q.qtype).unwrap_or(QType::A);
+        tracing::debug!("TxID: {:#06x} -> [chinadns] '{}' {} answered by {} group ({})", msg.header.id, qname, qtype.as_str(), group, reason);
+        CHINADNS_RESPONSES_TOTAL.with_label_values(&[&self.server, group, reason]).inc();
+        msg.raw_response = Some(resp);
+        msg.answered_by = "chinadns".to_string();
+        msg.halt_chain = true;
+        msg.clone()
+    }
+}
+
+/// 交给一组上游解析，返回应答报文；该组没有给出应答时为 None
+async fn query(group: &ForwardPlugin, mut msg: DnsMessage) -> Option<Vec<u8>> {
+    group.process(&mut msg).await.ok()?;
+    msg.raw_response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{DnsQuestion, QClass, ResourceRecord};
+    use tokio::net::UdpSocket;
+
+    /// 本地 UDP 上游：每个查询都用 `reply` 生成的报文应答
+    async fn udp_upstream(reply: fn(&[u8]) -> Vec<u8>) -> u16 {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+                let _ = socket.send_to(&reply(&buf[..len]), src).await;
+            }
+        });
+        port
+    }
+
+    fn answer(query: &[u8], addr: Option<[u8; 4]>) -> Vec<u8> {
+        let mut resp = DnsMessage::from_wire(query).unwrap().reply();
+        let name = resp.questions[0].name.clone();
+        if let Some(addr) = addr { resp.answers.push(ResourceRecord { name, rclass: QClass::IN, ttl: 60, data: Record::A { addr: addr.into() } }); }
+        resp.to_wire().unwrap()
+    }
+
+    /// 国内组：cn / blocked 给出中国地址，nodata 没有地址，其余给出境外地址
+    fn domestic(query: &[u8]) -> Vec<u8> {
+        let name = DnsMessage::from_wire(query).unwrap().questions[0].name.trim_end_matches('.').to_string();
+        let addr = match name.as_str() {
+            "cn.test" | "blocked.test" => Some([1, 2, 4, 8]),
+            "nodata.test" => None,
+            _ => Some([8, 8, 8, 8]),
+        };
+        answer(query, addr)
+    }
+
+    fn chinadns(domestic: u16, trusted: u16, china_list: &str, extra: &[&str]) -> ChinaDnsPlugin {
+        let option = |name: &str, args: Vec<String>| PluginConfig { name: name.to_string(), args, block: Vec::new(), zone: String::new() };
+        let mut block = vec![
+            option("domestic", vec![format!("127.0.0.1:{}", domestic)]),
+            option("trusted", vec![format!("127.0.0.1:{}", trusted)]),
+            option("china_ip", vec!["1.2.4.0/24".to_string()]),
+            option("china_list", vec!["full:forced.test".to_string(), china_list.to_string()]),
+            option("gfw_list", vec!["domain:blocked.test".to_string()]),
+        ];
+        block.extend(extra.iter().map(|name| option(name, Vec::new())));
+        let config = PluginConfig { name: "chinadns".to_string(), args: Vec::new(), block, zone: "dns://.:53".to_string() };
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        ChinaDnsPlugin::from_config(&config, shared).unwrap()
+    }
+
+    /// 解析一次，返回应答中的 A 地址 (没有应答时为 None，NODATA 为空列表)
+    async fn resolve(plugin: &ChinaDnsPlugin, name: &str) -> Option<Vec<[u8; 4]>> {
+        let mut msg = DnsMessage::default();
+        msg.header.id = rand::random();
+        msg.header.flags.rd = true;
+        msg.questions.push(DnsQuestion { name: name.to_string(), qtype: QType::A, qclass: QClass::IN });
+        msg.raw_query = msg.to_wire().unwrap();
+        plugin.process(&mut msg).await.unwrap();
+        let resp = DnsMessage::from_wire(msg.raw_response.as_ref()?).unwrap();
+        Some(resp.answers.iter().filter_map(|rr| match rr.data { Record::A { addr } => Some(addr.octets()), _ => None }).collect())
+    }
+
+    #[tokio::test]
+    async fn chooses_group_by_answer_and_lists() {
+        let domestic = udp_upstream(domestic).await;
+        let trusted = udp_upstream(|query| answer(query, Some([9, 9, 9, 9]))).await;
+        let list = std::env::temp_dir().join(format!("coredns-chinadns-{}.conf", std::process::id()));
+        std::fs::write(&list, "server=/dnsmasq.test/114.114.114.114\n# comment\nplain.test\n").unwrap();
+        let list = list.to_str().unwrap();
+
+        let plugin = chinadns(domestic, trusted, list, &[]);
+        assert_eq!(resolve(&plugin, "cn.test.").await, Some(vec![[1, 2, 4, 8]]));
+        assert_eq!(resolve(&plugin, "foreign.test.").await, Some(vec![[9, 9, 9, 9]]));
+        assert_eq!(resolve(&plugin, "nodata.test.").await, Some(vec![[9, 9, 9, 9]]));
+        // 名单优先于应答地址的判定，dnsmasq 行与普通行都按域名及其子域名匹配
+        assert_eq!(resolve(&plugin, "Forced.Test.").await, Some(vec![[8, 8, 8, 8]]));
+        assert_eq!(resolve(&plugin, "www.dnsmasq.test.").await, Some(vec![[8, 8, 8, 8]]));
+        assert_eq!(resolve(&plugin, "plain.test.").await, Some(vec![[8, 8, 8, 8]]));
+        assert_eq!(resolve(&plugin, "www.blocked.test.").await, Some(vec![[9, 9, 9, 9]]));
+
+        let plugin = chinadns(domestic, trusted, list, &["noip_as_china"]);
+        assert_eq!(resolve(&plugin, "nodata.test.").await, Some(vec![]));
+        assert_eq!(resolve(&plugin, "foreign.test.").await, Some(vec![[9, 9, 9, 9]]));
+        let _ = std::fs::remove_file(list);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_domestic_when_trusted_fails() {
+        let domestic = udp_upstream(domestic).await;
+        let broken = udp_upstream(|query| query[..3].to_vec()).await;
+        let plugin = chinadns(domestic, broken, "keyword:unused", &[]);
+        assert_eq!(resolve(&plugin, "foreign.test.").await, Some(vec![[8, 8, 8, 8]]));
+        assert_eq!(resolve(&plugin, "nodata.test.").await, Some(vec![]));
+        // 只问可信组的名字没有退路
+        assert_eq!(resolve(&plugin, "blocked.test.").await, None);
+    }
+}
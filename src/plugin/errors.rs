@@ -1,5 +1,5 @@
 use crate::plugin::{Plugin, SharedState};
-use crate::config::PluginConfig;
+use crate::config::{parse_duration, PluginConfig};
 use crate::types::DnsMessage;
 use anyhow::Result;
 use regex::Regex;
@@ -26,13 +26,14 @@ impl Plugin for ErrorsPlugin {
 
     fn from_config(config: &PluginConfig, shared: Arc<SharedState>) -> Result<Self> {
         let mut rules = Vec::new();
+        let match_all = Regex::new(".*")?;
         
         for sub in &config.block {
             if sub.name == "consolidate" {
                 if sub.args.len() < 2 { continue; }
                 let dur = parse_duration(&sub.args[0]).unwrap_or(Duration::from_secs(30));
                 let raw_pattern = sub.args[1].clone();
-                let pattern = Regex::new(&raw_pattern).unwrap_or_else(|_| Regex::new(".*").unwrap());
+                let pattern = Regex::new(&raw_pattern).unwrap_or_else(|_| match_all.clone());
                 
                 let mut level = "error".to_string();
                 let mut show_first = false;
@@ -108,15 +109,6 @@ impl Drop for ErrorsPlugin {
     }
 }
 
-fn parse_duration(s: &str) -> Result<Duration> {
-    let s = s.trim();
-    if let Some(stripped) = s.strip_suffix("ms") { Ok(Duration::from_millis(stripped.parse()?)) }
-    else if let Some(stripped) = s.strip_suffix('s') { Ok(Duration::from_secs(stripped.parse()?)) }
-    else if let Some(stripped) = s.strip_suffix('m') { Ok(Duration::from_secs(stripped.parse::<u64>()? * 60)) }
-    else if let Some(stripped) = s.strip_suffix('h') { Ok(Duration::from_secs(stripped.parse::<u64>()? * 3600)) }
-    else { anyhow::bail!("invalid duration") }
-}
-
 fn log_msg(level: &str, msg: &str) {
     match level.to_lowercase().as_str() {
         "warning" | "warn" => tracing::warn!("{}", msg),
@@ -0,0 +1,125 @@
+use crate::plugin::{file_stamp, Plugin, SharedState};
+use crate::config::{parse_duration, PluginConfig};
+use crate::types::{DnsMessage, QClass, QType};
+use crate::zone::{in_zone, normalize, Zone, ZoneHandle};
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct FilePlugin {
+    // 加载失败的区为空，区内查询应答 SERVFAIL 直到重新加载成功
+    zones: Vec<Arc<ZoneHandle>>,
+    // 周期性检查 SERIAL 变化的任务，插件销毁 (热重载) 时取消
+    reload_task: Option<tokio::task::JoinHandle<()>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for FilePlugin {
+    fn name(&self) -> &str { "file" }
+
+    fn from_config(config: &PluginConfig, shared: Arc<SharedState>) -> Result<Self> {
+        // file DBFILE [ZONES...]
+        let Some(path) = config.args.first().cloned() else { anyhow::bail!("file requires a zone file path"); };
+        let mut origins: Vec<String> = config.args.iter().skip(1).map(|z| normalize(z)).collect();
+        if origins.is_empty() { origins.push(config.origin()); }
+
+        let mut reload = Duration::from_secs(60);
+        for sub in &config.block {
+            if sub.name == "reload" && !sub.args.is_empty() {
+                reload = parse_duration(&sub.args[0]).unwrap_or(Duration::from_secs(60));
+            }
+        }
+
+        let zones: Vec<Arc<ZoneHandle>> = origins.into_iter().map(|origin| {
+            let zone = match Zone::parse_file(&path, &origin) {
+                Ok(zone) => {
+                    tracing::info!("[file] Loaded zone {} from {} ({} records, serial {})", origin, path, zone.len(), zone.serial());
+                    Some(zone)
+                }
+                Err(e) => {
+                    tracing::error!("[file] Failed to load zone {} from {}: {:#}", origin, path, e);
+                    None
+                }
+            };
+            let handle = Arc::new(ZoneHandle::new(&origin, zone));
+            // 登记给 transfer 插件做区传送
+            shared.register_zone(&config.zone, handle.clone());
+            handle
+        }).collect();
+
+        // reload 0 关闭；文件的修改时间变化后重新解析，SERIAL 不同才替换并通知从服务器
+        let reload_task = (!reload.is_zero()).then(|| {
+            let (zones, block) = (zones.clone(), config.zone.clone());
+            tokio::spawn(async move {
+                let mut stamp = file_stamp(&path);
+                loop {
+                    tokio::time::sleep(reload).await;
+                    let current = file_stamp(&path);
+                    let failed = zones.iter().any(|handle| handle.get().is_none());
+                    if current == stamp && !failed { continue; }
+                    stamp = current;
+                    for handle in &zones {
+                        let (file, origin) = (path.clone(), handle.origin.clone());
+                        let parsed = match tokio::task::spawn_blocking(move || Zone::parse_file(&file, &origin)).await {
+                            Ok(Ok(zone)) => zone,
+                            Ok(Err(e)) => { tracing::error!("[file] Failed to reload zone {} from {}: {:#}", handle.origin, path, e); continue; }
+                            Err(_) => continue,
+                        };
+                        if !handle.update(parsed) { continue; }
+                        let Some(zone) = handle.get() else { continue };
+                        tracing::info!("[file] Reloaded zone {} with serial {} ({} records)", zone.origin, zone.serial(), zone.len());
+                        crate::plugin::transfer::notify(&shared, &block, &zone);
+                    }
+                }
+            })
+        });
+
+        Ok(Self { zones, reload_task })
+    }
+
+    async fn process(&self, msg: &mut DnsMessage) -> Result<DnsMessage> {
+        if msg.halt_chain { return Ok(msg.clone()); }
+        let Some(question) = msg.question().cloned() else { return Ok(msg.clone()); };
+        if question.qclass != QClass::IN { return Ok(msg.clone()); }
+
+        // 选最长匹配的区，不在任何区内的名字交给后续插件
+        let qname = question.name.trim_end_matches('.').to_ascii_lowercase();
+        let Some(handle) = self.zones.iter()
+            .filter(|h| in_zone(&qname, &h.origin))
+            .max_by_key(|h| if h.origin == "." { 0 } else { h.origin.len() + 1 })
+        else { return Ok(msg.clone()); };
+
+        let mut resp = msg.reply();
+        resp.header.flags.ra = false;
+        match handle.get() {
+            // 区传送由 transfer 插件处理，这里不应答整区
+            _ if matches!(question.qtype, QType::AXFR | QType::IXFR) => resp.header.flags.rcode = 5,
+            None => resp.header.flags.rcode = 2,
+            Some(zone) => {
+                let result = zone.lookup(&qname, question.qtype);
+                resp.header.flags.aa = result.authoritative;
+                resp.header.flags.rcode = result.rcode;
+                resp.answers = result.answers;
+                resp.authority = result.authority;
+                resp.additional = result.additional;
+            }
+        }
+
+        tracing::debug!(
+            "TxID: {:#06x} -> [file] Zone {} answered '{}' with RCODE {} ({} answers)",
+            msg.header.id, handle.origin, question.name, resp.header.flags.rcode, resp.answers.len()
+        );
+        msg.raw_response = Some(resp.to_wire()?);
+        msg.answered_by = "file".to_string();
+        msg.halt_chain = true;
+        Ok(msg.clone())
+    }
+
+    fn priority(&self) -> u8 { 108 }
+}
+
+impl Drop for FilePlugin {
+    fn drop(&mut self) {
+        if let Some(task) = &self.reload_task { task.abort(); }
+    }
+}
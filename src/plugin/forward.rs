@@ -1,22 +1,60 @@
 use crate::plugin::{Plugin, SharedState};
-use crate::config::PluginConfig;
-use crate::types::DnsMessage;
+use crate::config::{parse_duration, PluginConfig};
+use crate::types::{parse_rcode, DnsMessage, Record};
+use crate::zone::{in_zone, normalize};
+use crate::cidr::IpSet;
+use crate::geosite::DomainMatcher;
 use crate::plugin::prometheus::{
     PROXY_REQUEST_DURATION, PROXY_CONN_CACHE_HITS, PROXY_CONN_CACHE_MISSES, 
-    FORWARD_MAX_CONCURRENT_REJECTS, rcode_to_str
+    FORWARD_MAX_CONCURRENT_REJECTS, FORWARD_COALESCED_TOTAL, FORWARD_POLLUTED_TOTAL, FORWARD_ROUTED_TOTAL, FORWARD_HEDGED_TOTAL, rcode_to_str
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
 use tokio::net::{TcpStream, UdpSocket};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::time::{timeout, sleep, Duration};
-use tokio::sync::{Semaphore, Mutex as AsyncMutex};
+use tokio::sync::{watch, Semaphore, Mutex as AsyncMutex};
 use tokio_rustls::{TlsConnector, client::TlsStream, rustls::{ClientConfig, RootCertStore, ServerName}};
 use rand::seq::SliceRandom;
+use futures::stream::{FuturesUnordered, StreamExt};
+use bytes::Bytes;
+
+const DOH_DEFAULT_PATH: &str = "/dns-query";
+const DOQ_ALPN: &[u8] = b"doq";
+/// hedged 策略用于计算延迟百分位的最近样本数，以及样本不足时的等待时间
+const LATENCY_WINDOW: usize = 256;
+const HEDGE_MIN_SAMPLES: usize = 16;
+const HEDGE_DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Policy {
+    Sequential,
+    Random,
+    RoundRobin,
+    /// 同时发往 N 个上游，采用最先到达的可用应答
+    Race(usize),
+    /// 先发一个上游，等待给定时间仍没有应答时再发下一个
+    Hedged(HedgeDelay),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HedgeDelay {
+    Fixed(Duration),
+    /// 最近成功查询延迟的百分位 (1-99)
+    Percentile(u8),
+}
 
 #[derive(Debug, PartialEq, Clone, Copy)]
-pub enum Policy { Sequential, Random, RoundRobin }
+pub enum Transport { Udp, Tls, Https, Quic }
+
+impl Transport {
+    fn as_str(&self) -> &'static str {
+        match self { Transport::Udp => "udp", Transport::Tls => "tls", Transport::Https => "https", Transport::Quic => "quic" }
+    }
+}
 
 struct IdleConnection {
     stream: TlsStream<TcpStream>,
@@ -24,12 +62,58 @@ struct IdleConnection {
 }
 
 pub struct Upstream {
+    /// IP 地址；https:// 上游也可以是主机名 (由系统解析器解析)
     pub ip: String,
     pub port: u16,
-    pub is_tls: bool,
+    pub transport: Transport,
+    /// DoH 请求路径，默认 /dns-query
+    pub path: String,
     pub is_healthy: Arc<AtomicBool>,
     pub fails: Arc<AtomicUsize>,
     idle_tls_conns: Arc<AsyncMutex<Vec<IdleConnection>>>, 
+    // DoH 复用的 HTTP/2 连接，所有并发查询在同一连接上多路复用
+    h2_conn: AsyncMutex<Option<h2::client::SendRequest<Bytes>>>,
+    // DoQ 复用的 QUIC 连接，每个查询独占一条双向流
+    quic_conn: AsyncMutex<Option<quinn::Connection>>,
+    // DoQ 的本地 UDP 端点，重连时复用，不必每次重新绑定端口
+    quic_endpoint: std::sync::Mutex<Option<quinn::Endpoint>>,
+}
+
+/// 一次上游解析的结果，广播给合并进来的等待者
+#[derive(Clone)]
+struct FlightOutcome {
+    response: Option<Vec<u8>>,
+    halt_chain: bool,
+}
+
+type Flights = std::sync::Mutex<HashMap<Vec<u8>, watch::Receiver<Option<FlightOutcome>>>>;
+
+/// 领头请求结束 (包括被取消) 时把自己从在途表中摘除
+struct FlightGuard<'a> {
+    flights: &'a Flights,
+    key: Vec<u8>,
+}
+
+/// 一个上游给出的可用应答
+struct Answer {
+    response: Vec<u8>,
+    rcode: u8,
+    upstream: String,
+    duration: f64,
+}
+
+/// 应答中命中污染列表的地址
+enum Pollution {
+    /// 已知的投毒地址：换下一个上游重试
+    Poisoned(IpAddr),
+    /// 运营商劫持 NXDOMAIN 用的地址：改写为 NXDOMAIN
+    BogusNxdomain(IpAddr),
+}
+
+impl Drop for FlightGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut flights) = self.flights.lock() { flights.remove(&self.key); }
+    }
 }
 
 pub struct ForwardPlugin {
@@ -39,6 +123,10 @@ pub struct ForwardPlugin {
     pub next_rcodes: Vec<u8>,
     pub policy: Policy,
     pub except_domains: Vec<String>,
+    /// 配置了 match 时只转发命中规则的名字，其余交给后续插件 (如下一组 forward)
+    pub domains: Option<DomainMatcher>,
+    pub poisoned: IpSet,
+    pub bogus_nxdomain: IpSet,
     pub force_tcp: bool,
     pub max_concurrent: Option<Arc<Semaphore>>,
     pub failfast: bool,
@@ -46,7 +134,13 @@ pub struct ForwardPlugin {
     pub expire_duration: Duration,
     rr_counter: AtomicUsize,
     tls_connector: TlsConnector,
+    h2_connector: TlsConnector,
+    quic_config: quinn::ClientConfig,
     error_tx: tokio::sync::mpsc::Sender<String>,
+    // 在途的上游查询 (single-flight)，键为 小写 QNAME + QTYPE + QCLASS + DO 位
+    inflight: Flights,
+    // hedged 策略按百分位计算等待时间所用的最近成功查询延迟
+    latencies: std::sync::Mutex<VecDeque<Duration>>,
 }
 
 #[async_trait::async_trait]
@@ -57,19 +151,32 @@ impl Plugin for ForwardPlugin {
         let mut upstreams = Vec::new();
         for arg in &config.args {
             if arg == "." || arg == "{}" { continue; }
-            let is_tls = arg.starts_with("tls://");
-            let clean_ip = arg.replace("tls://", "");
-            let (ip, port) = if clean_ip.contains(':') {
-                let parts: Vec<&str> = clean_ip.split(':').collect();
-                (parts[0].to_string(), parts[1].parse().unwrap_or(if is_tls { 853 } else { 53 }))
+            let (transport, rest) = if let Some(rest) = arg.strip_prefix("tls://") {
+                (Transport::Tls, rest)
+            } else if let Some(rest) = arg.strip_prefix("https://") {
+                (Transport::Https, rest)
+            } else if let Some(rest) = arg.strip_prefix("quic://") {
+                (Transport::Quic, rest)
             } else {
-                (clean_ip, if is_tls { 853 } else { 53 })
+                (Transport::Udp, arg.strip_prefix("dns://").unwrap_or(arg))
+            };
+            let (host_port, path) = match rest.split_once('/') {
+                Some((host_port, path)) => (host_port, format!("/{}", path)),
+                None => (rest, DOH_DEFAULT_PATH.to_string()),
+            };
+            let default_port = match transport { Transport::Udp => 53, Transport::Tls | Transport::Quic => 853, Transport::Https => 443 };
+            let (ip, port) = match host_port.split_once(':') {
+                Some((ip, port)) => (ip.to_string(), port.parse().unwrap_or(default_port)),
+                None => (host_port.to_string(), default_port),
             };
             upstreams.push(Arc::new(Upstream { 
-                ip, port, is_tls,
+                ip, port, transport, path,
                 is_healthy: Arc::new(AtomicBool::new(true)),
                 fails: Arc::new(AtomicUsize::new(0)),
                 idle_tls_conns: Arc::new(AsyncMutex::new(Vec::new())),
+                h2_conn: AsyncMutex::new(None),
+                quic_conn: AsyncMutex::new(None),
+                quic_endpoint: std::sync::Mutex::new(None),
             }));
         }
 
@@ -78,6 +185,10 @@ impl Plugin for ForwardPlugin {
         let mut next_rcodes = Vec::new();
         let mut policy = Policy::Random; 
         let mut except_domains = Vec::new();
+        let mut match_rules = Vec::new();
+        let mut geosite = None;
+        let mut poisoned = Vec::new();
+        let mut bogus_nxdomain = Vec::new();
         let mut force_tcp = false;
         let mut failfast = false;
         let mut max_fails = 2;
@@ -89,9 +200,13 @@ impl Plugin for ForwardPlugin {
         for sub in &config.block {
             match sub.name.as_str() {
                 "tls_servername" => tls_servername = sub.args.first().cloned(),
-                "failover" => { for arg in &sub.args { failover_rcodes.push(parse_rcode(arg)); } }
-                "next" => { for arg in &sub.args { next_rcodes.push(parse_rcode(arg)); } }
-                "except" => { except_domains = sub.args.clone(); }
+                "failover" => { for arg in &sub.args { failover_rcodes.push(parse_rcode(arg).with_context(|| format!("unknown rcode {}", arg))?); } }
+                "next" => { for arg in &sub.args { next_rcodes.push(parse_rcode(arg).with_context(|| format!("unknown rcode {}", arg))?); } }
+                "except" => { except_domains = sub.args.iter().map(|d| normalize(d)).collect(); }
+                "match" => match_rules.extend(sub.args.iter().cloned()),
+                "geosite" => geosite = sub.args.first().cloned(),
+                "poisoned" => poisoned.extend(sub.args.iter().cloned()),
+                "bogus_nxdomain" => bogus_nxdomain.extend(sub.args.iter().cloned()),
                 "force_tcp" => { force_tcp = true; }
                 "failfast_all_unhealthy_upstreams" => { failfast = true; }
                 "max_fails" => { if let Some(a) = sub.args.first() { max_fails = a.parse().unwrap_or(2); } }
@@ -112,6 +227,10 @@ impl Plugin for ForwardPlugin {
                         policy = match p.as_str() {
                             "sequential" => Policy::Sequential,
                             "round_robin" => Policy::RoundRobin,
+                            // race [N]，默认同时发往 2 个上游
+                            "race" => Policy::Race(sub.args.get(1).and_then(|n| n.parse().ok()).filter(|&n| n > 0).unwrap_or(2)),
+                            // hedged [pNN|DURATION]，默认等待 p95 延迟
+                            "hedged" => Policy::Hedged(parse_hedge_delay(sub.args.get(1).map(String::as_str))),
                             _ => Policy::Random,
                         };
                     }
@@ -120,12 +239,34 @@ impl Plugin for ForwardPlugin {
             }
         }
 
+        let domains = if match_rules.is_empty() { None } else {
+            let matcher = DomainMatcher::from_args(&match_rules, geosite.as_deref())?;
+            tracing::info!("[forward] Routing names matching {} rules to {:?}", matcher.rule_count(), config.args);
+            Some(matcher)
+        };
+
+        // 每个参数是 IP、CIDR 或每行一个条目的列表文件
+        let poisoned = IpSet::from_args(&poisoned)?;
+        let bogus_nxdomain = IpSet::from_args(&bogus_nxdomain)?;
+        if !poisoned.is_empty() || !bogus_nxdomain.is_empty() {
+            tracing::info!("[forward] Filtering answers against {} poisoned and {} bogus-nxdomain ranges", poisoned.len(), bogus_nxdomain.len());
+        }
+
         let mut root_store = RootCertStore::empty();
         root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
             tokio_rustls::rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
         }));
         let client_config = ClientConfig::builder().with_safe_defaults().with_root_certificates(root_store).with_no_client_auth();
+        // DoH 上游必须通过 ALPN 协商 HTTP/2
+        let mut h2_config = client_config.clone();
+        h2_config.alpn_protocols = vec![b"h2".to_vec()];
+        // DoQ 上游使用 ALPN "doq"，开启 0-RTT：会话票据缓存在 rustls 的默认 resumption store 中
+        let mut quic_crypto = client_config.clone();
+        quic_crypto.alpn_protocols = vec![DOQ_ALPN.to_vec()];
+        quic_crypto.enable_early_data = true;
+        let quic_config = quinn::ClientConfig::new(Arc::new(quic_crypto));
         let tls_connector = TlsConnector::from(Arc::new(client_config));
+        let h2_connector = TlsConnector::from(Arc::new(h2_config));
 
         if max_fails > 0 {
             for upstream in &upstreams {
@@ -133,16 +274,23 @@ impl Plugin for ForwardPlugin {
                 let interval = health_check_interval;
                 let fails_limit = max_fails;
                 let tls_conn_clone = tls_connector.clone();
+                let h2_conn_clone = h2_connector.clone();
+                let quic_config_clone = quic_config.clone();
                 let sni = tls_servername.clone();
                 
                 tokio::spawn(async move {
                     loop {
                         sleep(interval).await;
                         let probe_query = build_health_probe();
-                        let is_ok = if up_clone.is_tls {
-                            ping_tls(&up_clone, &probe_query, &tls_conn_clone, sni.as_deref()).await.is_ok()
-                        } else {
-                            ping_udp(&up_clone, &probe_query).await.is_ok()
+                        let is_ok = match up_clone.transport {
+                            Transport::Tls => ping_tls(&up_clone, &probe_query, &tls_conn_clone, sni.as_deref()).await.is_ok(),
+                            Transport::Https => {
+                                timeout(Duration::from_millis(1500), doh_exchange(&up_clone, &probe_query, &h2_conn_clone, sni.as_deref())).await.is_ok_and(|r| r.is_ok())
+                            }
+                            Transport::Quic => {
+                                timeout(Duration::from_millis(1500), doq_exchange(&up_clone, &probe_query, &quic_config_clone, sni.as_deref())).await.is_ok_and(|r| r.is_ok())
+                            }
+                            Transport::Udp => ping_udp(&up_clone, &probe_query).await.is_ok(),
                         };
 
                         if is_ok {
@@ -150,10 +298,8 @@ impl Plugin for ForwardPlugin {
                             up_clone.is_healthy.store(true, Ordering::Relaxed);
                         } else {
                             let current_fails = up_clone.fails.fetch_add(1, Ordering::Relaxed) + 1;
-                            if current_fails >= fails_limit {
-                                if up_clone.is_healthy.swap(false, Ordering::Relaxed) {
-                                    tracing::warn!("Upstream {}:{} marked as UNHEALTHY (Failed {} times)", up_clone.ip, up_clone.port, current_fails);
-                                }
+                            if current_fails >= fails_limit && up_clone.is_healthy.swap(false, Ordering::Relaxed) {
+                                tracing::warn!("Upstream {}:{} marked as UNHEALTHY (Failed {} times)", up_clone.ip, up_clone.port, current_fails);
                             }
                         }
                     }
@@ -163,12 +309,16 @@ impl Plugin for ForwardPlugin {
 
         Ok(Self {
             upstreams, tls_servername, failover_rcodes, next_rcodes, policy,
-            except_domains, force_tcp, max_concurrent, failfast, 
+            except_domains, domains, poisoned, bogus_nxdomain, force_tcp, max_concurrent, failfast, 
             max_idle_conns: if max_idle_conns == 0 { 1000 } else { max_idle_conns }, 
             expire_duration,
             rr_counter: AtomicUsize::new(0),
             tls_connector,
+            h2_connector,
+            quic_config,
             error_tx: shared.error_tx.clone(),
+            inflight: std::sync::Mutex::new(HashMap::new()),
+            latencies: std::sync::Mutex::new(VecDeque::with_capacity(LATENCY_WINDOW)),
         })
     }
 
@@ -176,17 +326,72 @@ impl Plugin for ForwardPlugin {
         if msg.halt_chain || self.upstreams.is_empty() || msg.raw_query.is_empty() { return Ok(msg.clone()); }
 
         // 【新增】：在入口处统一提取并解析域名，方便后续全局日志打印
-        let qname = extract_qname_string(&msg.raw_query).unwrap_or_else(|| ".".to_string());
+        let qname = msg.question().map(|q| q.name.clone()).unwrap_or_else(|| ".".to_string());
 
-        if !self.except_domains.is_empty() {
-            for ex in &self.except_domains {
-                if qname.ends_with(ex) { 
-                    tracing::debug!("Domain '{}' matches except rule {}, skipping forward.", qname, ex);
-                    return Ok(msg.clone()); 
+        // except 与 match 都按标签边界比较小写、无尾点的名字
+        let name = normalize(&qname);
+        if let Some(ex) = self.except_domains.iter().find(|ex| in_zone(&name, ex)) {
+            tracing::debug!("Domain '{}' matches except rule {}, skipping forward.", qname, ex);
+            return Ok(msg.clone());
+        }
+
+        if let Some(domains) = &self.domains {
+            let Some(hit) = domains.find(&name) else { return Ok(msg.clone()); };
+            tracing::debug!("TxID: {:#06x} -> Domain '{}' matches {} from {}, forwarding to this group", msg.header.id, qname, hit.rule, hit.list);
+            FORWARD_ROUTED_TOTAL.with_label_values(&[hit.list]).inc();
+        }
+
+        // 同一问题已有在途的上游查询时直接等它的结果，不再重复占用并发配额与上游
+        let Some(key) = flight_key(msg) else {
+            self.resolve(msg, &qname).await;
+            return Ok(msg.clone());
+        };
+        let (tx, mut rx) = {
+            let mut flights = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+            match flights.get(&key) {
+                Some(rx) => (None, rx.clone()),
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    flights.insert(key.clone(), rx.clone());
+                    (Some(tx), rx)
                 }
             }
-        }
+        };
 
+        let Some(tx) = tx else {
+            let outcome = rx.wait_for(Option::is_some).await.ok().and_then(|o| o.clone());
+            // 领头请求被取消而没有给出结果时，自己走一遍完整流程
+            let Some(outcome) = outcome else {
+                self.resolve(msg, &qname).await;
+                return Ok(msg.clone());
+            };
+            FORWARD_COALESCED_TOTAL.inc();
+            tracing::debug!("TxID: {:#06x} -> Joined in-flight query for '{}'", msg.header.id, qname);
+            if let Some(mut resp) = outcome.response {
+                crate::wire::adopt_query(&mut resp, &msg.raw_query);
+                msg.raw_response = Some(resp);
+                msg.answered_by = "forward".to_string();
+                msg.halt_chain = outcome.halt_chain;
+            }
+            return Ok(msg.clone());
+        };
+
+        let _guard = FlightGuard { flights: &self.inflight, key };
+        // 只共享本次交换得到的应答；msg 上可能留有上一层 forward 的应答 (next 语义)
+        let answered = self.resolve(msg, &qname).await;
+        let _ = tx.send(Some(FlightOutcome {
+            response: if answered { msg.raw_response.clone() } else { None },
+            halt_chain: msg.halt_chain,
+        }));
+        Ok(msg.clone())
+    }
+
+    fn priority(&self) -> u8 { 100 }
+}
+
+impl ForwardPlugin {
+    /// 选择上游并发送查询，处理 failover / next 语义；本次给出了应答 (写入 msg.raw_response) 时返回 true
+    async fn resolve(&self, msg: &mut DnsMessage, qname: &str) -> bool {
         let _permit = if let Some(sema) = &self.max_concurrent {
             match sema.try_acquire() {
                 Ok(p) => Some(p),
@@ -196,7 +401,7 @@ impl Plugin for ForwardPlugin {
                     msg.raw_response = Some(build_error_response(&msg.raw_query, 5)); 
                     msg.halt_chain = true;
                     msg.answered_by = "forward".to_string();
-                    return Ok(msg.clone());
+                    return true;
                 }
             }
         } else { None };
@@ -212,7 +417,7 @@ impl Plugin for ForwardPlugin {
                 msg.raw_response = Some(build_error_response(&msg.raw_query, 2)); 
                 msg.halt_chain = true;
                 msg.answered_by = "forward".to_string();
-                return Ok(msg.clone());
+                return true;
             } else {
                 healthy_upstreams = (0..self.upstreams.len()).collect(); 
             }
@@ -220,7 +425,7 @@ impl Plugin for ForwardPlugin {
 
         match self.policy {
             Policy::Sequential => {}
-            Policy::Random => { healthy_upstreams.shuffle(&mut rand::thread_rng()); }
+            Policy::Random | Policy::Race(_) | Policy::Hedged(_) => { healthy_upstreams.shuffle(&mut rand::thread_rng()); }
             Policy::RoundRobin => {
                 if !healthy_upstreams.is_empty() {
                     let start = self.rr_counter.fetch_add(1, Ordering::Relaxed) % healthy_upstreams.len();
@@ -229,70 +434,183 @@ impl Plugin for ForwardPlugin {
             }
         }
 
-        for &idx in &healthy_upstreams {
-            let upstream = &self.upstreams[idx];
-            let upstream_addr = format!("{}:{}", upstream.ip, upstream.port);
-            
-            tracing::debug!("TxID: {:#06x} -> Trying {}://{} for '{}' (Policy: {:?})", msg.header.id, if upstream.is_tls {"tls"} else {"udp"}, upstream_addr, qname, self.policy);
+        let answer = match self.policy {
+            Policy::Race(parallel) => self.race(&healthy_upstreams, parallel, None, msg, qname).await,
+            Policy::Hedged(delay) => {
+                let answer = self.race(&healthy_upstreams, 1, Some(self.hedge_delay()), msg, qname).await;
+                // 只用首选上游的可用应答估计延迟：换到后备上游、failover 或被污染而重试的都不计入
+                let first = healthy_upstreams.first().map(|&idx| &self.upstreams[idx]);
+                if let (HedgeDelay::Percentile(_), Some(answer), Some(first)) = (delay, &answer, first) {
+                    if answer.upstream == format!("{}:{}", first.ip, first.port) { self.record_latency(Duration::from_secs_f64(answer.duration)); }
+                }
+                answer
+            }
+            _ => {
+                let mut answer = None;
+                for &idx in &healthy_upstreams {
+                    answer = self.attempt(&self.upstreams[idx], msg, qname).await;
+                    if answer.is_some() { break; }
+                }
+                answer
+            }
+        };
+        let Some(answer) = answer else { return false; };
+        let rcode_str = rcode_to_str(answer.rcode);
 
-            let start_req = std::time::Instant::now();
-            let result = if upstream.is_tls || self.force_tcp { 
-                self.send_tls_with_pool(upstream, &msg.raw_query).await 
-            } else { 
-                PROXY_CONN_CACHE_MISSES.with_label_values(&["udp", "forward", &upstream_addr]).inc();
-                self.send_udp(upstream, &msg.raw_query).await 
-            };
+        msg.raw_response = Some(answer.response);
+        msg.answered_by = "forward".to_string(); 
 
-            let duration = start_req.elapsed().as_secs_f64();
-
-            match result {
-                Ok(response_bytes) => {
-                    let rcode = response_bytes[3] & 0x0F;
-                    let rcode_str = rcode_to_str(rcode);
-                    
-                    PROXY_REQUEST_DURATION.with_label_values(&["forward", rcode_str, &upstream_addr]).observe(duration);
-                    
-                    if self.failover_rcodes.contains(&rcode) { 
-                        // 【改进】：打印重试状态，带上域名和耗时
-                        tracing::warn!("Upstream {} returned failover RCODE {} for '{}' in {:.4}s, triggering retry...", upstream_addr, rcode_str, qname, duration);
-                        continue; 
-                    }
+        if self.next_rcodes.contains(&answer.rcode) {
+            // 【改进】：打印转入下一层的日志，带上域名和耗时
+            tracing::info!("Upstream {} returned next RCODE {} for '{}' in {:.4}s, pushing to next tier!", answer.upstream, rcode_str, qname, answer.duration);
+            msg.halt_chain = false; 
+            return true;
+        }
 
-                    msg.raw_response = Some(response_bytes);
-                    msg.answered_by = "forward".to_string(); 
+        // 【核心改进】：最直观的成功解析日志，包含域名、上游节点、耗时以及 RCODE
+        tracing::info!("Success resolution for '{}' from {} in {:.4}s, RCODE: {}", qname, answer.upstream, answer.duration, rcode_str);
+        msg.halt_chain = true;
+        true
+    }
 
-                    if self.next_rcodes.contains(&rcode) {
-                        // 【改进】：打印转入下一层的日志，带上域名和耗时
-                        tracing::info!("Upstream {} returned next RCODE {} for '{}' in {:.4}s, pushing to next tier!", upstream_addr, rcode_str, qname, duration);
-                        msg.halt_chain = false; 
-                        return Ok(msg.clone());
+    /// 并发尝试上游：先同时发出 `parallel` 个，之后每有一个失败、或等待超过 `hedge` 仍没有应答时再发出下一个，
+    /// 采用最先到达的可用应答，其余在途的查询随之取消
+    async fn race(&self, order: &[usize], parallel: usize, hedge: Option<Duration>, msg: &DnsMessage, qname: &str) -> Option<Answer> {
+        let mut remaining = order.iter().map(|&idx| &self.upstreams[idx]);
+        let mut pending: FuturesUnordered<_> = remaining.by_ref().take(parallel).map(|up| self.attempt(up, msg, qname)).collect();
+        // 对冲计时器只创建一次，每发出一个对冲查询后重新计时
+        let delay = hedge.unwrap_or_default();
+        let hedge_timer = sleep(delay);
+        tokio::pin!(hedge_timer);
+        loop {
+            let can_hedge = hedge.is_some() && remaining.len() > 0;
+            tokio::select! {
+                attempt = pending.next() => match attempt {
+                    Some(Some(answer)) => return Some(answer),
+                    Some(None) => { if let Some(up) = remaining.next() { pending.push(self.attempt(up, msg, qname)); } }
+                    None => return None,
+                },
+                () = &mut hedge_timer, if can_hedge => {
+                    if let Some(up) = remaining.next() {
+                        FORWARD_HEDGED_TOTAL.inc();
+                        tracing::debug!("TxID: {:#06x} -> No answer for '{}' after {:?}, hedging to {}:{}", msg.header.id, qname, delay, up.ip, up.port);
+                        pending.push(self.attempt(up, msg, qname));
                     }
+                    hedge_timer.as_mut().reset(tokio::time::Instant::now() + delay);
+                }
+            }
+        }
+    }
 
-                    // 【核心改进】：最直观的成功解析日志，包含域名、上游节点、耗时以及 RCODE
-                    tracing::info!("Success resolution for '{}' from {} in {:.4}s, RCODE: {}", qname, upstream_addr, duration, rcode_str);
-                    msg.halt_chain = true;
-                    return Ok(msg.clone());
+    /// 向一个上游发送查询；失败、命中 failover 应答码或应答被污染时返回 None
+    async fn attempt(&self, upstream: &Upstream, msg: &DnsMessage, qname: &str) -> Option<Answer> {
+        let upstream_addr = format!("{}:{}", upstream.ip, upstream.port);
+        
+        tracing::debug!("TxID: {:#06x} -> Trying {}://{} for '{}' (Policy: {:?})", msg.header.id, upstream.transport.as_str(), upstream_addr, qname, self.policy);
+
+        let start_req = std::time::Instant::now();
+        let result = match upstream.transport {
+            Transport::Https => self.send_https(upstream, &msg.raw_query).await,
+            Transport::Quic => self.send_quic(upstream, &msg.raw_query).await,
+            Transport::Tls => self.send_tls_with_pool(upstream, &msg.raw_query).await,
+            Transport::Udp if self.force_tcp => self.send_tls_with_pool(upstream, &msg.raw_query).await,
+            Transport::Udp => {
+                PROXY_CONN_CACHE_MISSES.with_label_values(&["udp", "forward", &upstream_addr]).inc();
+                self.send_udp(upstream, &msg.raw_query).await 
+            }
+        };
+
+        let elapsed = start_req.elapsed();
+        let duration = elapsed.as_secs_f64();
+        // 不足 12 字节的应答连报文头都不完整，按上游失败处理
+        let result = result.and_then(|resp| {
+            if resp.len() < 12 { anyhow::bail!("short response ({} bytes)", resp.len()); }
+            Ok(resp)
+        });
+
+        match result {
+            Ok(mut response_bytes) => {
+                let rcode = response_bytes[3] & 0x0F;
+                let rcode_str = rcode_to_str(rcode);
+                
+                PROXY_REQUEST_DURATION.with_label_values(&["forward", rcode_str, &upstream_addr]).observe(duration);
+                
+                if self.failover_rcodes.contains(&rcode) { 
+                    // 【改进】：打印重试状态，带上域名和耗时
+                    tracing::warn!("Upstream {} returned failover RCODE {} for '{}' in {:.4}s, triggering retry...", upstream_addr, rcode_str, qname, duration);
+                    return None; 
                 }
-                Err(e) => {
-                    PROXY_REQUEST_DURATION.with_label_values(&["forward", "SERVFAIL", &upstream_addr]).observe(duration);
-                    let err_msg = format!("Failed to connect to {} for '{}': {:?}", upstream_addr, qname, e);
-                    let _ = self.error_tx.send(err_msg).await;
-                    // 【改进】：打印超时或网络失败，带上域名和耗时
-                    tracing::debug!("Upstream {} timeout or failed for '{}' in {:.4}s, trying next...", upstream_addr, qname, duration);
+
+                match self.check_pollution(rcode, &response_bytes) {
+                    Some(Pollution::Poisoned(ip)) => {
+                        FORWARD_POLLUTED_TOTAL.with_label_values(&[&upstream_addr, "poisoned"]).inc();
+                        tracing::warn!("Upstream {} answered '{}' with poisoned address {}, triggering retry...", upstream_addr, qname, ip);
+                        return None;
+                    }
+                    Some(Pollution::BogusNxdomain(ip)) => {
+                        FORWARD_POLLUTED_TOTAL.with_label_values(&[&upstream_addr, "bogus_nxdomain"]).inc();
+                        tracing::info!("Upstream {} answered '{}' with bogus address {}, rewriting to NXDOMAIN", upstream_addr, qname, ip);
+                        response_bytes = build_nxdomain(&response_bytes);
+                    }
+                    None => {}
                 }
+
+                Some(Answer { response: response_bytes, rcode, upstream: upstream_addr, duration })
+            }
+            Err(e) => {
+                PROXY_REQUEST_DURATION.with_label_values(&["forward", "SERVFAIL", &upstream_addr]).observe(duration);
+                let err_msg = format!("Failed to connect to {} for '{}': {:?}", upstream_addr, qname, e);
+                let _ = self.error_tx.send(err_msg).await;
+                // 【改进】：打印超时或网络失败，带上域名和耗时
+                tracing::debug!("Upstream {} timeout or failed for '{}' in {:.4}s, trying next...", upstream_addr, qname, duration);
+                None
             }
         }
-        Ok(msg.clone())
     }
-    fn priority(&self) -> u8 { 100 }
-}
 
-impl ForwardPlugin {
+    /// hedged 策略发出下一个上游前的等待时间：固定值，或最近成功查询延迟的百分位 (样本不足时用默认值)
+    fn hedge_delay(&self) -> Duration {
+        let Policy::Hedged(HedgeDelay::Percentile(percentile)) = self.policy else {
+            return match self.policy { Policy::Hedged(HedgeDelay::Fixed(delay)) => delay, _ => HEDGE_DEFAULT_DELAY };
+        };
+        let mut samples: Vec<Duration> = match self.latencies.lock() {
+            Ok(latencies) if latencies.len() >= HEDGE_MIN_SAMPLES => latencies.iter().copied().collect(),
+            _ => return HEDGE_DEFAULT_DELAY,
+        };
+        samples.sort_unstable();
+        samples[(samples.len() - 1) * percentile as usize / 100]
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        if let Ok(mut latencies) = self.latencies.lock() {
+            if latencies.len() == LATENCY_WINDOW { latencies.pop_front(); }
+            latencies.push_back(latency);
+        }
+    }
+
+    /// 检查 NOERROR 应答中的 A / AAAA 记录是否命中 poisoned / bogus_nxdomain 列表
+    fn check_pollution(&self, rcode: u8, response: &[u8]) -> Option<Pollution> {
+        if rcode != 0 || (self.poisoned.is_empty() && self.bogus_nxdomain.is_empty()) { return None; }
+        let resp = DnsMessage::from_wire(response).ok()?;
+        let addrs = resp.answers.iter().filter_map(|rr| match rr.data {
+            Record::A { addr } => Some(IpAddr::V4(addr)),
+            Record::AAAA { addr } => Some(IpAddr::V6(addr)),
+            _ => None,
+        });
+        // 投毒优先：能换上游拿到真实应答时不必改写
+        let mut bogus = None;
+        for ip in addrs {
+            if self.poisoned.contains(ip) { return Some(Pollution::Poisoned(ip)); }
+            if bogus.is_none() && self.bogus_nxdomain.contains(ip) { bogus = Some(Pollution::BogusNxdomain(ip)); }
+        }
+        bogus
+    }
+
     async fn send_udp(&self, up: &Upstream, query: &[u8]) -> Result<Vec<u8>> {
         let socket = UdpSocket::bind("0.0.0.0:0").await?;
         socket.connect(format!("{}:{}", up.ip, up.port)).await?;
         socket.send(query).await?;
-        let mut buf = vec![0u8; 4096];
+        let mut buf = vec![0u8; 65535];
         let len = timeout(Duration::from_secs(2), socket.recv(&mut buf)).await??;
         buf.truncate(len);
         Ok(buf)
@@ -356,6 +674,159 @@ impl ForwardPlugin {
     }
 }
 
+impl ForwardPlugin {
+    async fn send_https(&self, up: &Upstream, query: &[u8]) -> Result<Vec<u8>> {
+        timeout(Duration::from_secs(2), doh_exchange(up, query, &self.h2_connector, self.tls_servername.as_deref())).await?
+    }
+
+    async fn send_quic(&self, up: &Upstream, query: &[u8]) -> Result<Vec<u8>> {
+        timeout(Duration::from_secs(2), doq_exchange(up, query, &self.quic_config, self.tls_servername.as_deref())).await?
+    }
+}
+
+/// RFC 9250: 在复用的 QUIC 连接上为每个查询新开一条双向流；报文 ID 置 0 发送，应答再恢复原 ID
+async fn doq_exchange(up: &Upstream, query: &[u8], client_config: &quinn::ClientConfig, sni: Option<&str>) -> Result<Vec<u8>> {
+    if query.len() < 12 { anyhow::bail!("query too short"); }
+    let upstream_addr = format!("{}:{}", up.ip, up.port);
+    let mut frame = Vec::with_capacity(query.len() + 2);
+    frame.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    frame.extend_from_slice(query);
+    frame[2..4].fill(0);
+
+    let mut last_err = anyhow::anyhow!("no DoQ attempt made to {}", upstream_addr);
+    for attempt in 0..2 {
+        let conn = {
+            let mut cached = up.quic_conn.lock().await;
+            match cached.as_ref() {
+                Some(conn) if attempt == 0 && conn.close_reason().is_none() => {
+                    PROXY_CONN_CACHE_HITS.with_label_values(&["quic", "forward", &upstream_addr]).inc();
+                    conn.clone()
+                }
+                _ => {
+                    PROXY_CONN_CACHE_MISSES.with_label_values(&["quic", "forward", &upstream_addr]).inc();
+                    let conn = connect_quic(up, client_config, sni).await?;
+                    *cached = Some(conn.clone());
+                    conn
+                }
+            }
+        };
+
+        match doq_request(&conn, &frame).await {
+            Ok(mut resp) => {
+                resp[..2].copy_from_slice(&query[..2]);
+                return Ok(resp);
+            }
+            Err(e) => {
+                if attempt == 0 { tracing::debug!("DoQ connection to {} failed ({}), reconnecting", upstream_addr, e); }
+                last_err = e;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+async fn connect_quic(up: &Upstream, client_config: &quinn::ClientConfig, sni: Option<&str>) -> Result<quinn::Connection> {
+    let addr = tokio::net::lookup_host((up.ip.as_str(), up.port)).await?.next().ok_or_else(|| anyhow::anyhow!("cannot resolve {}", up.ip))?;
+    let connecting = quic_endpoint(up, addr)?.connect_with(client_config.clone(), addr, sni.unwrap_or(&up.ip))?;
+    // 持有会话票据时走 0-RTT，查询随握手一起发出
+    match connecting.into_0rtt() {
+        Ok((conn, _accepted)) => Ok(conn),
+        Err(connecting) => Ok(connecting.await?),
+    }
+}
+
+/// 取上游的本地端点，第一次连接或解析出的地址族变化时才绑定新端点
+fn quic_endpoint(up: &Upstream, addr: SocketAddr) -> Result<quinn::Endpoint> {
+    let mut cached = up.quic_endpoint.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(endpoint) = cached.as_ref() {
+        if endpoint.local_addr().is_ok_and(|local| local.is_ipv6() == addr.is_ipv6()) { return Ok(endpoint.clone()); }
+    }
+    let endpoint = quinn::Endpoint::client(if addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" }.parse()?)?;
+    *cached = Some(endpoint.clone());
+    Ok(endpoint)
+}
+
+async fn doq_request(conn: &quinn::Connection, frame: &[u8]) -> Result<Vec<u8>> {
+    let (mut send, mut recv) = conn.open_bi().await?;
+    send.write_all(frame).await?;
+    // finish 要等对端确认数据，与读应答并行进行，避免多等一个 RTT
+    let (_, resp) = tokio::join!(send.finish(), recv.read_to_end(u16::MAX as usize + 2));
+    let resp = resp?;
+    if resp.len() < 14 || u16::from_be_bytes([resp[0], resp[1]]) as usize != resp.len() - 2 { anyhow::bail!("malformed DoQ response"); }
+    Ok(resp[2..].to_vec())
+}
+
+/// 通过上游的 HTTP/2 连接发送一次 RFC 8484 POST 请求；连接失效时重建一次再试
+async fn doh_exchange(up: &Upstream, query: &[u8], connector: &TlsConnector, sni: Option<&str>) -> Result<Vec<u8>> {
+    let upstream_addr = format!("{}:{}", up.ip, up.port);
+    let mut last_err = anyhow::anyhow!("no DoH attempt made to {}", upstream_addr);
+    for attempt in 0..2 {
+        let sender = {
+            let mut conn = up.h2_conn.lock().await;
+            match conn.as_ref() {
+                Some(sender) if attempt == 0 => {
+                    PROXY_CONN_CACHE_HITS.with_label_values(&["https", "forward", &upstream_addr]).inc();
+                    sender.clone()
+                }
+                _ => {
+                    PROXY_CONN_CACHE_MISSES.with_label_values(&["https", "forward", &upstream_addr]).inc();
+                    let sender = connect_h2(up, connector, sni).await?;
+                    *conn = Some(sender.clone());
+                    sender
+                }
+            }
+        };
+
+        match doh_request(sender, up, query).await {
+            Ok(resp) => return Ok(resp),
+            Err(e) => {
+                if attempt == 0 { tracing::debug!("DoH connection to {} failed ({}), reconnecting", upstream_addr, e); }
+                last_err = e;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+async fn connect_h2(up: &Upstream, connector: &TlsConnector, sni: Option<&str>) -> Result<h2::client::SendRequest<Bytes>> {
+    let domain = ServerName::try_from(sni.unwrap_or(&up.ip)).map_err(|_| anyhow::anyhow!("Invalid SNI"))?;
+    let stream = timeout(Duration::from_secs(2), TcpStream::connect(format!("{}:{}", up.ip, up.port))).await??;
+    let tls_stream = connector.connect(domain, stream).await?;
+    let (sender, connection) = h2::client::handshake(tls_stream).await?;
+    let upstream_addr = format!("{}:{}", up.ip, up.port);
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::debug!("DoH connection to {} closed: {}", upstream_addr, e);
+        }
+    });
+    Ok(sender)
+}
+
+async fn doh_request(sender: h2::client::SendRequest<Bytes>, up: &Upstream, query: &[u8]) -> Result<Vec<u8>> {
+    let request = http::Request::builder()
+        .method(http::Method::POST)
+        .uri(format!("https://{}:{}{}", up.ip, up.port, up.path))
+        .header(http::header::CONTENT_TYPE, "application/dns-message")
+        .header(http::header::ACCEPT, "application/dns-message")
+        .body(())?;
+    let mut sender = sender.ready().await?;
+    let (response, mut stream) = sender.send_request(request, false)?;
+    stream.send_data(Bytes::copy_from_slice(query), true)?;
+
+    let response = response.await?;
+    if response.status() != http::StatusCode::OK { anyhow::bail!("DoH upstream returned HTTP {}", response.status()); }
+    let mut body = response.into_body();
+    let mut resp = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        resp.extend_from_slice(&chunk);
+        if resp.len() > u16::MAX as usize { anyhow::bail!("DoH response too large"); }
+    }
+    if resp.len() < 12 { anyhow::bail!("DoH response too short"); }
+    Ok(resp)
+}
+
 async fn ping_udp(up: &Upstream, query: &[u8]) -> Result<()> {
     let socket = UdpSocket::bind("0.0.0.0:0").await?;
     socket.connect(format!("{}:{}", up.ip, up.port)).await?;
@@ -383,39 +854,333 @@ fn build_health_probe() -> Vec<u8> {
     vec![ 0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01 ]
 }
 
+/// single-flight 键：小写 QNAME + QTYPE + QCLASS + DO + CD，与 cache 的缓存键相同；DO 或 CD 不同的查询应答内容不同
+fn flight_key(msg: &DnsMessage) -> Option<Vec<u8>> {
+    let q = msg.question()?;
+    let mut key = crate::wire::name_to_wire(&q.name.to_ascii_lowercase()).ok()?;
+    key.extend_from_slice(&q.qtype.to_u16().to_be_bytes());
+    key.extend_from_slice(&q.qclass.to_u16().to_be_bytes());
+    key.push(msg.edns.as_ref().is_some_and(|e| e.dnssec_ok) as u8);
+    key.push(msg.header.flags.cd as u8);
+    Some(key)
+}
+
 fn build_error_response(query: &[u8], rcode: u8) -> Vec<u8> {
     let mut resp = query.to_vec();
     if resp.len() >= 4 { resp[2] |= 0x80; resp[3] |= rcode & 0x0F; }
     resp
 }
 
-fn parse_rcode(s: &str) -> u8 {
-    match s.to_uppercase().as_str() {
-        "NOERROR" => 0, "FORMERR" => 1, "SERVFAIL" => 2,
-        "NXDOMAIN" => 3, "NOTIMP" => 4, "REFUSED" => 5, _ => 2, 
-    }
+/// 保留 ID、问题与 EDNS，去掉全部记录并置 RCODE 为 NXDOMAIN
+fn build_nxdomain(response: &[u8]) -> Vec<u8> {
+    let Ok(resp) = DnsMessage::from_wire(response) else { return build_error_response(response, 3) };
+    let mut nx = resp.reply();
+    nx.header.flags = resp.header.flags;
+    nx.header.flags.rcode = 3;
+    nx.edns = resp.edns;
+    nx.to_wire().unwrap_or_else(|_| build_error_response(response, 3))
 }
 
-fn parse_duration(s: &str) -> Result<Duration> {
-    let s = s.trim();
-    if let Some(stripped) = s.strip_suffix("ms") { Ok(Duration::from_millis(stripped.parse()?)) }
-    else if let Some(stripped) = s.strip_suffix('s') { Ok(Duration::from_secs(stripped.parse()?)) }
-    else if let Some(stripped) = s.strip_suffix('m') { Ok(Duration::from_secs(stripped.parse::<u64>()? * 60)) }
-    else { anyhow::bail!("invalid duration") }
+/// "p90" 为延迟百分位，"50ms" 等为固定等待时间，缺省或无法解析时为 p95
+fn parse_hedge_delay(arg: Option<&str>) -> HedgeDelay {
+    match arg {
+        Some(p) if p.starts_with('p') => HedgeDelay::Percentile(p[1..].parse().ok().filter(|p| (1..=99).contains(p)).unwrap_or(95)),
+        Some(d) => parse_duration(d).map(HedgeDelay::Fixed).unwrap_or(HedgeDelay::Percentile(95)),
+        None => HedgeDelay::Percentile(95),
+    }
 }
 
-fn extract_qname_string(query: &[u8]) -> Option<String> {
-    if query.len() < 12 { return None; }
-    let mut offset = 12;
-    let mut parts = Vec::new();
-    while offset < query.len() {
-        let len = query[offset] as usize;
-        offset += 1;
-        if len == 0 { break; }
-        if offset + len <= query.len() {
-            if let Ok(s) = std::str::from_utf8(&query[offset..offset+len]) { parts.push(s.to_string()); }
-            offset += len;
-        } else { break; }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::dns_server::DnsServer;
+    use crate::types::{DnsQuestion, QClass, QType, ResourceRecord};
+
+    fn query(name: &str) -> DnsMessage {
+        let mut msg = DnsMessage::default();
+        msg.header.id = rand::random();
+        msg.header.flags.rd = true;
+        msg.questions.push(DnsQuestion { name: name.to_string(), qtype: QType::A, qclass: QClass::IN });
+        msg.raw_query = msg.to_wire().unwrap();
+        msg
+    }
+
+    /// 经 forward 解析一次，返回 whoami 应答中的客户端地址与端口
+    async fn whoami(forward: &ForwardPlugin) -> Option<(IpAddr, u16)> {
+        let mut msg = query("doq.test");
+        forward.process(&mut msg).await.ok()?;
+        let resp = DnsMessage::from_wire(msg.raw_response.as_ref()?).ok()?;
+        let ip = resp.answers.iter().find_map(|rr| match rr.data { Record::A { addr } => Some(IpAddr::V4(addr)), _ => None })?;
+        let port = resp.additional.iter().find_map(|rr| match rr.data { Record::SRV { port, .. } => Some(port), _ => None })?;
+        Some((ip, port))
     }
-    if parts.is_empty() { Some(".".to_string()) } else { Some(parts.join(".")) }
-}
\ No newline at end of file
+
+    /// 本地 UDP 上游：每个查询都用 `reply` 生成的报文应答
+    async fn udp_upstream(reply: fn(&[u8]) -> Vec<u8>) -> u16 {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+                let _ = socket.send_to(&reply(&buf[..len]), src).await;
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn short_reply_fails_over_to_next_upstream() {
+        let short = udp_upstream(|query| query[..3].to_vec()).await;
+        let good = udp_upstream(|query| {
+            let mut resp = DnsMessage::from_wire(query).unwrap().reply();
+            resp.answers.push(ResourceRecord { name: "short.test".to_string(), rclass: QClass::IN, ttl: 60, data: Record::A { addr: [192, 0, 2, 1].into() } });
+            resp.to_wire().unwrap()
+        }).await;
+
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        let config = PluginConfig {
+            name: "forward".to_string(),
+            args: vec![".".to_string(), format!("127.0.0.1:{}", short), format!("127.0.0.1:{}", good)],
+            block: vec![PluginConfig { name: "policy".to_string(), args: vec!["sequential".to_string()], block: Vec::new(), zone: String::new() }],
+            zone: "dns://.:53".to_string(),
+        };
+        let forward = ForwardPlugin::from_config(&config, shared).unwrap();
+        let mut msg = query("short.test");
+        forward.process(&mut msg).await.unwrap();
+        let resp = DnsMessage::from_wire(msg.raw_response.as_ref().unwrap()).unwrap();
+        assert_eq!(resp.answers.iter().map(|rr| &rr.data).collect::<Vec<_>>(), vec![&Record::A { addr: [192, 0, 2, 1].into() }]);
+    }
+
+    #[tokio::test]
+    async fn hedge_latency_counts_only_usable_answers() {
+        let good = udp_upstream(|query| {
+            let mut resp = DnsMessage::from_wire(query).unwrap().reply();
+            resp.answers.push(ResourceRecord { name: "hedge.test".to_string(), rclass: QClass::IN, ttl: 60, data: Record::A { addr: [192, 0, 2, 1].into() } });
+            resp.to_wire().unwrap()
+        }).await;
+        let poisoned = udp_upstream(|query| {
+            let mut resp = DnsMessage::from_wire(query).unwrap().reply();
+            resp.answers.push(ResourceRecord { name: "hedge.test".to_string(), rclass: QClass::IN, ttl: 60, data: Record::A { addr: [198, 51, 100, 1].into() } });
+            resp.to_wire().unwrap()
+        }).await;
+        let servfail = udp_upstream(|query| {
+            let mut resp = DnsMessage::from_wire(query).unwrap().reply();
+            resp.header.flags.rcode = 2;
+            resp.to_wire().unwrap()
+        }).await;
+
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        let option = |name: &str, args: &[&str]| PluginConfig { name: name.to_string(), args: args.iter().map(|a| a.to_string()).collect(), block: Vec::new(), zone: String::new() };
+        let samples = |port: u16| {
+            let config = PluginConfig {
+                name: "forward".to_string(),
+                args: vec![".".to_string(), format!("127.0.0.1:{}", port)],
+                block: vec![option("policy", &["hedged", "p90"]), option("failover", &["SERVFAIL"]), option("poisoned", &["198.51.100.0/24"])],
+                zone: "dns://.:53".to_string(),
+            };
+            let forward = ForwardPlugin::from_config(&config, shared.clone()).unwrap();
+            async move {
+                let mut msg = query("hedge.test");
+                forward.process(&mut msg).await.unwrap();
+                forward.latencies.lock().unwrap().len()
+            }
+        };
+        assert_eq!(samples(good).await, 1);
+        assert_eq!(samples(poisoned).await, 0);
+        assert_eq!(samples(servfail).await, 0);
+    }
+
+    #[tokio::test]
+    async fn hedges_a_slow_upstream_after_the_delay() {
+        let slow = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let slow_port = slow.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            while let Ok((len, src)) = slow.recv_from(&mut buf).await {
+                let mut resp = DnsMessage::from_wire(&buf[..len]).unwrap().reply();
+                resp.answers.push(ResourceRecord { name: "slow.test".to_string(), rclass: QClass::IN, ttl: 60, data: Record::A { addr: [192, 0, 2, 9].into() } });
+                sleep(Duration::from_secs(1)).await;
+                let _ = slow.send_to(&resp.to_wire().unwrap(), src).await;
+            }
+        });
+        let fast = udp_upstream(|query| {
+            let mut resp = DnsMessage::from_wire(query).unwrap().reply();
+            resp.answers.push(ResourceRecord { name: "slow.test".to_string(), rclass: QClass::IN, ttl: 60, data: Record::A { addr: [192, 0, 2, 1].into() } });
+            resp.to_wire().unwrap()
+        }).await;
+
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        let option = |name: &str, args: &[&str]| PluginConfig { name: name.to_string(), args: args.iter().map(|a| a.to_string()).collect(), block: Vec::new(), zone: String::new() };
+        let config = PluginConfig {
+            name: "forward".to_string(),
+            args: vec![".".to_string(), format!("127.0.0.1:{}", slow_port), format!("127.0.0.1:{}", fast)],
+            block: vec![option("policy", &["hedged", "150ms"]), option("max_fails", &["0"])],
+            zone: "dns://.:53".to_string(),
+        };
+        let forward = ForwardPlugin::from_config(&config, shared).unwrap();
+        assert_eq!(forward.hedge_delay(), Duration::from_millis(150));
+
+        // 固定先问慢的上游：等满对冲延迟后才发给下一个，并采用它的应答
+        let msg = query("slow.test");
+        let start = std::time::Instant::now();
+        let answer = forward.race(&[0, 1], 1, Some(forward.hedge_delay()), &msg, "slow.test").await.unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(answer.upstream, format!("127.0.0.1:{}", fast));
+        assert!(elapsed >= Duration::from_millis(150) && elapsed < Duration::from_millis(900), "answered after {:?}", elapsed);
+
+        // 先问快的上游时不需要对冲
+        let start = std::time::Instant::now();
+        let answer = forward.race(&[1, 0], 1, Some(forward.hedge_delay()), &msg, "slow.test").await.unwrap();
+        assert_eq!(answer.upstream, format!("127.0.0.1:{}", fast));
+        assert!(start.elapsed() < Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn coalesces_identical_misses_into_one_upstream_query() {
+        // 上游延迟应答，让并发的同一问题都赶上第一个在途查询
+        static QUERIES: AtomicUsize = AtomicUsize::new(0);
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+                QUERIES.fetch_add(1, Ordering::SeqCst);
+                let mut resp = DnsMessage::from_wire(&buf[..len]).unwrap().reply();
+                resp.answers.push(ResourceRecord { name: "flight.test".to_string(), rclass: QClass::IN, ttl: 60, data: Record::A { addr: [192, 0, 2, 1].into() } });
+                let socket = socket.clone();
+                tokio::spawn(async move {
+                    sleep(Duration::from_millis(200)).await;
+                    let _ = socket.send_to(&resp.to_wire().unwrap(), src).await;
+                });
+            }
+        });
+
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        let config = PluginConfig {
+            name: "forward".to_string(),
+            args: vec![".".to_string(), format!("127.0.0.1:{}", port)],
+            block: vec![PluginConfig { name: "max_fails".to_string(), args: vec!["0".to_string()], block: Vec::new(), zone: String::new() }],
+            zone: "dns://.:53".to_string(),
+        };
+        let forward = ForwardPlugin::from_config(&config, shared).unwrap();
+
+        // 每个客户端有自己的 TxID 与 0x20 大小写
+        let names = ["flight.test", "FLIGHT.test", "fLiGhT.TeSt", "Flight.Test", "flighT.tesT"];
+        let answers = futures::future::join_all(names.iter().enumerate().map(|(i, name)| {
+            let forward = &forward;
+            async move {
+                let mut msg = query(name);
+                msg.header.id = 0x1000 + i as u16;
+                msg.raw_query = msg.to_wire().unwrap();
+                forward.process(&mut msg).await.unwrap();
+                assert!(msg.halt_chain);
+                DnsMessage::from_wire(msg.raw_response.as_ref().unwrap()).unwrap()
+            }
+        })).await;
+
+        assert_eq!(QUERIES.load(Ordering::SeqCst), 1);
+        for (i, (name, resp)) in names.iter().zip(&answers).enumerate() {
+            assert_eq!(resp.header.id, 0x1000 + i as u16);
+            assert_eq!(resp.questions[0].name.trim_end_matches('.'), *name);
+            assert_eq!(resp.answers.len(), 1);
+        }
+        assert!(forward.inflight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn routes_groups_by_match_and_except() {
+        fn answer(query: &[u8], addr: [u8; 4]) -> Vec<u8> {
+            let mut resp = DnsMessage::from_wire(query).unwrap().reply();
+            let name = resp.questions[0].name.clone();
+            resp.answers.push(ResourceRecord { name, rclass: QClass::IN, ttl: 60, data: Record::A { addr: addr.into() } });
+            resp.to_wire().unwrap()
+        }
+        let first = udp_upstream(|query| answer(query, [192, 0, 2, 1])).await;
+        let second = udp_upstream(|query| answer(query, [192, 0, 2, 2])).await;
+
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        let option = |name: &str, args: &[&str]| PluginConfig { name: name.to_string(), args: args.iter().map(|a| a.to_string()).collect(), block: Vec::new(), zone: String::new() };
+        let group = |port: u16, block: Vec<PluginConfig>| {
+            let config = PluginConfig { name: "forward".to_string(), args: vec![".".to_string(), format!("127.0.0.1:{}", port)], block, zone: "dns://.:53".to_string() };
+            ForwardPlugin::from_config(&config, shared.clone()).unwrap()
+        };
+        let chain = [
+            group(first, vec![option("match", &["domain:corp.test", "keyword:intranet"]), option("except", &["Public.Corp.Test."])]),
+            group(second, vec![option("match", &["domain:test"])]),
+        ];
+        let resolve = |name: &'static str| {
+            let chain = &chain;
+            async move {
+                let mut msg = query(name);
+                for forward in chain {
+                    forward.process(&mut msg).await.unwrap();
+                    if msg.halt_chain { break; }
+                }
+                let resp = DnsMessage::from_wire(msg.raw_response.as_ref()?).ok()?;
+                resp.answers.iter().find_map(|rr| match rr.data { Record::A { addr } => Some(addr.octets()), _ => None })
+            }
+        };
+        // 两组都能命中时由第一组应答；except 按标签边界、忽略大小写排除
+        assert_eq!(resolve("www.corp.test").await, Some([192, 0, 2, 1]));
+        assert_eq!(resolve("intranet.test").await, Some([192, 0, 2, 1]));
+        assert_eq!(resolve("www.PUBLIC.corp.test.").await, Some([192, 0, 2, 2]));
+        assert_eq!(resolve("notpublic.corp.test").await, Some([192, 0, 2, 1]));
+        assert_eq!(resolve("www.example.test").await, Some([192, 0, 2, 2]));
+        assert_eq!(resolve("www.example.org").await, None);
+    }
+
+    #[tokio::test]
+    async fn resolves_through_local_doq_server() {
+        let dir = std::env::temp_dir().join(format!("coredns-doq-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        let corefile = format!("quic://.:{} {{\n    tls {} {}\n    whoami\n}}\n", port, cert_path.display(), key_path.display());
+        let server = DnsServer::new(Config::parse(&corefile, shared.clone()).unwrap(), shared.clone()).unwrap();
+        let (_reload_tx, reload_rx) = watch::channel(false);
+        tokio::spawn(async move { server.run("127.0.0.1:53".to_string(), reload_rx).await });
+
+        let config = PluginConfig {
+            name: "forward".to_string(),
+            args: vec![".".to_string(), format!("quic://127.0.0.1:{}", port)],
+            block: vec![
+                PluginConfig { name: "tls_servername".to_string(), args: vec!["localhost".to_string()], block: Vec::new(), zone: String::new() },
+                PluginConfig { name: "max_fails".to_string(), args: vec!["0".to_string()], block: Vec::new(), zone: String::new() },
+            ],
+            zone: "dns://.:53".to_string(),
+        };
+        let mut forward = ForwardPlugin::from_config(&config, shared).unwrap();
+        // 只信任测试用的自签名证书
+        let mut roots = RootCertStore::empty();
+        roots.add(&tokio_rustls::rustls::Certificate(cert.serialize_der().unwrap())).unwrap();
+        let mut crypto = ClientConfig::builder().with_safe_defaults().with_root_certificates(roots).with_no_client_auth();
+        crypto.alpn_protocols = vec![DOQ_ALPN.to_vec()];
+        forward.quic_config = quinn::ClientConfig::new(Arc::new(crypto));
+
+        // 等监听器绑定完成
+        let mut first = None;
+        for _ in 0..50 {
+            first = whoami(&forward).await;
+            if first.is_some() { break; }
+            sleep(Duration::from_millis(100)).await;
+        }
+        let (ip, port) = first.expect("no answer from the DoQ server");
+        assert_eq!(ip, IpAddr::from([127, 0, 0, 1]));
+
+        // 同一连接上的后续查询，以及连接关闭后重连，都沿用同一个本地端点 (端口不变)
+        assert_eq!(whoami(&forward).await, Some((ip, port)));
+        let up = forward.upstreams[0].clone();
+        let conn = up.quic_conn.lock().await.take().unwrap();
+        conn.close(0u32.into(), b"test");
+        assert_eq!(whoami(&forward).await, Some((ip, port)));
+        assert!(up.quic_conn.lock().await.as_ref().is_some_and(|c| c.stable_id() != conn.stable_id()));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::plugin::{file_stamp, Plugin, SharedState};
+use crate::config::{parse_duration, PluginConfig};
+use crate::zone::{in_zone, normalize};
+use crate::types::{DnsMessage, QClass, QType, Record, ResourceRecord};
+use crate::plugin::prometheus::{HOSTS_ENTRIES, HOSTS_RELOAD_TIMESTAMP};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// 解析后的 hosts 表，域名统一为小写、无尾点
+#[derive(Default)]
+struct HostsMap {
+    v4: HashMap<String, Vec<Ipv4Addr>>,
+    v6: HashMap<String, Vec<Ipv6Addr>>,
+    // 反向表：地址 -> 域名，按文件中出现的顺序
+    reverse: HashMap<IpAddr, Vec<String>>,
+}
+
+impl HostsMap {
+    /// 解析 /etc/hosts 格式的一行：`IP NAME [ALIAS...]`，`#` 之后为注释，无法识别的行直接忽略
+    fn add_line(&mut self, line: &str) {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let Some(addr) = fields.next() else { return; };
+        // fe80::1%lo0 这类带 zone 的地址去掉 zone 部分
+        let Ok(ip) = addr.split('%').next().unwrap_or_default().parse::<IpAddr>() else { return; };
+        for name in fields {
+            let name = name.trim_end_matches('.').to_ascii_lowercase();
+            if name.is_empty() { continue; }
+            match ip {
+                IpAddr::V4(v4) => push_unique(self.v4.entry(name.clone()).or_default(), v4),
+                IpAddr::V6(v6) => push_unique(self.v6.entry(name.clone()).or_default(), v6),
+            }
+            push_unique(self.reverse.entry(ip).or_default(), name);
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.v4.contains_key(name) || self.v6.contains_key(name)
+    }
+
+    fn len(&self) -> usize {
+        self.v4.values().map(Vec::len).sum::<usize>() + self.v6.values().map(Vec::len).sum::<usize>()
+    }
+}
+
+fn push_unique<T: PartialEq>(list: &mut Vec<T>, item: T) {
+    if !list.contains(&item) { list.push(item); }
+}
+
+/// 内联条目在前、文件条目在后，合并为一张新表
+fn build_map(path: &str, inline: &[String]) -> HostsMap {
+    let mut map = HostsMap::default();
+    for line in inline { map.add_line(line); }
+    match std::fs::read_to_string(path) {
+        Ok(content) => content.lines().for_each(|line| map.add_line(line)),
+        Err(e) => tracing::warn!("[hosts] Failed to read {}: {}", path, e),
+    }
+    map
+}
+
+pub struct HostsPlugin {
+    zones: Vec<String>,
+    ttl: u32,
+    no_reverse: bool,
+    // None 表示不下沉；Some(空) 表示对全部 zone 下沉
+    fallthrough: Option<Vec<String>>,
+    map: Arc<RwLock<Arc<HostsMap>>>,
+    // 周期性检查文件变化的任务，插件销毁 (热重载) 时取消
+    reload_task: Option<tokio::task::JoinHandle<()>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for HostsPlugin {
+    fn name(&self) -> &str { "hosts" }
+
+    fn from_config(config: &PluginConfig, _shared: Arc<SharedState>) -> Result<Self> {
+        // hosts [FILE [ZONES...]]
+        let path = config.args.first().cloned().unwrap_or_else(|| "/etc/hosts".to_string());
+        let mut zones: Vec<String> = config.args.iter().skip(1).map(|z| normalize(z)).collect();
+        if zones.is_empty() { zones.push(config.origin()); }
+
+        let mut ttl = 3600;
+        let mut no_reverse = false;
+        let mut fallthrough = None;
+        let mut reload = Duration::from_secs(5);
+        let mut inline = Vec::new();
+
+        for sub in &config.block {
+            match sub.name.as_str() {
+                "ttl" if !sub.args.is_empty() => { ttl = sub.args[0].parse().unwrap_or(3600).max(1); }
+                "reload" if !sub.args.is_empty() => { reload = parse_duration(&sub.args[0]).unwrap_or(Duration::from_secs(5)); }
+                "no_reverse" => { no_reverse = true; }
+                "fallthrough" => { fallthrough = Some(sub.args.iter().map(|z| normalize(z)).collect()); }
+                // 其余行视为内联的 hosts 条目，如 `10.0.0.1 example.org`
+                _ => { inline.push(std::iter::once(sub.name.as_str()).chain(sub.args.iter().map(String::as_str)).collect::<Vec<_>>().join(" ")); }
+            }
+        }
+
+        // 先取文件戳再读取：读取期间或后台任务首次运行前的改动都会在下一轮被发现
+        let mut stamp = file_stamp(&path);
+        let initial = build_map(&path, &inline);
+        HOSTS_ENTRIES.with_label_values(&[&config.zone]).set(initial.len() as f64);
+        tracing::info!(
+            "[hosts] Loaded {} entries from {} ({} inline) for zones {:?} (TTL: {}s, Reload: {:?}, Reverse: {})",
+            initial.len(), path, inline.len(), zones, ttl, reload, !no_reverse
+        );
+        let map = Arc::new(RwLock::new(Arc::new(initial)));
+
+        // reload 0 关闭文件监视
+        let reload_task = (!reload.is_zero()).then(|| {
+            let (map, server) = (map.clone(), config.zone.clone());
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(reload).await;
+                    let current = file_stamp(&path);
+                    if current == stamp { continue; }
+                    stamp = current;
+                    let fresh = build_map(&path, &inline);
+                    tracing::info!("[hosts] {} changed, reloaded {} entries", path, fresh.len());
+                    HOSTS_ENTRIES.with_label_values(&[&server]).set(fresh.len() as f64);
+                    HOSTS_RELOAD_TIMESTAMP.with_label_values(&[&server]).set(chrono::Utc::now().timestamp() as f64);
+                    *map.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(fresh);
+                }
+            })
+        });
+
+        Ok(Self { zones, ttl, no_reverse, fallthrough, map, reload_task })
+    }
+
+    async fn process(&self, msg: &mut DnsMessage) -> Result<DnsMessage> {
+        if msg.halt_chain { return Ok(msg.clone()); }
+        let Some(question) = msg.question().cloned() else { return Ok(msg.clone()); };
+        if question.qclass != QClass::IN { return Ok(msg.clone()); }
+
+        let qname = question.name.trim_end_matches('.').to_ascii_lowercase();
+        if !self.zones.iter().any(|z| in_zone(&qname, z)) { return Ok(msg.clone()); }
+
+        let map = self.map.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let answers: Vec<Record> = match question.qtype {
+            QType::A => map.v4.get(&qname).map(|ips| ips.iter().map(|&addr| Record::A { addr }).collect()).unwrap_or_default(),
+            QType::AAAA => map.v6.get(&qname).map(|ips| ips.iter().map(|&addr| Record::AAAA { addr }).collect()).unwrap_or_default(),
+            QType::PTR if !self.no_reverse => reverse_to_ip(&qname)
+                .and_then(|ip| map.reverse.get(&ip))
+                .map(|names| names.iter().map(|n| Record::PTR { ptrdname: n.clone() }).collect())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        // 名字不在 hosts 中：按 fallthrough 交给后续插件，否则 SERVFAIL (hosts 没有 SOA，给不出规范的 NXDOMAIN)
+        let known = !answers.is_empty() || map.contains(&qname);
+        if !known {
+            let falls = self.fallthrough.as_ref().is_some_and(|zones| zones.is_empty() || zones.iter().any(|z| in_zone(&qname, z)));
+            if falls { return Ok(msg.clone()); }
+        }
+
+        let mut resp = msg.reply();
+        resp.header.flags.aa = true;
+        if !known { resp.header.flags.rcode = 2; }
+        resp.answers = answers.into_iter()
+            .map(|data| ResourceRecord { name: question.name.clone(), rclass: QClass::IN, ttl: self.ttl, data })
+            .collect();
+
+        tracing::debug!("TxID: {:#06x} -> [hosts] {} answer(s) for '{}'", msg.header.id, resp.answers.len(), question.name);
+        msg.raw_response = Some(resp.to_wire()?);
+        msg.answered_by = "hosts".to_string();
+        msg.halt_chain = true;
+        Ok(msg.clone())
+    }
+
+    fn priority(&self) -> u8 { 110 }
+}
+
+impl Drop for HostsPlugin {
+    fn drop(&mut self) {
+        if let Some(task) = &self.reload_task { task.abort(); }
+    }
+}
+
+/// "4.3.2.1.in-addr.arpa" -> 1.2.3.4；ip6.arpa 需要完整的 32 个 nibble
+fn reverse_to_ip(name: &str) -> Option<IpAddr> {
+    if let Some(head) = name.strip_suffix(".in-addr.arpa") {
+        let mut octets: Vec<u8> = head.split('.').map(|o| o.parse().ok()).collect::<Option<_>>()?;
+        if octets.len() != 4 { return None; }
+        octets.reverse();
+        return Some(IpAddr::V4(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3])));
+    }
+    let head = name.strip_suffix(".ip6.arpa")?;
+    let nibbles: Vec<u8> = head.split('.').map(|n| u8::from_str_radix(n, 16).ok().filter(|_| n.len() == 1)).collect::<Option<_>>()?;
+    if nibbles.len() != 32 { return None; }
+    let mut bytes = [0u8; 16];
+    for (i, pair) in nibbles.rchunks(2).enumerate() { bytes[i] = (pair[1] << 4) | pair[0]; }
+    Some(IpAddr::V6(Ipv6Addr::from(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DnsQuestion;
+
+    fn hosts(path: &str, args: &[&str], block: &[&str]) -> HostsPlugin {
+        let config = PluginConfig {
+            name: "hosts".to_string(),
+            args: std::iter::once(path).chain(args.iter().copied()).map(String::from).collect(),
+            block: block.iter().map(|line| {
+                let mut words = line.split_whitespace().map(String::from);
+                PluginConfig { name: words.next().unwrap(), args: words.collect(), block: Vec::new(), zone: String::new() }
+            }).collect(),
+            zone: "dns://.:53".to_string(),
+        };
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        HostsPlugin::from_config(&config, shared).unwrap()
+    }
+
+    /// 查询一次；没有应答 (交给后续插件) 时为 None，否则为 (RCODE, 应答记录)
+    async fn ask(plugin: &HostsPlugin, name: &str, qtype: QType) -> Option<(u8, Vec<Record>)> {
+        let mut msg = DnsMessage::default();
+        msg.questions.push(DnsQuestion { name: name.to_string(), qtype, qclass: QClass::IN });
+        plugin.process(&mut msg).await.unwrap();
+        let resp = DnsMessage::from_wire(msg.raw_response.as_ref()?).unwrap();
+        assert!(msg.halt_chain && resp.header.flags.aa);
+        Some((resp.header.flags.rcode, resp.answers.into_iter().map(|rr| rr.data).collect()))
+    }
+
+    fn temp_file(tag: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("coredns-hosts-{}-{}", tag, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn parses_lines_aliases_and_comments() {
+        let mut map = HostsMap::default();
+        for line in ["# comment only", "10.0.0.1 Foo.Test. foo-alias # trailing comment", "10.0.0.1 foo.test", "10.0.0.2\tfoo.test", "fe80::1%lo0 link.test", "not-an-ip bad.test", "10.0.0.3"] {
+            map.add_line(line);
+        }
+        assert_eq!(map.v4["foo.test"], vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]);
+        assert_eq!(map.v4["foo-alias"], vec![Ipv4Addr::new(10, 0, 0, 1)]);
+        assert_eq!(map.v6["link.test"], vec!["fe80::1".parse::<Ipv6Addr>().unwrap()]);
+        assert_eq!(map.reverse[&IpAddr::from([10, 0, 0, 1])], vec!["foo.test", "foo-alias"]);
+        assert!(!map.contains("bad.test"));
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    fn parses_reverse_names() {
+        assert_eq!(reverse_to_ip("4.3.2.1.in-addr.arpa"), Some(IpAddr::from([1, 2, 3, 4])));
+        assert_eq!(reverse_to_ip("3.2.1.in-addr.arpa"), None);
+        assert_eq!(reverse_to_ip("256.3.2.1.in-addr.arpa"), None);
+        let v6 = "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa";
+        assert_eq!(reverse_to_ip(v6), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(reverse_to_ip(&v6[2..]), None);
+        assert_eq!(reverse_to_ip("example.test"), None);
+    }
+
+    #[tokio::test]
+    async fn answers_synthesizes_ptr_and_falls_through() {
+        let path = temp_file("answers", "10.0.0.1 foo.test foo-alias\n2001:db8::1 v6.test\n");
+        let plugin = hosts(&path, &[], &["ttl 60", "reload 0", "192.0.2.9 inline.test"]);
+        let a = |addr: [u8; 4]| Record::A { addr: addr.into() };
+        assert_eq!(ask(&plugin, "Foo.Test", QType::A).await, Some((0, vec![a([10, 0, 0, 1])])));
+        assert_eq!(ask(&plugin, "inline.test", QType::A).await, Some((0, vec![a([192, 0, 2, 9])])));
+        assert_eq!(ask(&plugin, "v6.test", QType::AAAA).await, Some((0, vec![Record::AAAA { addr: "2001:db8::1".parse().unwrap() }])));
+        // 已知名字的其他类型为 NODATA，未知名字在没有 fallthrough 时为 SERVFAIL
+        assert_eq!(ask(&plugin, "foo.test", QType::AAAA).await, Some((0, vec![])));
+        assert_eq!(ask(&plugin, "unknown.test", QType::A).await, Some((2, vec![])));
+        let ptr = |name: &str| Record::PTR { ptrdname: name.to_string() };
+        assert_eq!(ask(&plugin, "1.0.0.10.in-addr.arpa", QType::PTR).await, Some((0, vec![ptr("foo.test"), ptr("foo-alias")])));
+        assert_eq!(ask(&plugin, "2.0.0.10.in-addr.arpa", QType::PTR).await, Some((2, vec![])));
+
+        let plugin = hosts(&path, &[], &["reload 0", "no_reverse"]);
+        assert_eq!(ask(&plugin, "1.0.0.10.in-addr.arpa", QType::PTR).await, Some((2, vec![])));
+
+        // fallthrough 不带参数时对全部名字生效，带 zone 时只对这些 zone 生效
+        let plugin = hosts(&path, &[], &["reload 0", "fallthrough"]);
+        assert_eq!(ask(&plugin, "unknown.test", QType::A).await, None);
+        assert!(ask(&plugin, "foo.test", QType::A).await.is_some());
+        let plugin = hosts(&path, &[], &["reload 0", "fallthrough other.test"]);
+        assert_eq!(ask(&plugin, "a.other.test", QType::A).await, None);
+        assert_eq!(ask(&plugin, "unknown.test", QType::A).await, Some((2, vec![])));
+
+        // 只回答所列 zone 内的名字
+        let plugin = hosts(&path, &["example.test"], &["reload 0", "10.0.0.5 www.example.test"]);
+        assert_eq!(ask(&plugin, "foo.test", QType::A).await, None);
+        assert_eq!(ask(&plugin, "www.example.test", QType::A).await, Some((0, vec![a([10, 0, 0, 5])])));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn reloads_when_the_file_changes() {
+        let path = temp_file("reload", "10.0.0.1 foo.test\n");
+        let plugin = hosts(&path, &[], &["reload 50ms"]);
+        assert_eq!(ask(&plugin, "foo.test", QType::A).await, Some((0, vec![Record::A { addr: [10, 0, 0, 1].into() }])));
+
+        std::fs::write(&path, "10.0.0.22 foo.test\n10.0.0.3 bar.test\n").unwrap();
+        let mut reloaded = None;
+        for _ in 0..40 {
+            reloaded = ask(&plugin, "bar.test", QType::A).await.filter(|(rcode, _)| *rcode == 0);
+            if reloaded.is_some() { break; }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+        assert_eq!(reloaded, Some((0, vec![Record::A { addr: [10, 0, 0, 3].into() }])));
+        assert_eq!(ask(&plugin, "foo.test", QType::A).await, Some((0, vec![Record::A { addr: [10, 0, 0, 22].into() }])));
+        let _ = std::fs::remove_file(&path);
+    }
+}
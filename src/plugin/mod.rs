@@ -1,14 +1,23 @@
+pub mod block;
 pub mod cache;
+pub mod chinadns;
 pub mod errors;
+pub mod file;
 pub mod forward;
+pub mod hosts;
 pub mod log;
 pub mod prometheus;
 pub mod reload;
+pub mod rewrite;
+pub mod secondary;
 pub mod health;
 pub mod whoami;
 pub mod stubs;
+pub mod template;
+pub mod transfer;
 
 use anyhow::Result;
+use std::collections::HashMap;
 use std::sync::Arc;
 use crate::config::PluginConfig;
 use crate::types::DnsMessage;
@@ -31,6 +40,16 @@ pub struct SharedState {
     pub error_tx: tokio::sync::mpsc::Sender<String>,
     pub error_rx: std::sync::Mutex<Option<tokio::sync::mpsc::Receiver<String>>>,
     pub config_path: String,
+    /// 除 Corefile 外需要被 reload 插件监视的文件 (路径, 加载时的 SHA512)，如 TLS 证书
+    pub watched_files: std::sync::Mutex<Vec<(String, String)>>,
+    /// 当前生效的配置，由 DnsServer 创建后回填；插件借此在没有客户端的情况下重新驱动插件链 (如缓存预取)
+    pub config: std::sync::RwLock<std::sync::Weak<crate::config::Config>>,
+    /// file / secondary 插件提供的权威区，键为 (server block 标识, origin)，供 transfer 插件读取整区与变更历史
+    pub zones: std::sync::RwLock<HashMap<(String, String), Arc<crate::zone::ZoneHandle>>>,
+    /// transfer 插件按 server block 登记的规则，区重新加载后据此发送 NOTIFY
+    pub transfers: std::sync::RwLock<HashMap<String, Vec<transfer::TransferRule>>>,
+    /// cache 插件的 persist 快照路径 (绝对路径) 与使用它的 server block，同一路径只能属于一个 block
+    pub cache_snapshots: std::sync::Mutex<HashMap<String, String>>,
 }
 
 impl SharedState {
@@ -45,20 +64,70 @@ impl SharedState {
             error_tx,
             error_rx: std::sync::Mutex::new(Some(error_rx)),
             config_path,
+            watched_files: std::sync::Mutex::new(Vec::new()),
+            config: std::sync::RwLock::new(std::sync::Weak::new()),
+            zones: std::sync::RwLock::new(HashMap::new()),
+            transfers: std::sync::RwLock::new(HashMap::new()),
+            cache_snapshots: std::sync::Mutex::new(HashMap::new()),
         }
     }
+
+    pub fn set_config(&self, config: &Arc<crate::config::Config>) {
+        if let Ok(mut slot) = self.config.write() {
+            *slot = Arc::downgrade(config);
+        }
+    }
+
+    /// 取当前配置；热重载后旧配置被释放，返回 None
+    pub fn config(&self) -> Option<Arc<crate::config::Config>> {
+        self.config.read().ok()?.upgrade()
+    }
+
+    /// 登记一个对外提供的权威区
+    pub fn register_zone(&self, block: &str, handle: Arc<crate::zone::ZoneHandle>) {
+        if let Ok(mut zones) = self.zones.write() {
+            zones.insert((block.to_string(), handle.origin.clone()), handle);
+        }
+    }
+
+    /// 查找本 server block 中 origin 恰为 `origin` 的权威区
+    pub fn zone(&self, block: &str, origin: &str) -> Option<Arc<crate::zone::ZoneHandle>> {
+        self.zones.read().ok()?.get(&(block.to_string(), origin.to_string())).cloned()
+    }
+
+    /// 记录文件当前的哈希，内容变化时由 reload 插件触发热重载
+    pub fn watch_file(&self, path: &str) {
+        let hash = reload::hash_file(path).unwrap_or_default();
+        if let Ok(mut files) = self.watched_files.lock() {
+            files.push((path.to_string(), hash));
+        }
+    }
+}
+
+/// 文件的修改时间与大小，file / hosts / block 据此判断是否需要重新加载；文件不可读时为 None
+pub(crate) fn file_stamp(path: &str) -> Option<(std::time::SystemTime, u64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
 }
 
 // 恢复工厂函数，供 config.rs 使用
 pub fn create_plugin(config: &PluginConfig, shared: Arc<SharedState>) -> Result<Box<dyn Plugin>> {
     match config.name.as_str() {
+        "block" => Ok(Box::new(block::BlockPlugin::from_config(config, shared)?)),
         "cache" => Ok(Box::new(cache::CachePlugin::from_config(config, shared)?)),
+        "chinadns" => Ok(Box::new(chinadns::ChinaDnsPlugin::from_config(config, shared)?)),
         "forward" => Ok(Box::new(forward::ForwardPlugin::from_config(config, shared)?)),
+        "file" => Ok(Box::new(file::FilePlugin::from_config(config, shared)?)),
+        "hosts" => Ok(Box::new(hosts::HostsPlugin::from_config(config, shared)?)),
         "prometheus" => Ok(Box::new(prometheus::PrometheusPlugin::from_config(config, shared)?)),
         "log" => Ok(Box::new(log::LogPlugin::from_config(config, shared)?)),
         "errors" => Ok(Box::new(errors::ErrorsPlugin::from_config(config, shared)?)),
         "reload" => Ok(Box::new(reload::ReloadPlugin::from_config(config, shared)?)),
+        "rewrite" => Ok(Box::new(rewrite::RewritePlugin::from_config(config, shared)?)),
+        "secondary" => Ok(Box::new(secondary::SecondaryPlugin::from_config(config, shared)?)),
         "health" => Ok(Box::new(health::HealthPlugin::from_config(config, shared)?)),
+        "template" => Ok(Box::new(template::TemplatePlugin::from_config(config, shared)?)),
+        "transfer" => Ok(Box::new(transfer::TransferPlugin::from_config(config, shared)?)),
         "whoami" => Ok(Box::new(whoami::WhoamiPlugin::from_config(config, shared)?)),
         
         // 【关键修复】：把 "stubs" 改为 "dummy"，并调用 stubs 模块里的 DummyPlugin
@@ -71,6 +71,18 @@ lazy_static! {
         &["server", "view", "zones"]
     ).unwrap();
 
+    pub static ref CACHE_SERVED_STALE_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "coredns_cache_served_stale_total",
+        "The number of requests served from stale cache entries.",
+        &["server", "view", "zones"]
+    ).unwrap();
+
+    pub static ref CACHE_PREFETCH_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "coredns_cache_prefetch_total",
+        "The number of times the cache has prefetched a cached item.",
+        &["server", "view", "zones"]
+    ).unwrap();
+
     pub static ref PROXY_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
         "coredns_proxy_request_duration_seconds",
         "Histogram of the time each request took.",
@@ -95,6 +107,95 @@ lazy_static! {
         "Counter of the number of queries rejected because the concurrent queries were at maximum."
     ).unwrap();
 
+    pub static ref FORWARD_COALESCED_TOTAL: IntCounter = register_int_counter!(
+        "coredns_forward_coalesced_total",
+        "Counter of queries answered by joining an identical in-flight upstream query."
+    ).unwrap();
+
+    pub static ref FORWARD_HEDGED_TOTAL: IntCounter = register_int_counter!(
+        "coredns_forward_hedged_requests_total",
+        "Counter of extra upstream queries sent by the hedged policy after the first upstream was slow to answer."
+    ).unwrap();
+
+    pub static ref FORWARD_ROUTED_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "coredns_forward_routed_requests_total",
+        "Counter of queries sent to a forward group because they matched one of its domain lists.",
+        &["list"]
+    ).unwrap();
+
+    pub static ref FORWARD_POLLUTED_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "coredns_forward_polluted_responses_total",
+        "Counter of upstream answers containing an address from a poisoned or bogus-nxdomain list.",
+        &["to", "list"]
+    ).unwrap();
+
+    pub static ref CHINADNS_RESPONSES_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "coredns_chinadns_responses_total",
+        "Counter of chinadns answers per upstream group and the reason that group was chosen.",
+        &["server", "group", "reason"]
+    ).unwrap();
+
+    pub static ref HOSTS_ENTRIES: GaugeVec = register_gauge_vec!(
+        "coredns_hosts_entries",
+        "The combined number of entries in hosts and Corefile.",
+        &["server"]
+    ).unwrap();
+
+    pub static ref HOSTS_RELOAD_TIMESTAMP: GaugeVec = register_gauge_vec!(
+        "coredns_hosts_reload_timestamp_seconds",
+        "The timestamp of the last reload of hosts file.",
+        &["server"]
+    ).unwrap();
+
+    pub static ref BLOCK_BLOCKED_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "coredns_block_blocked_requests_total",
+        "Counter of queries answered by the block plugin, per list that matched.",
+        &["server", "list"]
+    ).unwrap();
+
+    pub static ref BLOCK_ALLOWED_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "coredns_block_allowed_requests_total",
+        "Counter of listed queries let through by the allowlist.",
+        &["server"]
+    ).unwrap();
+
+    pub static ref BLOCK_LIST_ENTRIES: GaugeVec = register_gauge_vec!(
+        "coredns_block_list_entries",
+        "The number of domains loaded from each block list.",
+        &["server", "list"]
+    ).unwrap();
+
+    pub static ref TEMPLATE_MATCHES_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "coredns_template_matches_total",
+        "Counter of template regex matches.",
+        &["server", "zone", "class", "type"]
+    ).unwrap();
+
+    pub static ref TEMPLATE_FAILURES_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "coredns_template_template_failures_total",
+        "Counter of records that could not be rendered or parsed from a template.",
+        &["server", "zone", "class", "type", "section"]
+    ).unwrap();
+
+    pub static ref DOH_REQUESTS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "coredns_dns_https_requests_total",
+        "Counter of DNS-over-HTTPS requests per server and HTTP method.",
+        &["server", "method"]
+    ).unwrap();
+
+    pub static ref DOH_RESPONSES_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "coredns_dns_https_responses_total",
+        "Counter of DNS-over-HTTPS responses per server and HTTP status code.",
+        &["server", "status"]
+    ).unwrap();
+
+    pub static ref DOH_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "coredns_dns_https_request_duration_seconds",
+        "Histogram of the time (in seconds) each DNS-over-HTTPS request took.",
+        &["server"],
+        vec![0.00025, 0.0005, 0.001, 0.002, 0.004, 0.008, 0.016, 0.032, 0.064, 0.128, 0.256, 0.512, 1.024, 2.048, 4.096, 8.192]
+    ).unwrap();
+
     pub static ref PLUGIN_ENABLED: GaugeVec = register_gauge_vec!(
         "coredns_plugin_enabled",
         "A metric that indicates whether a plugin is enabled on per server and zone basis.",
@@ -181,7 +282,7 @@ impl Plugin for PrometheusPlugin {
     async fn process(&self, msg: &mut DnsMessage) -> Result<DnsMessage> {
         let req_size = msg.raw_query.len() as f64;
         let server_label = format!("dns://:{}", get_port_from_msg(msg));
-        let qtype = get_qtype_str(&msg.raw_query);
+        let qtype = msg.question().map(|q| q.qtype.as_str()).unwrap_or("UNKNOWN");
         
         let family = match msg.client_addr {
             Some(std::net::SocketAddr::V6(_)) => "2",
@@ -236,19 +337,3 @@ pub fn rcode_to_str(rcode: u8) -> &'static str {
 fn get_port_from_msg(msg: &DnsMessage) -> u16 {
     msg.server_port.unwrap_or(53)
 }
-
-fn get_qtype_str(query: &[u8]) -> &'static str {
-    if query.len() < 12 { return "UNKNOWN"; }
-    let mut offset = 12;
-    while offset < query.len() {
-        let len = query[offset] as usize;
-        if len == 0 { offset += 1; break; }
-        offset += len + 1;
-    }
-    if offset + 2 <= query.len() {
-        let qtype = ((query[offset] as u16) << 8) | (query[offset + 1] as u16);
-        match qtype {
-            1 => "A", 28 => "AAAA", 33 => "SRV", 5 => "CNAME", 15 => "MX", 16 => "TXT", 2 => "NS", 6 => "SOA", 12 => "PTR", 255 => "ANY", _ => "OTHER"
-        }
-    } else { "UNKNOWN" }
-}
\ No newline at end of file
@@ -1,5 +1,5 @@
 use crate::plugin::{Plugin, SharedState};
-use crate::config::PluginConfig;
+use crate::config::{parse_duration, PluginConfig};
 use crate::types::DnsMessage;
 use crate::plugin::prometheus::{RELOAD_FAILED_TOTAL, RELOAD_VERSION_INFO};
 use anyhow::Result;
@@ -20,7 +20,7 @@ impl Plugin for ReloadPlugin {
         let mut interval = Duration::from_secs(30);
         let mut jitter = Duration::from_secs(15);
 
-        if config.args.len() > 0 {
+        if !config.args.is_empty() {
             interval = parse_duration(&config.args[0]).unwrap_or(Duration::from_secs(30));
             if interval < Duration::from_secs(2) { interval = Duration::from_secs(2); }
         }
@@ -57,6 +57,15 @@ impl Plugin for ReloadPlugin {
 
                 sleep(sleep_time).await;
 
+                let changed_file = shared_clone.watched_files.lock().ok().and_then(|files| {
+                    files.iter().find(|(file, hash)| hash_file(file).unwrap_or_default() != *hash).map(|(file, _)| file.clone())
+                });
+                if let Some(file) = changed_file {
+                    tracing::info!("[reload] Watched file {} changed, reloading", file);
+                    let _ = shared_clone.reload_tx.send(true);
+                    break;
+                }
+
                 match hash_file(&path) {
                     Ok(new_hash) => {
                         if new_hash != current_hash {
@@ -90,18 +99,9 @@ impl Drop for ReloadPlugin {
     }
 }
 
-fn hash_file(path: &str) -> Result<String> {
+pub fn hash_file(path: &str) -> Result<String> {
     let content = std::fs::read(path)?;
     let mut hasher = Sha512::new();
     hasher.update(&content);
     Ok(hex::encode(hasher.finalize()))
 }
-
-fn parse_duration(s: &str) -> Result<Duration> {
-    let s = s.trim();
-    if let Some(stripped) = s.strip_suffix("ms") { Ok(Duration::from_millis(stripped.parse()?)) }
-    else if let Some(stripped) = s.strip_suffix('s') { Ok(Duration::from_secs(stripped.parse()?)) }
-    else if let Some(stripped) = s.strip_suffix('m') { Ok(Duration::from_secs(stripped.parse::<u64>()? * 60)) }
-    else if let Some(stripped) = s.strip_suffix('h') { Ok(Duration::from_secs(stripped.parse::<u64>()? * 3600)) }
-    else { anyhow::bail!("invalid duration") }
-}
\ No newline at end of file
@@ -0,0 +1,426 @@
+use crate::plugin::{Plugin, SharedState};
+use crate::config::PluginConfig;
+use crate::types::{DnsMessage, Edns, QClass, QType, Record, ResourceRecord, RewriteStep};
+use crate::zone::{fqdn, normalize};
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// 每条 rewrite 指令一个实例，编号用于在报文上认领自己的改写
+static NEXT_RULE_ID: AtomicUsize = AtomicUsize::new(0);
+/// 查询原本没有 EDNS 时，为插入选项而新建的 OPT 所声明的 UDP 载荷
+const EDNS_UDP_PAYLOAD: u16 = 1232;
+
+#[derive(Clone, Copy, PartialEq)]
+enum MatchKind { Exact, Prefix, Suffix, Substring, Regex }
+
+enum EdnsAction { Set, Replace, Append }
+
+/// 应答中名字的还原方式
+enum AnswerRewrite {
+    None,
+    /// 按规则反向映射应答段的 owner 与 CNAME 等目标名
+    Auto,
+    /// `answer name REGEX REPLACEMENT` / `answer value REGEX REPLACEMENT`
+    Explicit { name: Option<(Regex, String)>, value: Option<(Regex, String)> },
+}
+
+enum Rule {
+    Name { kind: MatchKind, from: String, to: String, regex: Option<Regex>, answer: AnswerRewrite },
+    Type { from: QType, to: QType },
+    Class { from: QClass, to: QClass },
+    Edns0 { action: EdnsAction, code: u16, data: Vec<u8> },
+}
+
+pub struct RewritePlugin {
+    id: usize,
+    // stop (默认)：本规则生效后，之后的 rewrite 规则不再处理该查询
+    stop: bool,
+    rule: Rule,
+}
+
+#[async_trait::async_trait]
+impl Plugin for RewritePlugin {
+    fn name(&self) -> &str { "rewrite" }
+
+    fn from_config(config: &PluginConfig, _shared: Arc<SharedState>) -> Result<Self> {
+        // rewrite [continue|stop] FIELD ARGS...
+        let mut args: Vec<&str> = config.args.iter().map(String::as_str).collect();
+        let stop = match args.first() {
+            Some(&"continue") => { args.remove(0); false }
+            Some(&"stop") => { args.remove(0); true }
+            _ => true,
+        };
+        let Some((&field, rest)) = args.split_first() else { bail!("rewrite requires a rule"); };
+        let rule = match field {
+            "name" => parse_name_rule(rest)?,
+            "type" => match rest {
+                [from, to] => Rule::Type {
+                    from: from.parse()?,
+                    to: to.parse()?,
+                },
+                _ => bail!("rewrite type requires FROM TO"),
+            },
+            "class" => match rest {
+                [from, to] => Rule::Class {
+                    from: from.parse()?,
+                    to: to.parse()?,
+                },
+                _ => bail!("rewrite class requires FROM TO"),
+            },
+            "edns0" => match rest {
+                ["local", action, code, data] => Rule::Edns0 {
+                    action: match *action {
+                        "set" => EdnsAction::Set,
+                        "replace" => EdnsAction::Replace,
+                        "append" => EdnsAction::Append,
+                        other => bail!("unknown edns0 action {}", other),
+                    },
+                    code: parse_code(code).with_context(|| format!("invalid EDNS0 option code {}", code))?,
+                    data: parse_data(data)?,
+                },
+                _ => bail!("rewrite edns0 requires: local set|replace|append CODE DATA"),
+            },
+            other => bail!("unknown rewrite field {}", other),
+        };
+
+        tracing::info!("[rewrite] Loaded rule: {}", config.args.join(" "));
+        Ok(Self { id: NEXT_RULE_ID.fetch_add(1, Ordering::Relaxed), stop, rule })
+    }
+
+    async fn process(&self, msg: &mut DnsMessage) -> Result<DnsMessage> {
+        if msg.halt_chain || msg.rewrites.iter().any(|step| step.stop) { return Ok(msg.clone()); }
+        let Some(original) = msg.question().cloned() else { return Ok(msg.clone()); };
+
+        let mut question = original.clone();
+        let mut edns = msg.edns.clone();
+        let applied = match &self.rule {
+            Rule::Name { kind, from, to, regex, .. } => match rewrite_name(&question.name, *kind, from, to, regex.as_ref()) {
+                Some(name) => { question.name = name; true }
+                None => false,
+            },
+            Rule::Type { from, to } if question.qtype == *from => { question.qtype = *to; true }
+            Rule::Class { from, to } if question.qclass == *from => { question.qclass = *to; true }
+            Rule::Edns0 { action, code, data } => set_option(&mut edns, action, *code, data),
+            _ => false,
+        };
+        if !applied { return Ok(msg.clone()); }
+
+        tracing::debug!(
+            "TxID: {:#06x} -> [rewrite] {} {} -> {} {}",
+            msg.header.id, original.name, original.qtype.as_str(), question.name, question.qtype.as_str()
+        );
+        msg.rewrites.push(RewriteStep { rule: self.id, stop: self.stop, question: original, edns: msg.edns.clone(), raw_query: msg.raw_query.clone() });
+        msg.questions[0] = question;
+        msg.edns = edns;
+        // 后续插件 (cache 键、forward 上游查询) 都以改写后的报文为准
+        msg.raw_query = msg.to_wire()?;
+        Ok(msg.clone())
+    }
+
+    async fn post_process(&self, msg: &mut DnsMessage) -> Result<()> {
+        let Some(pos) = msg.rewrites.iter().rposition(|step| step.rule == self.id) else { return Ok(()); };
+        let step = msg.rewrites.remove(pos);
+        let rewritten = msg.question().cloned();
+        msg.questions = vec![step.question.clone()];
+        msg.edns = step.edns;
+        msg.raw_query = step.raw_query;
+
+        let question_changed = rewritten.as_ref().is_some_and(|q| q.name != step.question.name || q.qtype != step.question.qtype || q.qclass != step.question.qclass);
+        let has_answer_rule = matches!(&self.rule, Rule::Name { answer, .. } if !matches!(answer, AnswerRewrite::None));
+        if !question_changed && !has_answer_rule { return Ok(()); }
+        let Some(raw) = &msg.raw_response else { return Ok(()); };
+        let Ok(mut resp) = DnsMessage::from_wire(raw) else { return Ok(()); };
+
+        // 问题段还原为客户端所问，否则客户端会丢弃应答
+        resp.questions = vec![step.question.clone()];
+        if let (Rule::Name { kind, from, to, answer, .. }, Some(rewritten)) = (&self.rule, &rewritten) {
+            for rr in &mut resp.answers {
+                restore_record(rr, answer, *kind, from, to, &rewritten.name, &step.question.name);
+            }
+        }
+        let mut wire = resp.to_wire()?;
+        // 找回客户端原始的大小写 (0x20)
+        crate::wire::adopt_query(&mut wire, &msg.raw_query);
+        msg.raw_response = Some(wire);
+        Ok(())
+    }
+
+    fn priority(&self) -> u8 { 130 }
+}
+
+/// name [exact|prefix|suffix|substring|regex] FROM TO [answer auto | answer name FROM TO [answer value FROM TO]]
+fn parse_name_rule(args: &[&str]) -> Result<Rule> {
+    let (kind, rest) = match args.first() {
+        Some(&"exact") => (MatchKind::Exact, &args[1..]),
+        Some(&"prefix") => (MatchKind::Prefix, &args[1..]),
+        Some(&"suffix") => (MatchKind::Suffix, &args[1..]),
+        Some(&"substring") => (MatchKind::Substring, &args[1..]),
+        Some(&"regex") => (MatchKind::Regex, &args[1..]),
+        _ => (MatchKind::Exact, args),
+    };
+    let [from, to, answer_args @ ..] = rest else { bail!("rewrite name requires FROM TO"); };
+
+    let (from, to, regex) = match kind {
+        MatchKind::Regex => {
+            let regex = Regex::new(&format!("(?i){}", from)).with_context(|| format!("invalid regex {}", from))?;
+            (from.to_string(), capture_template(to), Some(regex))
+        }
+        // 前缀与子串按字面匹配，`foo.` 只匹配完整的首个标签
+        MatchKind::Prefix | MatchKind::Substring => (from.to_ascii_lowercase(), to.to_ascii_lowercase(), None),
+        MatchKind::Exact | MatchKind::Suffix => (normalize(from), normalize(to), None),
+    };
+
+    let answer = match answer_args {
+        [] => AnswerRewrite::None,
+        ["answer", "auto"] => AnswerRewrite::Auto,
+        _ => {
+            let (mut name, mut value) = (None, None);
+            for pair in answer_args.chunks(4) {
+                let ["answer", target, pattern, replacement] = pair else { bail!("invalid answer rewrite: {}", pair.join(" ")); };
+                let regex = Regex::new(&format!("(?i){}", pattern)).with_context(|| format!("invalid regex {}", pattern))?;
+                match *target {
+                    "name" => name = Some((regex, capture_template(replacement))),
+                    "value" => value = Some((regex, capture_template(replacement))),
+                    other => bail!("unknown answer rewrite target {}", other),
+                }
+            }
+            AnswerRewrite::Explicit { name, value }
+        }
+    };
+    Ok(Rule::Name { kind, from, to, regex, answer })
+}
+
+/// 按规则改写查询名；不匹配或改写后没有变化时返回 None
+fn rewrite_name(name: &str, kind: MatchKind, from: &str, to: &str, regex: Option<&Regex>) -> Option<String> {
+    let lower = name.to_ascii_lowercase();
+    let rewritten = match kind {
+        MatchKind::Exact => (lower == from).then(|| to.to_string()),
+        MatchKind::Prefix => lower.strip_prefix(from).map(|rest| format!("{}{}", to, rest)),
+        MatchKind::Suffix => lower.strip_suffix(from).map(|head| format!("{}{}", head, to)),
+        MatchKind::Substring => lower.contains(from).then(|| lower.replacen(from, to, 1)),
+        // 正则匹配带尾点的完整域名，与 CoreDNS 的写法保持一致
+        MatchKind::Regex => {
+            let fqdn = fqdn(name);
+            let regex = regex?;
+            regex.is_match(&fqdn).then(|| regex.replace(&fqdn, to).into_owned())
+        }
+    }?;
+    let rewritten = normalize(&rewritten);
+    (rewritten != lower).then_some(rewritten)
+}
+
+/// `answer auto` 时的反向映射：改写后的名字还原为改写前的
+fn reverse_name(name: &str, kind: MatchKind, from: &str, to: &str, rewritten: &str, original: &str) -> Option<String> {
+    let lower = name.to_ascii_lowercase();
+    if lower == rewritten.to_ascii_lowercase() { return Some(original.to_string()); }
+    match kind {
+        MatchKind::Exact | MatchKind::Regex => None,
+        MatchKind::Prefix => lower.strip_prefix(to).map(|rest| format!("{}{}", from, rest)),
+        MatchKind::Suffix => lower.strip_suffix(to).map(|head| format!("{}{}", head, from)),
+        MatchKind::Substring => lower.contains(to).then(|| lower.replacen(to, from, 1)),
+    }
+}
+
+fn restore_record(rr: &mut ResourceRecord, answer: &AnswerRewrite, kind: MatchKind, from: &str, to: &str, rewritten: &str, original: &str) {
+    let map = |name: &str, rule: Option<&(Regex, String)>| -> Option<String> {
+        match answer {
+            AnswerRewrite::None => None,
+            AnswerRewrite::Auto => reverse_name(name, kind, from, to, rewritten, original),
+            AnswerRewrite::Explicit { .. } => {
+                let (regex, replacement) = rule?;
+                let fqdn = fqdn(name);
+                regex.is_match(&fqdn).then(|| normalize(&regex.replace(&fqdn, replacement.as_str())))
+            }
+        }
+    };
+    let (name_rule, value_rule) = match answer {
+        AnswerRewrite::Explicit { name, value } => (name.as_ref(), value.as_ref()),
+        _ => (None, None),
+    };
+    if let Some(name) = map(&rr.name, name_rule) { rr.name = name; }
+    let target = match &mut rr.data {
+        Record::CNAME { cname } => cname,
+        Record::PTR { ptrdname } => ptrdname,
+        Record::NS { nsdname } => nsdname,
+        Record::MX { exchange, .. } => exchange,
+        Record::SRV { target, .. } => target,
+        _ => return,
+    };
+    if let Some(value) = map(target, value_rule) { *target = value; }
+}
+
+/// 按动作写入 EDNS0 选项，返回查询是否被修改
+fn set_option(edns: &mut Option<Edns>, action: &EdnsAction, code: u16, data: &[u8]) -> bool {
+    if edns.is_none() && matches!(action, EdnsAction::Replace) { return false; }
+    let edns = edns.get_or_insert_with(|| Edns::new(EDNS_UDP_PAYLOAD));
+    let existing = edns.options.iter_mut().find(|(c, _)| *c == code);
+    match (action, existing) {
+        (EdnsAction::Append, _) | (EdnsAction::Set, None) => edns.options.push((code, data.to_vec())),
+        (EdnsAction::Set | EdnsAction::Replace, Some(option)) => option.1 = data.to_vec(),
+        (EdnsAction::Replace, None) => return false,
+    }
+    true
+}
+
+/// CoreDNS 风格的 {1} 捕获组引用转换为 regex 的 ${1}
+fn capture_template(template: &str) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start + 1..].find('}') else { break };
+        let group = &rest[start + 1..start + 1 + len];
+        out.push_str(&rest[..start]);
+        if !group.is_empty() && group.chars().all(|c| c.is_ascii_digit()) { out.push_str(&format!("${{{}}}", group)); }
+        else { out.push_str(&rest[start..start + len + 2]); }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 十进制或 0x 开头的十六进制
+fn parse_code(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// 0x 开头按十六进制字节解析，否则取字符串本身的字节
+fn parse_data(s: &str) -> Result<Vec<u8>> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => hex::decode(hex).with_context(|| format!("invalid hex data {}", s)),
+        None => Ok(s.as_bytes().to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DnsQuestion;
+
+    fn rule(args: &str) -> RewritePlugin {
+        let config = PluginConfig { name: "rewrite".to_string(), args: args.split_whitespace().map(String::from).collect(), block: Vec::new(), zone: "dns://.:53".to_string() };
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        RewritePlugin::from_config(&config, shared).unwrap()
+    }
+
+    fn record(name: &str, data: Record) -> ResourceRecord {
+        ResourceRecord { name: name.to_string(), rclass: QClass::IN, ttl: 60, data }
+    }
+
+    /// 依次执行 rewrite 规则，上游按改写后的问题用 `answers` 应答，再逆序执行 post_process；
+    /// 返回上游看到的问题名与客户端收到的应答。`name` 与线格式解析出的问题名一样不带尾点
+    async fn resolve(rules: &[RewritePlugin], name: &str, answers: fn(&str) -> Vec<ResourceRecord>) -> (String, DnsMessage) {
+        let mut msg = DnsMessage::default();
+        msg.header.id = 0x1234;
+        msg.questions.push(DnsQuestion { name: name.to_string(), qtype: QType::A, qclass: QClass::IN });
+        msg.raw_query = msg.to_wire().unwrap();
+        for rule in rules {
+            rule.process(&mut msg).await.unwrap();
+        }
+        let asked = msg.questions[0].name.clone();
+        let mut resp = DnsMessage::from_wire(&msg.raw_query).unwrap().reply();
+        resp.answers = answers(&asked);
+        msg.raw_response = Some(resp.to_wire().unwrap());
+        for rule in rules.iter().rev() {
+            rule.post_process(&mut msg).await.unwrap();
+        }
+        assert!(msg.rewrites.is_empty());
+        (asked, DnsMessage::from_wire(msg.raw_response.as_ref().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn rewrites_and_reverses_each_match_kind() {
+        let rewrite = |name: &str, kind: MatchKind, from: &str, to: &str, regex: Option<&Regex>| rewrite_name(name, kind, from, to, regex);
+        assert_eq!(rewrite("Www.Example.COM", MatchKind::Exact, "www.example.com", "www.example.net", None).as_deref(), Some("www.example.net"));
+        assert_eq!(rewrite("a.www.example.com", MatchKind::Exact, "www.example.com", "www.example.net", None), None);
+        assert_eq!(rewrite("foo.example.com", MatchKind::Prefix, "foo.", "bar.", None).as_deref(), Some("bar.example.com"));
+        assert_eq!(rewrite("food.example.com", MatchKind::Prefix, "foo.", "bar.", None), None);
+        assert_eq!(rewrite("a.example.com", MatchKind::Suffix, "example.com", "example.net", None).as_deref(), Some("a.example.net"));
+        assert_eq!(rewrite("a.internal.test", MatchKind::Substring, "internal", "corp", None).as_deref(), Some("a.corp.test"));
+        let regex = Regex::new(r"(?i)^(.+)\.example\.com\.$").unwrap();
+        let to = capture_template("{1}.example.net.");
+        assert_eq!(rewrite("WWW.example.com", MatchKind::Regex, "", &to, Some(&regex)).as_deref(), Some("www.example.net"));
+        assert_eq!(rewrite("example.com", MatchKind::Regex, "", &to, Some(&regex)), None);
+        // 改写后没有变化时视为不匹配
+        assert_eq!(rewrite("www.example.com", MatchKind::Exact, "www.example.com", "www.example.com", None), None);
+
+        // 与查询名相同的名字还原为客户端所问；其余名字按规则反向映射，exact 与 regex 无法反向
+        let reverse = |name: &str, kind: MatchKind, from: &str, to: &str| reverse_name(name, kind, from, to, "www.example.net", "Www.Example.com");
+        assert_eq!(reverse("WWW.example.net", MatchKind::Regex, "", "").as_deref(), Some("Www.Example.com"));
+        assert_eq!(reverse("cdn.example.net", MatchKind::Exact, "www.example.com", "www.example.net"), None);
+        assert_eq!(reverse("cdn.example.net", MatchKind::Regex, "", ""), None);
+        assert_eq!(reverse("bar.cdn.test", MatchKind::Prefix, "foo.", "bar.").as_deref(), Some("foo.cdn.test"));
+        assert_eq!(reverse("cdn.example.net", MatchKind::Suffix, "example.com", "example.net").as_deref(), Some("cdn.example.com"));
+        assert_eq!(reverse("cdn.corp.test", MatchKind::Substring, "internal", "corp").as_deref(), Some("cdn.internal.test"));
+        assert_eq!(reverse("cdn.other.test", MatchKind::Suffix, "example.com", "example.net"), None);
+    }
+
+    #[test]
+    fn converts_capture_groups() {
+        assert_eq!(capture_template("{1}.svc.{2}.local."), "${1}.svc.${2}.local.");
+        assert_eq!(capture_template("{name}.test"), "{name}.test");
+        assert_eq!(capture_template("a{1"), "a{1");
+    }
+
+    #[test]
+    fn sets_replaces_and_appends_edns_options() {
+        let mut edns = None;
+        assert!(!set_option(&mut edns, &EdnsAction::Replace, 0xfde9, b"a"));
+        assert!(edns.is_none());
+        assert!(set_option(&mut edns, &EdnsAction::Set, 0xfde9, b"a"));
+        assert_eq!(edns.as_ref().map(|e| (e.udp_payload_size, e.options.clone())), Some((EDNS_UDP_PAYLOAD, vec![(0xfde9, b"a".to_vec())])));
+        assert!(set_option(&mut edns, &EdnsAction::Set, 0xfde9, b"b"));
+        assert!(set_option(&mut edns, &EdnsAction::Replace, 0xfde9, b"c"));
+        assert!(!set_option(&mut edns, &EdnsAction::Replace, 0xfdea, b"x"));
+        assert!(set_option(&mut edns, &EdnsAction::Append, 0xfde9, b"d"));
+        assert_eq!(edns.unwrap().options, vec![(0xfde9, b"c".to_vec()), (0xfde9, b"d".to_vec())]);
+
+        let mut edns = None;
+        assert!(set_option(&mut edns, &EdnsAction::Append, 10, b"x"));
+        assert_eq!(edns.unwrap().options, vec![(10, b"x".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn answer_auto_restores_owners_and_targets() {
+        fn cname_chain(name: &str) -> Vec<ResourceRecord> {
+            vec![
+                record(name, Record::CNAME { cname: "cdn.example.net".to_string() }),
+                record("cdn.example.net", Record::A { addr: [192, 0, 2, 1].into() }),
+            ]
+        }
+        let (asked, resp) = resolve(&[rule("name suffix example.com example.net answer auto")], "Www.Example.com", cname_chain).await;
+        assert_eq!(asked, "www.example.net");
+        assert_eq!(resp.header.id, 0x1234);
+        assert_eq!(resp.questions[0].name.trim_end_matches('.'), "Www.Example.com");
+        // 压缩指针指向问题段时会带上客户端的大小写，只按小写比较
+        let names: Vec<String> = resp.answers.iter().map(|rr| rr.name.to_ascii_lowercase()).collect();
+        assert_eq!(names, vec!["www.example.com", "cdn.example.com"]);
+        assert!(matches!(&resp.answers[0].data, Record::CNAME { cname } if cname.eq_ignore_ascii_case("cdn.example.com")));
+
+        // 没有 answer 规则时只还原问题段
+        let (_, resp) = resolve(&[rule("name suffix example.com example.net")], "www.example.com", cname_chain).await;
+        assert_eq!(resp.questions[0].name.trim_end_matches('.'), "www.example.com");
+        assert_eq!(resp.answers[0].name, "www.example.net");
+        assert_eq!(resp.answers[0].data, Record::CNAME { cname: "cdn.example.net".to_string() });
+    }
+
+    #[tokio::test]
+    async fn stop_ends_the_rewrite_chain_and_continue_does_not() {
+        fn none(_: &str) -> Vec<ResourceRecord> { Vec::new() }
+        let (asked, resp) = resolve(&[rule("name exact a.test b.test"), rule("name exact b.test c.test")], "a.test", none).await;
+        assert_eq!(asked, "b.test");
+        assert_eq!(resp.questions[0].name.trim_end_matches('.'), "a.test");
+
+        let (asked, resp) = resolve(&[rule("continue name exact a.test b.test"), rule("name regex ^b\\.(.*)$ c.{1}")], "a.test", none).await;
+        assert_eq!(asked, "c.test");
+        assert_eq!(resp.questions[0].name.trim_end_matches('.'), "a.test");
+
+        // 不匹配的规则不影响后续规则
+        let (asked, _) = resolve(&[rule("name exact x.test y.test"), rule("type A AAAA")], "a.test", none).await;
+        assert_eq!(asked, "a.test");
+    }
+}
@@ -0,0 +1,358 @@
+use crate::plugin::{Plugin, SharedState};
+use crate::config::PluginConfig;
+use crate::types::{DnsHeader, DnsMessage, DnsQuestion, QClass, QType, Record, ResourceRecord};
+use crate::zone::{in_zone, normalize, parse_target, serial_cmp, soa_serial, Zone, ZoneHandle};
+use anyhow::{bail, Context, Result};
+use std::cmp::Ordering;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio::time::{timeout, Duration, Instant};
+
+/// 还没有拿到过区数据 (也就没有 SOA 定时器) 时的重试间隔
+const INITIAL_RETRY: Duration = Duration::from_secs(10);
+/// 连接主服务器及等待每个应答报文的超时
+const XFR_TIMEOUT: Duration = Duration::from_secs(10);
+const OPCODE_NOTIFY: u8 = 4;
+
+/// 从主服务器同步的一个区
+struct SecondaryZone {
+    handle: Arc<ZoneHandle>,
+    // 收到 NOTIFY 后唤醒同步任务立即检查 SERIAL
+    notify: Arc<Notify>,
+}
+
+pub struct SecondaryPlugin {
+    zones: Vec<SecondaryZone>,
+    primaries: Vec<SocketAddr>,
+    // 每个区一个同步任务，插件销毁 (热重载) 时取消
+    tasks: Vec<tokio::task::JoinHandle<()>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for SecondaryPlugin {
+    fn name(&self) -> &str { "secondary" }
+
+    fn from_config(config: &PluginConfig, shared: Arc<SharedState>) -> Result<Self> {
+        // secondary [ZONES...] { transfer from ADDRESS... }
+        let mut origins: Vec<String> = config.args.iter().map(|z| normalize(z)).collect();
+        if origins.is_empty() { origins.push(config.origin()); }
+
+        let mut primaries = Vec::new();
+        for sub in config.block.iter().filter(|sub| sub.name == "transfer" && sub.args.first().is_some_and(|a| a == "from")) {
+            for addr in &sub.args[1..] {
+                match parse_target(addr) {
+                    Some(primary) => primaries.push(primary),
+                    None => tracing::error!("[secondary] Invalid address in 'transfer from': {}", addr),
+                }
+            }
+        }
+        if primaries.is_empty() { bail!("secondary requires a 'transfer from' address"); }
+
+        let mut zones = Vec::new();
+        let mut tasks = Vec::new();
+        for origin in origins {
+            // 首次同步完成前区为空，区内查询应答 SERVFAIL
+            let handle = Arc::new(ZoneHandle::new(&origin, None));
+            shared.register_zone(&config.zone, handle.clone());
+            let notify = Arc::new(Notify::new());
+            let (shared, block, primaries) = (shared.clone(), config.zone.clone(), primaries.clone());
+            let (task_handle, task_notify) = (handle.clone(), notify.clone());
+            tasks.push(tokio::spawn(async move { maintain(shared, block, task_handle, primaries, task_notify).await }));
+            zones.push(SecondaryZone { handle, notify });
+        }
+
+        tracing::info!("[secondary] Transferring zones {:?} from {:?}", zones.iter().map(|z| z.handle.origin.as_str()).collect::<Vec<_>>(), primaries);
+        Ok(Self { zones, primaries, tasks })
+    }
+
+    async fn process(&self, msg: &mut DnsMessage) -> Result<DnsMessage> {
+        if msg.halt_chain { return Ok(msg.clone()); }
+        let Some(question) = msg.question().cloned() else { return Ok(msg.clone()); };
+        if question.qclass != QClass::IN { return Ok(msg.clone()); }
+
+        let qname = question.name.trim_end_matches('.').to_ascii_lowercase();
+        let Some(zone) = self.zones.iter()
+            .filter(|z| in_zone(&qname, &z.handle.origin))
+            .max_by_key(|z| if z.handle.origin == "." { 0 } else { z.handle.origin.len() + 1 })
+        else { return Ok(msg.clone()); };
+
+        let mut resp = msg.reply();
+        resp.header.flags.ra = false;
+        if msg.header.flags.opcode == OPCODE_NOTIFY {
+            // RFC 1996 3.10: 只接受来自主服务器、针对区 apex 的 NOTIFY
+            let from_primary = msg.client_addr.is_some_and(|c| self.primaries.iter().any(|p| p.ip().to_canonical() == c.ip().to_canonical()));
+            if from_primary && qname == zone.handle.origin {
+                tracing::info!("[secondary] NOTIFY for {} from {}, checking primary", zone.handle.origin, msg.client_addr.map(|a| a.to_string()).unwrap_or_default());
+                resp.header.flags.aa = true;
+                zone.notify.notify_one();
+            } else {
+                tracing::warn!("[secondary] Refused NOTIFY for '{}' from {}", question.name, msg.client_addr.map(|a| a.to_string()).unwrap_or_default());
+                resp.header.flags.rcode = 5;
+            }
+        } else {
+            match zone.handle.get() {
+                // 区传送由 transfer 插件处理，这里不应答整区
+                _ if matches!(question.qtype, QType::AXFR | QType::IXFR) => resp.header.flags.rcode = 5,
+                None => resp.header.flags.rcode = 2,
+                Some(data) => {
+                    let result = data.lookup(&qname, question.qtype);
+                    resp.header.flags.aa = result.authoritative;
+                    resp.header.flags.rcode = result.rcode;
+                    resp.answers = result.answers;
+                    resp.authority = result.authority;
+                    resp.additional = result.additional;
+                }
+            }
+        }
+
+        tracing::debug!(
+            "TxID: {:#06x} -> [secondary] Zone {} answered '{}' with RCODE {} ({} answers)",
+            msg.header.id, zone.handle.origin, question.name, resp.header.flags.rcode, resp.answers.len()
+        );
+        msg.raw_response = Some(resp.to_wire()?);
+        msg.answered_by = "secondary".to_string();
+        msg.halt_chain = true;
+        Ok(msg.clone())
+    }
+
+    fn priority(&self) -> u8 { 106 }
+}
+
+impl Drop for SecondaryPlugin {
+    fn drop(&mut self) {
+        for task in &self.tasks { task.abort(); }
+    }
+}
+
+/// 按 SOA 的 REFRESH / RETRY / EXPIRE 维护一个区 (RFC 1034 4.3.5)：
+/// 检查成功后等 REFRESH，失败等 RETRY，连续失败超过 EXPIRE 则丢弃区数据；NOTIFY 立即触发一次检查
+async fn maintain(shared: Arc<SharedState>, block: String, handle: Arc<ZoneHandle>, primaries: Vec<SocketAddr>, notify: Arc<Notify>) {
+    let mut last_success = Instant::now();
+    loop {
+        let current = handle.get();
+        let ok = match refresh(&handle.origin, current.as_deref(), &primaries).await {
+            Ok(Some(zone)) => {
+                tracing::info!("[secondary] Transferred zone {} with serial {} ({} records)", zone.origin, zone.serial(), zone.len());
+                if handle.update(zone) {
+                    if let Some(zone) = handle.get() { crate::plugin::transfer::notify(&shared, &block, &zone); }
+                }
+                true
+            }
+            Ok(None) => true,
+            Err(e) => {
+                tracing::warn!("[secondary] Failed to refresh zone {}: {:#}", handle.origin, e);
+                false
+            }
+        };
+        if ok { last_success = Instant::now(); }
+
+        let timers = handle.get().and_then(|zone| match zone.soa().data {
+            Record::SOA { refresh, retry, expire, .. } => Some((refresh, retry, expire)),
+            _ => None,
+        });
+        let wait = match timers {
+            Some((_, _, expire)) if !ok && last_success.elapsed() >= Duration::from_secs(expire as u64) => {
+                tracing::error!("[secondary] Zone {} expired after {}s without reaching a primary", handle.origin, expire);
+                handle.clear();
+                INITIAL_RETRY
+            }
+            Some((refresh, retry, _)) => Duration::from_secs(if ok { refresh } else { retry }.max(1) as u64),
+            None => INITIAL_RETRY,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = notify.notified() => {}
+        }
+    }
+}
+
+/// 依次尝试各个主服务器：SERIAL 比本地新时做 AXFR 返回新区，已是最新返回 None
+async fn refresh(origin: &str, current: Option<&Zone>, primaries: &[SocketAddr]) -> Result<Option<Zone>> {
+    let mut last_err = anyhow::anyhow!("no primary configured");
+    for primary in primaries {
+        let attempt = async {
+            let soa = exchange(*primary, origin, QType::SOA).await?
+                .into_iter().find(|r| r.data.rtype() == QType::SOA && normalize(&r.name) == origin)
+                .context("primary answered without the zone's SOA")?;
+            if current.is_some_and(|zone| serial_cmp(soa_serial(&soa), zone.serial()) != Some(Ordering::Greater)) { return Ok(None); }
+            let records = exchange(*primary, origin, QType::AXFR).await?;
+            Zone::from_records(origin, records).map(Some)
+        };
+        match attempt.await {
+            Ok(result) => return Ok(result),
+            Err(e) => last_err = e.context(format!("primary {}", primary)),
+        }
+    }
+    Err(last_err)
+}
+
+/// 经 TCP 向主服务器发一个查询，返回应答记录；AXFR 读取多个报文直到结尾的 SOA，并去掉结尾那条重复的 SOA
+async fn exchange(primary: SocketAddr, origin: &str, qtype: QType) -> Result<Vec<ResourceRecord>> {
+    let mut query = DnsMessage { header: DnsHeader { id: rand::random(), ..Default::default() }, ..Default::default() };
+    query.questions.push(DnsQuestion { name: origin.to_string(), qtype, qclass: QClass::IN });
+    let wire = query.to_wire()?;
+
+    let mut stream = timeout(XFR_TIMEOUT, TcpStream::connect(primary)).await.context("connect timed out")??;
+    let mut framed = (wire.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(&wire);
+    stream.write_all(&framed).await?;
+
+    let mut records: Vec<ResourceRecord> = Vec::new();
+    loop {
+        let mut len_buf = [0u8; 2];
+        timeout(XFR_TIMEOUT, stream.read_exact(&mut len_buf)).await.context("read timed out")??;
+        let mut buf = vec![0u8; u16::from_be_bytes(len_buf) as usize];
+        timeout(XFR_TIMEOUT, stream.read_exact(&mut buf)).await.context("read timed out")??;
+        let resp = DnsMessage::from_wire(&buf)?;
+        if resp.header.id != query.header.id { bail!("response ID mismatch"); }
+        if resp.header.flags.rcode != 0 { bail!("{} refused with RCODE {}", qtype.as_str(), resp.header.flags.rcode); }
+        records.extend(resp.answers);
+        if qtype != QType::AXFR { return Ok(records); }
+
+        // RFC 5936 2.2: 首尾各一条相同的 SOA
+        let Some(first) = records.first() else { bail!("empty AXFR response"); };
+        if first.data.rtype() != QType::SOA { bail!("AXFR does not start with SOA"); }
+        if records.len() > 1 && records.last().is_some_and(|last| last.data.rtype() == QType::SOA) {
+            records.pop();
+            return Ok(records);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::parse_record;
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering as AtomicOrdering};
+    use tokio::net::TcpListener;
+    use tokio::time::sleep;
+
+    /// 本地主服务器：应答 SOA 查询，AXFR 分两个报文发出；记录收到的 SOA / AXFR 查询数
+    #[derive(Default)]
+    struct Primary {
+        serial: AtomicU32,
+        soa_queries: AtomicUsize,
+        axfr_queries: AtomicUsize,
+    }
+
+    impl Primary {
+        fn records(&self) -> (ResourceRecord, Vec<ResourceRecord>) {
+            let serial = self.serial.load(AtomicOrdering::SeqCst);
+            let rr = |text: &str| parse_record(text, "example.com", 3600).unwrap();
+            let soa = rr(&format!("@ SOA ns1 hostmaster {} 3600 600 86400 300", serial));
+            let body = vec![rr("@ NS ns1"), rr("ns1 A 192.0.2.1"), rr(&format!("www A 192.0.2.{}", serial % 256))];
+            (soa, body)
+        }
+
+        async fn serve(self: Arc<Self>, listener: TcpListener) {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let primary = self.clone();
+                tokio::spawn(async move {
+                    let mut len_buf = [0u8; 2];
+                    stream.read_exact(&mut len_buf).await?;
+                    let mut buf = vec![0u8; u16::from_be_bytes(len_buf) as usize];
+                    stream.read_exact(&mut buf).await?;
+                    let query = DnsMessage::from_wire(&buf)?;
+                    let (soa, body) = primary.records();
+                    let messages = match query.question().map(|q| q.qtype) {
+                        Some(QType::SOA) => {
+                            primary.soa_queries.fetch_add(1, AtomicOrdering::SeqCst);
+                            vec![vec![soa]]
+                        }
+                        Some(QType::AXFR) => {
+                            primary.axfr_queries.fetch_add(1, AtomicOrdering::SeqCst);
+                            let (head, tail) = body.split_at(1);
+                            vec![std::iter::once(soa.clone()).chain(head.iter().cloned()).collect(), tail.iter().cloned().chain(std::iter::once(soa)).collect()]
+                        }
+                        _ => Vec::new(),
+                    };
+                    for answers in messages {
+                        let mut resp = query.reply();
+                        resp.header.flags.aa = true;
+                        resp.answers = answers;
+                        let wire = resp.to_wire()?;
+                        stream.write_all(&(wire.len() as u16).to_be_bytes()).await?;
+                        stream.write_all(&wire).await?;
+                    }
+                    anyhow::Ok(())
+                });
+            }
+        }
+    }
+
+    fn request(name: &str, qtype: QType, opcode: u8, from: [u8; 4]) -> DnsMessage {
+        let mut msg = DnsMessage { header: DnsHeader { id: 7, ..Default::default() }, ..Default::default() };
+        msg.header.flags.opcode = opcode;
+        msg.questions.push(DnsQuestion { name: name.to_string(), qtype, qclass: QClass::IN });
+        msg.client_addr = Some(SocketAddr::new(IpAddr::from(from), 5300));
+        msg.protocol = "udp".to_string();
+        msg
+    }
+
+    async fn ask(plugin: &SecondaryPlugin, msg: DnsMessage) -> DnsMessage {
+        let mut msg = msg;
+        plugin.process(&mut msg).await.unwrap();
+        DnsMessage::from_wire(msg.raw_response.as_ref().unwrap()).unwrap()
+    }
+
+    /// 轮询直到条件成立，最多等 5 秒
+    async fn wait_for(what: &str, cond: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if cond() { return; }
+            sleep(Duration::from_millis(50)).await;
+        }
+        panic!("timed out waiting for {}", what);
+    }
+
+    #[tokio::test]
+    async fn transfers_from_primary_and_refreshes_on_notify() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let primary = Arc::new(Primary { serial: AtomicU32::new(1), ..Default::default() });
+        tokio::spawn(primary.clone().serve(listener));
+
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        let config = PluginConfig {
+            name: "secondary".to_string(),
+            args: vec!["example.com".to_string()],
+            block: vec![PluginConfig { name: "transfer".to_string(), args: vec!["from".to_string(), addr.to_string()], block: Vec::new(), zone: String::new() }],
+            zone: "dns://example.com:53".to_string(),
+        };
+        let plugin = SecondaryPlugin::from_config(&config, shared.clone()).unwrap();
+        let handle = shared.zone(&config.zone, "example.com").unwrap();
+
+        // 首次同步：两个报文的 AXFR 合并成完整的区
+        wait_for("initial transfer", || handle.get().is_some()).await;
+        let zone = handle.get().unwrap();
+        assert_eq!((zone.serial(), zone.len()), (1, 4));
+        assert_eq!((primary.soa_queries.load(AtomicOrdering::SeqCst), primary.axfr_queries.load(AtomicOrdering::SeqCst)), (1, 1));
+        let resp = ask(&plugin, request("www.example.com.", QType::A, 0, [127, 0, 0, 1])).await;
+        assert!(resp.header.flags.aa);
+        assert_eq!(resp.answers.iter().map(|rr| &rr.data).collect::<Vec<_>>(), vec![&Record::A { addr: [192, 0, 2, 1].into() }]);
+
+        // 主服务器的 NOTIFY 立即触发检查；SERIAL 未变时不做 AXFR
+        let resp = ask(&plugin, request("example.com.", QType::SOA, OPCODE_NOTIFY, [127, 0, 0, 1])).await;
+        assert_eq!((resp.header.flags.rcode, resp.header.flags.aa), (0, true));
+        wait_for("SOA check after NOTIFY", || primary.soa_queries.load(AtomicOrdering::SeqCst) == 2).await;
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(primary.axfr_queries.load(AtomicOrdering::SeqCst), 1);
+
+        // SERIAL 变大后再次 NOTIFY，拉取新区
+        primary.serial.store(2, AtomicOrdering::SeqCst);
+        ask(&plugin, request("example.com.", QType::SOA, OPCODE_NOTIFY, [127, 0, 0, 1])).await;
+        wait_for("transfer of serial 2", || handle.get().is_some_and(|zone| zone.serial() == 2)).await;
+        assert_eq!(primary.axfr_queries.load(AtomicOrdering::SeqCst), 2);
+        let resp = ask(&plugin, request("www.example.com.", QType::A, 0, [127, 0, 0, 1])).await;
+        assert_eq!(resp.answers.iter().map(|rr| &rr.data).collect::<Vec<_>>(), vec![&Record::A { addr: [192, 0, 2, 2].into() }]);
+
+        // 其他地址发来的 NOTIFY 被拒绝，也不触发检查
+        let resp = ask(&plugin, request("example.com.", QType::SOA, OPCODE_NOTIFY, [192, 0, 2, 99])).await;
+        assert_eq!(resp.header.flags.rcode, 5);
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(primary.soa_queries.load(AtomicOrdering::SeqCst), 3);
+    }
+}
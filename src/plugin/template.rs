@@ -0,0 +1,287 @@
+use crate::plugin::{Plugin, SharedState};
+use crate::config::PluginConfig;
+use crate::types::{parse_rcode, DnsMessage, QClass, QType, ResourceRecord};
+use crate::zone::{fqdn, in_zone, normalize};
+use crate::plugin::prometheus::{TEMPLATE_FAILURES_TOTAL, TEMPLATE_MATCHES_TOTAL};
+use anyhow::{bail, Context, Result};
+use regex::{Captures, Regex};
+use std::sync::Arc;
+
+/// 模板中没有写 TTL 的记录使用的 TTL
+const DEFAULT_TTL: u32 = 3600;
+
+/// 模板中的一段：原样文本或 `{{ ... }}` 表达式
+enum Segment {
+    Text(String),
+    Name,
+    Type,
+    Class,
+    Zone,
+    Remote,
+    /// `{{ .Group.NAME }}` 或 `{{ index .Group "NAME" }}`
+    Group(String),
+    /// `{{ index .Match N }}`，0 为整个匹配
+    Match(usize),
+}
+
+/// 一条 answer / additional / authority 模板
+struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+/// 渲染模板时可用的查询信息
+struct RenderContext<'a> {
+    name: String,
+    qtype: &'a str,
+    qclass: &'a str,
+    zone: String,
+    remote: String,
+    captures: &'a Captures<'a>,
+}
+
+pub struct TemplatePlugin {
+    // None 表示 ANY
+    class: Option<QClass>,
+    qtype: Option<QType>,
+    zones: Vec<String>,
+    matches: Vec<Regex>,
+    answers: Vec<Template>,
+    additional: Vec<Template>,
+    authority: Vec<Template>,
+    rcode: u8,
+    // None 表示不下沉；Some(空) 表示对全部 zone 下沉
+    fallthrough: Option<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for TemplatePlugin {
+    fn name(&self) -> &str { "template" }
+
+    fn from_config(config: &PluginConfig, _shared: Arc<SharedState>) -> Result<Self> {
+        // template CLASS TYPE [ZONES...]
+        let [class, qtype, zones @ ..] = config.args.as_slice() else { bail!("template requires CLASS and TYPE"); };
+        let class = match class.to_ascii_uppercase().as_str() {
+            "ANY" => None,
+            other => Some(other.parse()?),
+        };
+        let qtype = match qtype.to_ascii_uppercase().as_str() {
+            "ANY" => None,
+            other => Some(other.parse()?),
+        };
+        let mut zones: Vec<String> = zones.iter().map(|z| normalize(z)).collect();
+        if zones.is_empty() { zones.push(config.origin()); }
+
+        let mut plugin = Self {
+            class, qtype, zones,
+            matches: Vec::new(), answers: Vec::new(), additional: Vec::new(), authority: Vec::new(),
+            rcode: 0, fallthrough: None,
+        };
+        for sub in &config.block {
+            match sub.name.as_str() {
+                "match" => {
+                    for pattern in &sub.args {
+                        plugin.matches.push(Regex::new(&format!("(?i){}", pattern)).with_context(|| format!("invalid regex {}", pattern))?);
+                    }
+                }
+                "answer" => plugin.answers.extend(sub.args.iter().map(|t| Template::parse(t)).collect::<Result<Vec<_>>>()?),
+                "additional" => plugin.additional.extend(sub.args.iter().map(|t| Template::parse(t)).collect::<Result<Vec<_>>>()?),
+                "authority" => plugin.authority.extend(sub.args.iter().map(|t| Template::parse(t)).collect::<Result<Vec<_>>>()?),
+                "rcode" => {
+                    let Some(rcode) = sub.args.first() else { bail!("rcode requires a value"); };
+                    plugin.rcode = parse_rcode(rcode).with_context(|| format!("unknown rcode {}", rcode))?;
+                }
+                "fallthrough" => plugin.fallthrough = Some(sub.args.iter().map(|z| normalize(z)).collect()),
+                other => bail!("unknown template option {}", other),
+            }
+        }
+        // 没有 match 时匹配区内的任何名字
+        if plugin.matches.is_empty() { plugin.matches.push(Regex::new(".*")?); }
+
+        tracing::info!(
+            "[template] Loaded {} {} template for zones {:?} ({} answer(s), RCODE {})",
+            config.args[0], config.args[1], plugin.zones, plugin.answers.len(), plugin.rcode
+        );
+        Ok(plugin)
+    }
+
+    async fn process(&self, msg: &mut DnsMessage) -> Result<DnsMessage> {
+        if msg.halt_chain { return Ok(msg.clone()); }
+        let Some(question) = msg.question().cloned() else { return Ok(msg.clone()); };
+        if self.class.is_some_and(|c| c != question.qclass) || self.qtype.is_some_and(|t| t != question.qtype) { return Ok(msg.clone()); }
+
+        let qname = question.name.trim_end_matches('.').to_ascii_lowercase();
+        let Some(zone) = self.zones.iter()
+            .filter(|z| in_zone(&qname, z))
+            .max_by_key(|z| if *z == "." { 0 } else { z.len() + 1 })
+        else { return Ok(msg.clone()); };
+
+        // 与 CoreDNS 一致，正则匹配带尾点的完整域名
+        let name = fqdn(&question.name);
+        let server_label = format!("dns://:{}", msg.server_port.unwrap_or(53));
+        let (class_label, type_label) = (class_str(question.qclass), question.qtype.as_str());
+        let mut resp = msg.reply();
+        resp.header.flags.aa = true;
+
+        match self.matches.iter().find_map(|re| re.captures(&name)) {
+            Some(captures) => {
+                TEMPLATE_MATCHES_TOTAL.with_label_values(&[&server_label, zone, &class_label, type_label]).inc();
+                let ctx = RenderContext {
+                    name: name.clone(),
+                    qtype: type_label,
+                    qclass: &class_label,
+                    zone: fqdn(zone),
+                    remote: msg.client_addr.map(|a| a.ip().to_string()).unwrap_or_default(),
+                    captures: &captures,
+                };
+                let mut failed = false;
+                let mut render = |templates: &[Template], section: &str| -> Vec<ResourceRecord> {
+                    templates.iter().filter_map(|template| {
+                        let text = template.render(&ctx);
+                        match crate::zone::parse_record(&text, zone, DEFAULT_TTL) {
+                            Ok(rr) => Some(rr),
+                            Err(e) => {
+                                tracing::warn!("[template] Failed to parse rendered {} record '{}' (from '{}'): {:#}", section, text, template.source, e);
+                                TEMPLATE_FAILURES_TOTAL.with_label_values(&[&server_label, zone, &class_label, type_label, section]).inc();
+                                failed = true;
+                                None
+                            }
+                        }
+                    }).collect()
+                };
+                let answers = render(&self.answers, "answer");
+                let additional = render(&self.additional, "additional");
+                let authority = render(&self.authority, "authority");
+                // 与 CoreDNS 一致：任何一条渲染结果无法解析时应答 SERVFAIL，不返回残缺的记录集
+                if failed {
+                    resp.header.flags.rcode = 2;
+                } else {
+                    resp.header.flags.rcode = self.rcode;
+                    (resp.answers, resp.additional, resp.authority) = (answers, additional, authority);
+                }
+            }
+            None => {
+                // 区内但没有模板命中：按 fallthrough 交给后续插件，否则 NXDOMAIN
+                let falls = self.fallthrough.as_ref().is_some_and(|zones| zones.is_empty() || zones.iter().any(|z| in_zone(&qname, z)));
+                if falls { return Ok(msg.clone()); }
+                resp.header.flags.rcode = 3;
+            }
+        }
+
+        tracing::debug!(
+            "TxID: {:#06x} -> [template] '{}' answered with RCODE {} ({} answers)",
+            msg.header.id, question.name, resp.header.flags.rcode, resp.answers.len()
+        );
+        msg.raw_response = Some(resp.to_wire()?);
+        msg.answered_by = "template".to_string();
+        msg.halt_chain = true;
+        Ok(msg.clone())
+    }
+
+    fn priority(&self) -> u8 { 112 }
+}
+
+impl Template {
+    /// 支持 `.Name` `.Type` `.Class` `.Zone` `.Remote` `.Group.NAME` `index .Group "NAME"` `index .Match N`
+    fn parse(source: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 { segments.push(Segment::Text(rest[..start].to_string())); }
+            let Some(len) = rest[start + 2..].find("}}") else { bail!("unclosed '{{{{' in template '{}'", source); };
+            let expr = rest[start + 2..start + 2 + len].trim();
+            let words: Vec<&str> = expr.split_whitespace().collect();
+            let segment = match words.as_slice() {
+                [".Name"] => Segment::Name,
+                [".Type"] => Segment::Type,
+                [".Class"] => Segment::Class,
+                [".Zone"] => Segment::Zone,
+                [".Remote"] => Segment::Remote,
+                [group] if group.starts_with(".Group.") => Segment::Group(group[".Group.".len()..].to_string()),
+                ["index", ".Group", key] => Segment::Group(key.trim_matches('"').to_string()),
+                ["index", ".Match", n] => Segment::Match(n.parse().with_context(|| format!("invalid match index {}", n))?),
+                _ => bail!("unsupported template expression '{{{{ {} }}}}' in '{}'", expr, source),
+            };
+            segments.push(segment);
+            rest = &rest[start + 2 + len + 2..];
+        }
+        if !rest.is_empty() { segments.push(Segment::Text(rest.to_string())); }
+        Ok(Self { source: source.to_string(), segments })
+    }
+
+    fn render(&self, ctx: &RenderContext) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Name => out.push_str(&ctx.name),
+                Segment::Type => out.push_str(ctx.qtype),
+                Segment::Class => out.push_str(ctx.qclass),
+                Segment::Zone => out.push_str(&ctx.zone),
+                Segment::Remote => out.push_str(&ctx.remote),
+                Segment::Group(key) => {
+                    let group = match key.parse::<usize>() {
+                        Ok(i) => ctx.captures.get(i),
+                        Err(_) => ctx.captures.name(key),
+                    };
+                    out.push_str(group.map(|m| m.as_str()).unwrap_or_default());
+                }
+                Segment::Match(i) => out.push_str(ctx.captures.get(*i).map(|m| m.as_str()).unwrap_or_default()),
+            }
+        }
+        out
+    }
+}
+
+fn class_str(class: QClass) -> String {
+    match class {
+        QClass::IN => "IN".to_string(), QClass::CH => "CH".to_string(), QClass::HS => "HS".to_string(),
+        QClass::NONE => "NONE".to_string(), QClass::ANY => "ANY".to_string(), QClass::Other(v) => format!("CLASS{}", v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{DnsQuestion, Record};
+
+    fn template(args: &[&str], block: &[(&str, &[&str])]) -> Result<TemplatePlugin> {
+        let sub = |(name, args): &(&str, &[&str])| PluginConfig { name: name.to_string(), args: args.iter().map(|a| a.to_string()).collect(), block: Vec::new(), zone: String::new() };
+        let config = PluginConfig {
+            name: "template".to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            block: block.iter().map(sub).collect(),
+            zone: "dns://example.com:53".to_string(),
+        };
+        TemplatePlugin::from_config(&config, Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new())))
+    }
+
+    async fn ask(plugin: &TemplatePlugin, name: &str, qtype: QType) -> DnsMessage {
+        let mut msg = DnsMessage::default();
+        msg.questions.push(DnsQuestion { name: name.to_string(), qtype, qclass: QClass::IN });
+        plugin.process(&mut msg).await.unwrap();
+        DnsMessage::from_wire(msg.raw_response.as_ref().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn renders_records_for_any_type() {
+        let plugin = template(&["IN", "DS"], &[("answer", &["{{ .Name }} 60 IN {{ .Type }} \\# 4 01020304"])]).unwrap();
+        let resp = ask(&plugin, "sub.example.com.", QType::Other(43)).await;
+        assert_eq!(resp.header.flags.rcode, 0);
+        assert_eq!(resp.answers.iter().map(|rr| (rr.name.as_str(), &rr.data)).collect::<Vec<_>>(), vec![("sub.example.com", &Record::Unknown { rtype: 43, data: vec![1, 2, 3, 4] })]);
+        assert!(template(&["IN", "NOSUCH"], &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn unparsable_render_is_servfail() {
+        let plugin = template(
+            &["IN", "A"],
+            &[("match", &["^(?P<ip>[0-9.]+)\\.example\\.com\\.$"]), ("answer", &["{{ .Name }} 60 IN A 192.0.2.1", "{{ .Name }} 60 IN A {{ .Group.ip }}"])],
+        ).unwrap();
+        let resp = ask(&plugin, "192.0.2.7.example.com.", QType::A).await;
+        assert_eq!((resp.header.flags.rcode, resp.answers.len()), (0, 2));
+        // 第二条渲染出 "192.0.2.300"，整个应答为 SERVFAIL 且不带任何记录
+        let resp = ask(&plugin, "192.0.2.300.example.com.", QType::A).await;
+        assert_eq!((resp.header.flags.rcode, resp.answers.len()), (2, 0));
+    }
+}
@@ -0,0 +1,352 @@
+use crate::plugin::{Plugin, SharedState};
+use crate::config::PluginConfig;
+use crate::types::{DnsMessage, DnsQuestion, QClass, QType, ResourceRecord};
+use crate::zone::{in_zone, normalize, parse_target, serial_cmp, soa_serial, Zone};
+use anyhow::Result;
+use std::cmp::Ordering;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout, Duration};
+
+/// 单个 AXFR / IXFR 报文的目标大小，远低于 TCP 报文 65535 字节的上限
+const XFR_MESSAGE_SIZE: usize = 16 * 1024;
+/// NOTIFY 等待确认的时间与重试次数 (RFC 1996 3.6)
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+const NOTIFY_ATTEMPTS: usize = 3;
+const OPCODE_NOTIFY: u8 = 4;
+
+/// `transfer [ZONES...] { to ADDRESS... }` 中的一条规则
+#[derive(Clone, Debug)]
+pub struct TransferRule {
+    pub zones: Vec<String>,
+    /// `to *`：任何地址都可以传送 (不会收到 NOTIFY)
+    pub any: bool,
+    /// 允许传送并在区更新后收到 NOTIFY 的从服务器
+    pub to: Vec<SocketAddr>,
+}
+
+impl TransferRule {
+    fn covers(&self, origin: &str) -> bool {
+        self.zones.iter().any(|z| in_zone(origin, z))
+    }
+
+    fn allows(&self, ip: IpAddr) -> bool {
+        self.any || self.to.iter().any(|t| t.ip().to_canonical() == ip.to_canonical())
+    }
+}
+
+pub struct TransferPlugin {
+    rule: TransferRule,
+    block: String,
+    shared: Arc<SharedState>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for TransferPlugin {
+    fn name(&self) -> &str { "transfer" }
+
+    fn from_config(config: &PluginConfig, shared: Arc<SharedState>) -> Result<Self> {
+        let mut zones: Vec<String> = config.args.iter().map(|z| normalize(z)).collect();
+        if zones.is_empty() { zones.push(config.origin()); }
+
+        let mut rule = TransferRule { zones, any: false, to: Vec::new() };
+        for sub in config.block.iter().filter(|sub| sub.name == "to") {
+            for addr in &sub.args {
+                match addr.as_str() {
+                    "*" => rule.any = true,
+                    _ => match parse_target(addr) {
+                        Some(target) => rule.to.push(target),
+                        None => tracing::error!("[transfer] Invalid address in 'to': {}", addr),
+                    },
+                }
+            }
+        }
+        if !rule.any && rule.to.is_empty() { anyhow::bail!("transfer requires at least one 'to' address"); }
+
+        tracing::info!("[transfer] Allowing transfers of {:?} to {}", rule.zones, if rule.any { "any address".to_string() } else { format!("{:?}", rule.to) });
+        if let Ok(mut transfers) = shared.transfers.write() {
+            transfers.entry(config.zone.clone()).or_default().push(rule.clone());
+        }
+        Ok(Self { rule, block: config.zone.clone(), shared })
+    }
+
+    async fn process(&self, msg: &mut DnsMessage) -> Result<DnsMessage> {
+        if msg.halt_chain { return Ok(msg.clone()); }
+        let Some(question) = msg.question().cloned() else { return Ok(msg.clone()); };
+        if !matches!(question.qtype, QType::AXFR | QType::IXFR) { return Ok(msg.clone()); }
+        let origin = normalize(&question.name);
+        if !self.rule.covers(&origin) { return Ok(msg.clone()); }
+
+        let client = msg.client_addr.map(|a| a.ip());
+        let handle = self.shared.zone(&self.block, &origin);
+        let zone = handle.as_ref().and_then(|h| h.get());
+        let allowed = client.is_some_and(|ip| self.rule.allows(ip));
+        // RFC 5936 / RFC 9250: AXFR 只走流式传输 (TCP、DoT、DoQ)
+        let streaming = matches!(msg.protocol.as_str(), "tcp" | "tls" | "quic");
+        let rcode = if !allowed || (question.qtype == QType::AXFR && !streaming) { 5 }
+            else if handle.is_none() { 9 }
+            else if zone.is_none() { 2 }
+            else { 0 };
+        let Some(zone) = zone.filter(|_| rcode == 0) else {
+            tracing::warn!("[transfer] Refused {} of {} to {} (RCODE {})", question.qtype.as_str(), origin, client.map(|ip| ip.to_string()).unwrap_or_default(), rcode);
+            return Ok(self.reply_error(msg, rcode));
+        };
+
+        let records = match question.qtype {
+            QType::IXFR => {
+                let Some(client_serial) = msg.authority.iter().find(|r| r.data.rtype() == QType::SOA).map(soa_serial) else {
+                    return Ok(self.reply_error(msg, 1));
+                };
+                self.ixfr_records(msg, &zone, client_serial)
+            }
+            _ => axfr_records(&zone),
+        };
+        tracing::info!(
+            "[transfer] Sending {} of {} (serial {}, {} records) to {}",
+            question.qtype.as_str(), zone.origin, zone.serial(), records.len(), client.map(|ip| ip.to_string()).unwrap_or_default()
+        );
+        let mut messages = pack(msg, records)?;
+
+        msg.raw_response = Some(messages.remove(0));
+        msg.raw_continuation = messages;
+        msg.answered_by = "transfer".to_string();
+        msg.halt_chain = true;
+        Ok(msg.clone())
+    }
+
+    fn priority(&self) -> u8 { 109 }
+}
+
+impl TransferPlugin {
+    fn reply_error(&self, msg: &mut DnsMessage, rcode: u8) -> DnsMessage {
+        let mut resp = msg.reply();
+        resp.header.flags.ra = false;
+        resp.header.flags.rcode = rcode;
+        msg.raw_response = resp.to_wire().ok();
+        msg.answered_by = "transfer".to_string();
+        msg.halt_chain = true;
+        msg.clone()
+    }
+
+    /// RFC 1995：已是最新或经 UDP 查询时只回当前 SOA；历史足够时发送增量，否则退回完整区
+    fn ixfr_records(&self, msg: &DnsMessage, zone: &Zone, client_serial: u32) -> Vec<ResourceRecord> {
+        let soa = zone.soa().clone();
+        if matches!(serial_cmp(client_serial, zone.serial()), Some(Ordering::Equal | Ordering::Greater)) || msg.protocol == "udp" { return vec![soa]; }
+
+        let changes = self.shared.zone(&self.block, &zone.origin).and_then(|h| h.changes_since(client_serial));
+        match changes {
+            Some(changes) if changes.last().is_some_and(|d| soa_serial(&d.to_soa) == zone.serial()) => {
+                let mut records = vec![soa.clone()];
+                for delta in changes {
+                    records.push(delta.from_soa.clone());
+                    records.extend(delta.removed.iter().cloned());
+                    records.push(delta.to_soa.clone());
+                    records.extend(delta.added.iter().cloned());
+                }
+                records.push(soa);
+                records
+            }
+            _ => axfr_records(zone),
+        }
+    }
+}
+
+/// SOA、其余全部记录、SOA (RFC 5936 2.2)
+fn axfr_records(zone: &Zone) -> Vec<ResourceRecord> {
+    let soa = zone.soa().clone();
+    let mut records = vec![soa.clone()];
+    records.extend(zone.records().filter(|r| r.data.rtype() != QType::SOA).cloned());
+    records.push(soa);
+    records
+}
+
+/// 把记录装入若干应答报文，问题段只出现在第一个报文中
+fn pack(msg: &DnsMessage, records: Vec<ResourceRecord>) -> Result<Vec<Vec<u8>>> {
+    let new_message = |first: bool| {
+        let mut resp = msg.reply();
+        resp.header.flags.aa = true;
+        resp.header.flags.ra = false;
+        if !first { resp.questions.clear(); }
+        resp
+    };
+    let mut messages = Vec::new();
+    let mut current = new_message(true);
+    let mut size = 0;
+    for rr in records {
+        // 单独编码一次估算这条记录的大小 (不计压缩，偏保守)
+        let rr_size = DnsMessage { answers: vec![rr.clone()], ..Default::default() }.to_wire()?.len() - 12;
+        if !current.answers.is_empty() && size + rr_size > XFR_MESSAGE_SIZE {
+            messages.push(current.to_wire()?);
+            current = new_message(false);
+            size = 0;
+        }
+        size += rr_size;
+        current.answers.push(rr);
+    }
+    messages.push(current.to_wire()?);
+    Ok(messages)
+}
+
+/// 区重新加载后，按本 server block 的 transfer 规则向从服务器发送 NOTIFY (RFC 1996)
+pub fn notify(shared: &SharedState, block: &str, zone: &Zone) {
+    let mut targets: Vec<SocketAddr> = shared.transfers.read()
+        .map(|rules| rules.get(block).into_iter().flatten().filter(|r| r.covers(&zone.origin)).flat_map(|r| r.to.clone()).collect())
+        .unwrap_or_default();
+    targets.sort();
+    targets.dedup();
+
+    for target in targets {
+        let mut notify = DnsMessage { header: crate::types::DnsHeader { id: rand::random(), ..Default::default() }, ..Default::default() };
+        notify.header.flags.opcode = OPCODE_NOTIFY;
+        notify.header.flags.aa = true;
+        notify.questions.push(DnsQuestion { name: zone.soa().name.clone(), qtype: QType::SOA, qclass: QClass::IN });
+        notify.answers.push(zone.soa().clone());
+        let Ok(query) = notify.to_wire() else { continue };
+        let origin = zone.origin.clone();
+        tokio::spawn(async move { send_notify(target, &origin, &query).await });
+    }
+}
+
+async fn send_notify(target: SocketAddr, origin: &str, query: &[u8]) {
+    let bind = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let Ok(socket) = UdpSocket::bind(bind).await else { return };
+    for _ in 0..NOTIFY_ATTEMPTS {
+        if socket.send_to(query, target).await.is_err() { break; }
+        let mut buf = [0u8; 512];
+        if let Ok(Ok((len, from))) = timeout(NOTIFY_TIMEOUT, socket.recv_from(&mut buf)).await {
+            // 确认报文：同一 ID、QR=1、OPCODE=NOTIFY
+            if from == target && len >= 12 && buf[..2] == query[..2] && buf[2] & 0x80 != 0 && (buf[2] >> 3) & 0x0F == OPCODE_NOTIFY {
+                tracing::info!("[transfer] NOTIFY for {} acknowledged by {}", origin, target);
+                return;
+            }
+        }
+    }
+    tracing::warn!("[transfer] No NOTIFY acknowledgement for {} from {}", origin, target);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Record;
+    use crate::zone::{parse_record, ZoneHandle};
+
+    const BLOCK: &str = "dns://example.com:53";
+
+    /// SERIAL 为 `serial` 的区；`hosts` 条 TXT 记录让 AXFR 超过单个报文的大小
+    fn zone(serial: u32, hosts: usize, www: &str) -> Zone {
+        let rr = |text: &str| parse_record(text, "example.com", 3600).unwrap();
+        let mut records = vec![rr(&format!("@ SOA ns1 hostmaster {} 3600 600 86400 300", serial)), rr("@ NS ns1"), rr("ns1 A 192.0.2.1"), rr(&format!("www A {}", www))];
+        records.extend((0..hosts).map(|i| rr(&format!("h{} TXT \"{}\"", i, "x".repeat(200)))));
+        Zone::from_records("example.com", records).unwrap()
+    }
+
+    fn setup(to: &[&str]) -> (Arc<SharedState>, Arc<ZoneHandle>, TransferPlugin) {
+        let shared = Arc::new(SharedState::new_with_cache(Arc::new(crate::plugin::cache::CacheStore::new()), String::new()));
+        let handle = Arc::new(ZoneHandle::new("example.com", Some(zone(1, 200, "192.0.2.10"))));
+        shared.register_zone(BLOCK, handle.clone());
+        let config = PluginConfig {
+            name: "transfer".to_string(),
+            args: Vec::new(),
+            block: vec![PluginConfig { name: "to".to_string(), args: to.iter().map(|a| a.to_string()).collect(), block: Vec::new(), zone: String::new() }],
+            zone: BLOCK.to_string(),
+        };
+        let plugin = TransferPlugin::from_config(&config, shared.clone()).unwrap();
+        (shared, handle, plugin)
+    }
+
+    /// 发出一个区传送请求，返回解码后的全部应答报文
+    async fn transfer(plugin: &TransferPlugin, qtype: QType, serial: Option<u32>, protocol: &str, from: [u8; 4]) -> Vec<DnsMessage> {
+        let mut msg = DnsMessage::default();
+        msg.questions.push(DnsQuestion { name: "example.com.".to_string(), qtype, qclass: QClass::IN });
+        if let Some(serial) = serial { msg.authority.push(parse_record(&format!("@ SOA ns1 hostmaster {} 1 1 1 1", serial), "example.com", 0).unwrap()); }
+        msg.client_addr = Some(SocketAddr::new(IpAddr::from(from), 40000));
+        msg.protocol = protocol.to_string();
+        plugin.process(&mut msg).await.unwrap();
+        std::iter::once(msg.raw_response.unwrap()).chain(msg.raw_continuation).map(|wire| DnsMessage::from_wire(&wire).unwrap()).collect()
+    }
+
+    fn serials(records: &[ResourceRecord]) -> Vec<Option<u32>> {
+        records.iter().map(|rr| (rr.data.rtype() == QType::SOA).then(|| soa_serial(rr))).collect()
+    }
+
+    #[tokio::test]
+    async fn axfr_spans_several_messages() {
+        let (_, handle, plugin) = setup(&["127.0.0.1"]);
+        let messages = transfer(&plugin, QType::AXFR, None, "tcp", [127, 0, 0, 1]).await;
+        assert!(messages.len() > 1);
+        // 问题段只在第一个报文中
+        assert_eq!(messages.iter().map(|m| m.questions.len()).collect::<Vec<_>>(), std::iter::once(1).chain(std::iter::repeat(0)).take(messages.len()).collect::<Vec<_>>());
+        assert!(messages.iter().all(|m| m.header.flags.aa && m.header.flags.rcode == 0));
+
+        // 首尾各一条 SOA，其余记录重新装成同一个区
+        let mut records: Vec<ResourceRecord> = messages.into_iter().flat_map(|m| m.answers).collect();
+        assert_eq!((serials(&records).first(), serials(&records).last()), (Some(&Some(1)), Some(&Some(1))));
+        records.pop();
+        let received = Zone::from_records("example.com", records).unwrap();
+        assert_eq!(received.len(), handle.get().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn ixfr_uses_journal_and_falls_back_to_axfr() {
+        let (_, handle, plugin) = setup(&["127.0.0.1"]);
+        assert!(handle.update(zone(2, 200, "192.0.2.20")));
+
+        // 增量：SOA(2)、SOA(1)、删除的记录、SOA(2)、新增的记录、SOA(2)
+        let messages = transfer(&plugin, QType::IXFR, Some(1), "tcp", [127, 0, 0, 1]).await;
+        let records: Vec<ResourceRecord> = messages.into_iter().flat_map(|m| m.answers).collect();
+        assert_eq!(serials(&records), vec![Some(2), Some(1), None, Some(2), None, Some(2)]);
+        assert_eq!(records[2].data, Record::A { addr: [192, 0, 2, 10].into() });
+        assert_eq!(records[4].data, Record::A { addr: [192, 0, 2, 20].into() });
+
+        // 已是最新，或经 UDP 查询：只回当前 SOA
+        for (serial, protocol) in [(2, "tcp"), (1, "udp")] {
+            let messages = transfer(&plugin, QType::IXFR, Some(serial), protocol, [127, 0, 0, 1]).await;
+            assert_eq!(serials(&messages[0].answers), vec![Some(2)], "{} {}", serial, protocol);
+        }
+
+        // 日志中找不到客户端的序列号时退回完整区
+        let messages = transfer(&plugin, QType::IXFR, Some(0), "tcp", [127, 0, 0, 1]).await;
+        let records: Vec<ResourceRecord> = messages.into_iter().flat_map(|m| m.answers).collect();
+        assert_eq!(records.len(), handle.get().unwrap().len() + 1);
+        assert_eq!(serials(&records)[..2], [Some(2), None]);
+
+        // IXFR 缺少客户端的 SOA 时为 FORMERR
+        assert_eq!(transfer(&plugin, QType::IXFR, None, "tcp", [127, 0, 0, 1]).await[0].header.flags.rcode, 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_unlisted_clients_and_axfr_over_udp() {
+        let (_, _, plugin) = setup(&["127.0.0.1"]);
+        for (qtype, protocol, from) in [(QType::AXFR, "tcp", [192, 0, 2, 99]), (QType::IXFR, "tcp", [192, 0, 2, 99]), (QType::AXFR, "udp", [127, 0, 0, 1])] {
+            let messages = transfer(&plugin, qtype, Some(1), protocol, from).await;
+            assert_eq!((messages.len(), messages[0].header.flags.rcode), (1, 5), "{:?} {} {:?}", qtype, protocol, from);
+            assert!(messages[0].answers.is_empty());
+        }
+        // AXFR 也可以走 DoT / DoQ
+        for protocol in ["tls", "quic"] {
+            assert_eq!(transfer(&plugin, QType::AXFR, None, protocol, [127, 0, 0, 1]).await[0].header.flags.rcode, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn notifies_listed_secondaries() {
+        let secondary = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = secondary.local_addr().unwrap().to_string();
+        let (shared, handle, _plugin) = setup(&[target.as_str()]);
+
+        notify(&shared, BLOCK, &handle.get().unwrap());
+        let mut buf = [0u8; 512];
+        let (len, from) = timeout(Duration::from_secs(2), secondary.recv_from(&mut buf)).await.unwrap().unwrap();
+        let query = DnsMessage::from_wire(&buf[..len]).unwrap();
+        assert_eq!((query.header.flags.opcode, query.header.flags.aa), (OPCODE_NOTIFY, true));
+        assert_eq!((query.questions[0].name.as_str(), query.questions[0].qtype), ("example.com", QType::SOA));
+        assert_eq!(serials(&query.answers), vec![Some(1)]);
+
+        // 确认后不再重发
+        let mut ack = query.reply();
+        ack.answers.clear();
+        secondary.send_to(&ack.to_wire().unwrap(), from).await.unwrap();
+        assert!(timeout(NOTIFY_TIMEOUT + Duration::from_millis(500), secondary.recv_from(&mut buf)).await.is_err());
+    }
+}
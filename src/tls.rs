@@ -0,0 +1,155 @@
+//! TLS server configuration shared by the encrypted listeners (DoT / DoH / DoQ)
+
+use crate::config::PluginConfig;
+use anyhow::{bail, Result};
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio_rustls::rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::{self, CertifiedKey};
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+
+/// 握手时最多每隔这么久检查一次证书与私钥文件是否被替换
+const CERT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientAuth { NoCert, VerifyIfGiven, RequireAndVerify }
+
+/// `tls CERT KEY [CA] { client_auth ... }` directive of a server block
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    pub cert: String,
+    pub key: String,
+    pub ca: Option<String>,
+    pub client_auth: ClientAuth,
+}
+
+impl TlsSettings {
+    pub fn from_config(config: &PluginConfig) -> Result<Self> {
+        if config.args.len() < 2 { bail!("tls requires CERT and KEY arguments"); }
+        let ca = config.args.get(2).cloned();
+
+        // 指定了 CA 时默认强制校验客户端证书，与 CoreDNS 行为一致
+        let mut client_auth = if ca.is_some() { ClientAuth::RequireAndVerify } else { ClientAuth::NoCert };
+        for sub in &config.block {
+            if sub.name == "client_auth" {
+                client_auth = match sub.args.first().map(|s| s.as_str()) {
+                    Some("nocert") => ClientAuth::NoCert,
+                    // rustls 不支持"只要求证书但不校验"，request/require 退化为基于 CA 的校验
+                    Some("request") | Some("verify_if_given") => ClientAuth::VerifyIfGiven,
+                    Some("require") | Some("require_and_verify") => ClientAuth::RequireAndVerify,
+                    other => bail!("unknown client_auth mode {:?}", other),
+                };
+            }
+        }
+        if client_auth != ClientAuth::NoCert && ca.is_none() { bail!("client_auth requires a CA file"); }
+
+        Ok(Self { cert: config.args[0].clone(), key: config.args[1].clone(), ca, client_auth })
+    }
+
+    /// Files whose changes should trigger a reload (the CA; certificate and key are also swapped in without one)
+    pub fn files(&self) -> Vec<String> {
+        let mut files = vec![self.cert.clone(), self.key.clone()];
+        files.extend(self.ca.clone());
+        files
+    }
+}
+
+/// Load certificates from disk and build a rustls server config advertising the given ALPN protocols
+pub fn server_config(settings: &TlsSettings, alpn: &[&[u8]]) -> Result<Arc<ServerConfig>> {
+    let resolver = Arc::new(ReloadingCert::new(&settings.cert, &settings.key)?);
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let mut config = match (&settings.ca, settings.client_auth) {
+        (Some(ca), mode) if mode != ClientAuth::NoCert => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca)? { roots.add(&cert)?; }
+            let verifier = if mode == ClientAuth::RequireAndVerify {
+                AllowAnyAuthenticatedClient::new(roots).boxed()
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+            };
+            builder.with_client_cert_verifier(verifier).with_cert_resolver(resolver)
+        }
+        _ => builder.with_no_client_auth().with_cert_resolver(resolver),
+    };
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+    Ok(Arc::new(config))
+}
+
+/// Certificate served on every handshake; replaced in place when the certificate or key file changes,
+/// so a rotated certificate is used for new connections without a reload
+struct ReloadingCert {
+    cert: String,
+    key: String,
+    state: RwLock<CertState>,
+}
+
+struct CertState {
+    key: Arc<CertifiedKey>,
+    modified: (Option<SystemTime>, Option<SystemTime>),
+    checked: Instant,
+}
+
+impl ReloadingCert {
+    fn new(cert: &str, key: &str) -> Result<Self> {
+        let modified = (mtime(cert), mtime(key));
+        let state = CertState { key: load_certified_key(cert, key)?, modified, checked: Instant::now() };
+        Ok(Self { cert: cert.to_string(), key: key.to_string(), state: RwLock::new(state) })
+    }
+
+    fn refresh(&self) {
+        if self.state.read().map_or(true, |s| s.checked.elapsed() < CERT_CHECK_INTERVAL) { return; }
+        let Ok(mut state) = self.state.write() else { return };
+        if state.checked.elapsed() < CERT_CHECK_INTERVAL { return; }
+        state.checked = Instant::now();
+
+        let modified = (mtime(&self.cert), mtime(&self.key));
+        if modified == state.modified { return; }
+        match load_certified_key(&self.cert, &self.key) {
+            Ok(key) => {
+                tracing::info!("🔐 Certificate {} changed, using the new certificate for new connections", self.cert);
+                state.key = key;
+                state.modified = modified;
+            }
+            // 证书与私钥可能还没写完整，保留旧证书，下次检查时重试
+            Err(e) => tracing::warn!("Failed to reload certificate {}: {}; keeping the current one", self.cert, e),
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.refresh();
+        self.state.read().ok().map(|s| s.key.clone())
+    }
+}
+
+fn mtime(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn load_certified_key(cert: &str, key: &str) -> Result<Arc<CertifiedKey>> {
+    let certs = load_certs(cert)?;
+    let key = sign::any_supported_type(&load_key(key)?).map_err(|e| anyhow::anyhow!("Unsupported private key '{}': {}", key, e))?;
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>> {
+    let file = std::fs::File::open(path).map_err(|e| anyhow::anyhow!("Failed to open certificate '{}': {}", path, e))?;
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(file))?.into_iter().map(Certificate).collect();
+    if certs.is_empty() { bail!("no certificates found in '{}'", path); }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> Result<PrivateKey> {
+    let file = std::fs::File::open(path).map_err(|e| anyhow::anyhow!("Failed to open private key '{}': {}", path, e))?;
+    let mut reader = BufReader::new(file);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(k) | rustls_pemfile::Item::RSAKey(k) | rustls_pemfile::Item::ECKey(k) => return Ok(PrivateKey(k)),
+            _ => {}
+        }
+    }
+    bail!("no private key found in '{}'", path)
+}
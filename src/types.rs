@@ -1,25 +1,143 @@
 //! Basic types for CoreDNS
 
+use anyhow::Result;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum QType { A, AAAA, MX, TXT, CNAME, NS, SOA, PTR, SRV, ANY }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QType { A, AAAA, MX, TXT, CNAME, NS, SOA, PTR, SRV, OPT, AXFR, IXFR, ANY, Other(u16) }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum QClass { IN, CH, HS }
+impl QType {
+    pub fn from_u16(v: u16) -> Self {
+        match v {
+            1 => QType::A, 28 => QType::AAAA, 15 => QType::MX, 16 => QType::TXT, 5 => QType::CNAME,
+            2 => QType::NS, 6 => QType::SOA, 12 => QType::PTR, 33 => QType::SRV, 41 => QType::OPT,
+            252 => QType::AXFR, 251 => QType::IXFR, 255 => QType::ANY, other => QType::Other(other),
+        }
+    }
 
-#[derive(Debug, Clone)]
+    pub fn to_u16(self) -> u16 {
+        match self {
+            QType::A => 1, QType::AAAA => 28, QType::MX => 15, QType::TXT => 16, QType::CNAME => 5,
+            QType::NS => 2, QType::SOA => 6, QType::PTR => 12, QType::SRV => 33, QType::OPT => 41,
+            QType::AXFR => 252, QType::IXFR => 251, QType::ANY => 255, QType::Other(v) => v,
+        }
+    }
+
+    /// 用于 prometheus 标签与日志输出的类型名；不在助记符表中的类型为 "OTHER"
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QType::A => "A", QType::AAAA => "AAAA", QType::MX => "MX", QType::TXT => "TXT", QType::CNAME => "CNAME",
+            QType::NS => "NS", QType::SOA => "SOA", QType::PTR => "PTR", QType::SRV => "SRV", QType::OPT => "OPT",
+            QType::AXFR => "AXFR", QType::IXFR => "IXFR", QType::ANY => "ANY",
+            QType::Other(v) => TYPE_NAMES.iter().find(|&&(_, t)| t == *v).map_or("OTHER", |&(name, _)| name),
+        }
+    }
+}
+
+/// 配置与区文件中可用的类型助记符
+const TYPE_NAMES: &[(&str, u16)] = &[
+    ("A", 1), ("NS", 2), ("CNAME", 5), ("SOA", 6), ("PTR", 12), ("HINFO", 13), ("MX", 15), ("TXT", 16),
+    ("RP", 17), ("AFSDB", 18), ("SIG", 24), ("KEY", 25), ("AAAA", 28), ("LOC", 29), ("SRV", 33), ("NAPTR", 35),
+    ("KX", 36), ("CERT", 37), ("DNAME", 39), ("OPT", 41), ("APL", 42), ("DS", 43), ("SSHFP", 44), ("IPSECKEY", 45),
+    ("RRSIG", 46), ("NSEC", 47), ("DNSKEY", 48), ("DHCID", 49), ("NSEC3", 50), ("NSEC3PARAM", 51), ("TLSA", 52),
+    ("SMIMEA", 53), ("HIP", 55), ("CDS", 59), ("CDNSKEY", 60), ("OPENPGPKEY", 61), ("CSYNC", 62), ("ZONEMD", 63),
+    ("SVCB", 64), ("HTTPS", 65), ("SPF", 99), ("TKEY", 249), ("TSIG", 250), ("IXFR", 251), ("AXFR", 252),
+    ("ANY", 255), ("URI", 256), ("CAA", 257),
+];
+
+impl std::str::FromStr for QType {
+    type Err = anyhow::Error;
+
+    /// 类型助记符 (不区分大小写) 或 RFC 3597 的 TYPEnnn
+    fn from_str(s: &str) -> Result<Self> {
+        let upper = s.to_ascii_uppercase();
+        let value = match upper.strip_prefix("TYPE") {
+            Some(num) => num.parse().ok(),
+            None => TYPE_NAMES.iter().find(|(name, _)| *name == upper).map(|&(_, v)| v),
+        };
+        value.map(QType::from_u16).ok_or_else(|| anyhow::anyhow!("unknown type {}", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QClass { IN, CH, HS, NONE, ANY, Other(u16) }
+
+impl QClass {
+    pub fn from_u16(v: u16) -> Self {
+        match v { 1 => QClass::IN, 3 => QClass::CH, 4 => QClass::HS, 254 => QClass::NONE, 255 => QClass::ANY, other => QClass::Other(other) }
+    }
+
+    pub fn to_u16(self) -> u16 {
+        match self { QClass::IN => 1, QClass::CH => 3, QClass::HS => 4, QClass::NONE => 254, QClass::ANY => 255, QClass::Other(v) => v }
+    }
+}
+
+impl std::str::FromStr for QClass {
+    type Err = anyhow::Error;
+
+    /// 类别助记符 (不区分大小写) 或 RFC 3597 的 CLASSnnn
+    fn from_str(s: &str) -> Result<Self> {
+        let upper = s.to_ascii_uppercase();
+        if let Some(num) = upper.strip_prefix("CLASS") {
+            return num.parse().map(QClass::from_u16).map_err(|_| anyhow::anyhow!("unknown class {}", s));
+        }
+        Ok(match upper.as_str() {
+            "IN" => QClass::IN, "CH" | "CHAOS" => QClass::CH, "HS" | "HESIOD" => QClass::HS, "NONE" => QClass::NONE, "ANY" => QClass::ANY,
+            _ => anyhow::bail!("unknown class {}", s),
+        })
+    }
+}
+
+/// 名字 (不区分大小写) 或数字形式的 RCODE，数字须小于 16
+pub fn parse_rcode(s: &str) -> Option<u8> {
+    if let Ok(code) = s.parse::<u8>() { return (code < 16).then_some(code); }
+    Some(match s.to_ascii_uppercase().as_str() {
+        "NOERROR" => 0, "FORMERR" => 1, "SERVFAIL" => 2, "NXDOMAIN" => 3, "NOTIMP" => 4, "REFUSED" => 5,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Record {
     A { addr: Ipv4Addr },
     AAAA { addr: std::net::Ipv6Addr },
-    TXT { text: Vec<String> },
+    /// 字符串按原始字节保存，不要求是 UTF-8
+    TXT { text: Vec<Vec<u8>> },
     CNAME { cname: String },
     MX { preference: u16, exchange: String },
     NS { nsdname: String },
     SOA { mname: String, rname: String, serial: u32, refresh: u32, retry: u32, expire: u32, minimum: u32 },
     PTR { ptrdname: String },
     SRV { priority: u16, weight: u16, port: u16, target: String },
+    /// 未内置解析的类型 (包括 OPT)，按 RFC 3597 原样保留 RDATA
+    Unknown { rtype: u16, data: Vec<u8> },
+}
+
+impl Record {
+    pub fn rtype(&self) -> QType {
+        match self {
+            Record::A { .. } => QType::A,
+            Record::AAAA { .. } => QType::AAAA,
+            Record::TXT { .. } => QType::TXT,
+            Record::CNAME { .. } => QType::CNAME,
+            Record::MX { .. } => QType::MX,
+            Record::NS { .. } => QType::NS,
+            Record::SOA { .. } => QType::SOA,
+            Record::PTR { .. } => QType::PTR,
+            Record::SRV { .. } => QType::SRV,
+            Record::Unknown { rtype, .. } => QType::from_u16(*rtype),
+        }
+    }
+}
+
+/// A resource record as it appears in the answer, authority or additional section
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceRecord {
+    pub name: String,
+    pub rclass: QClass,
+    pub ttl: u32,
+    pub data: Record,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -32,7 +150,76 @@ pub struct DnsHeader {
 #[derive(Debug, Clone, Copy, Default)]
 pub struct HeaderFlags {
     pub qr: bool, pub opcode: u8, pub aa: bool, pub tc: bool,
-    pub rd: bool, pub ra: bool, pub rcode: u8,
+    pub rd: bool, pub ra: bool, pub ad: bool, pub cd: bool, pub rcode: u8,
+}
+
+impl HeaderFlags {
+    pub fn from_u16(v: u16) -> Self {
+        Self {
+            qr: v & 0x8000 != 0, opcode: ((v >> 11) & 0x0F) as u8, aa: v & 0x0400 != 0, tc: v & 0x0200 != 0,
+            rd: v & 0x0100 != 0, ra: v & 0x0080 != 0, ad: v & 0x0020 != 0, cd: v & 0x0010 != 0, rcode: (v & 0x0F) as u8,
+        }
+    }
+
+    pub fn to_u16(self) -> u16 {
+        let mut v = ((self.opcode as u16 & 0x0F) << 11) | (self.rcode as u16 & 0x0F);
+        if self.qr { v |= 0x8000; }
+        if self.aa { v |= 0x0400; }
+        if self.tc { v |= 0x0200; }
+        if self.rd { v |= 0x0100; }
+        if self.ra { v |= 0x0080; }
+        if self.ad { v |= 0x0020; }
+        if self.cd { v |= 0x0010; }
+        v
+    }
+}
+
+/// EDNS0 OPT pseudo-record (RFC 6891), lifted out of the additional section when decoding
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edns {
+    pub udp_payload_size: u16,
+    /// 扩展 RCODE 的高 8 位，完整 RCODE = (extended_rcode << 4) | header.rcode
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<(u16, Vec<u8>)>,
+}
+
+impl Edns {
+    pub fn new(udp_payload_size: u16) -> Self {
+        Self { udp_payload_size, extended_rcode: 0, version: 0, dnssec_ok: false, options: Vec::new() }
+    }
+
+    pub fn to_record(&self) -> ResourceRecord {
+        let mut data = Vec::new();
+        for (code, value) in &self.options {
+            data.extend_from_slice(&code.to_be_bytes());
+            data.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            data.extend_from_slice(value);
+        }
+        let ttl = ((self.extended_rcode as u32) << 24) | ((self.version as u32) << 16) | if self.dnssec_ok { 0x8000 } else { 0 };
+        ResourceRecord { name: ".".to_string(), rclass: QClass::from_u16(self.udp_payload_size), ttl, data: Record::Unknown { rtype: 41, data } }
+    }
+
+    pub fn from_record(rr: &ResourceRecord) -> Option<Self> {
+        let Record::Unknown { rtype: 41, data } = &rr.data else { return None; };
+        let mut options = Vec::new();
+        let mut p = 0;
+        while p + 4 <= data.len() {
+            let code = u16::from_be_bytes([data[p], data[p + 1]]);
+            let len = u16::from_be_bytes([data[p + 2], data[p + 3]]) as usize;
+            let value = data.get(p + 4..p + 4 + len)?;
+            options.push((code, value.to_vec()));
+            p += 4 + len;
+        }
+        Some(Self {
+            udp_payload_size: rr.rclass.to_u16(),
+            extended_rcode: (rr.ttl >> 24) as u8,
+            version: (rr.ttl >> 16) as u8,
+            dnssec_ok: rr.ttl & 0x8000 != 0,
+            options,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -42,19 +229,105 @@ pub struct DnsQuestion { pub name: String, pub qtype: QType, pub qclass: QClass
 pub struct DnsMessage {
     pub header: DnsHeader,
     pub questions: Vec<DnsQuestion>,
-    pub answers: Vec<Record>,
-    pub authority: Vec<Record>,
-    pub additional: Vec<Record>,
-    
+    pub answers: Vec<ResourceRecord>,
+    pub authority: Vec<ResourceRecord>,
+    pub additional: Vec<ResourceRecord>,
+    pub edns: Option<Edns>,
+
     pub raw_query: Vec<u8>,
     pub raw_response: Option<Vec<u8>>,
+    /// 多报文应答 (AXFR / IXFR) 中跟在 raw_response 之后的报文，只在 TCP / DoT / DoQ 连接上发送
+    pub raw_continuation: Vec<Vec<u8>>,
     pub halt_chain: bool,
+    /// rewrite 插件对查询做过的改写，post_process 中按相反顺序还原
+    pub rewrites: Vec<RewriteStep>,
 
     pub client_addr: Option<SocketAddr>,
     pub protocol: String,
-    
+
     // --- 【监控上下文】 ---
     pub server_port: Option<u16>,
     pub start_time: Option<std::time::Instant>,
     pub answered_by: String, // 记录是哪个插件(如 "cache", "forward")响应的
-}
\ No newline at end of file
+    pub zone_index: Option<usize>, // 路由到的 server block (Config.zones 下标)
+}
+
+/// One rewrite applied to a query, with what it replaced so the response can be mapped back
+#[derive(Debug, Clone)]
+pub struct RewriteStep {
+    /// 做出改写的 rewrite 插件实例
+    pub rule: usize,
+    /// 之后的 rewrite 规则不再生效
+    pub stop: bool,
+    pub question: DnsQuestion,
+    pub edns: Option<Edns>,
+    pub raw_query: Vec<u8>,
+}
+
+impl DnsMessage {
+    /// Decode a wire-format message into header, questions and all record sections.
+    /// Only the parsed fields are populated; `raw_query` and the context fields are left for the caller.
+    pub fn from_wire(buf: &[u8]) -> Result<Self> {
+        crate::wire::decode(buf)
+    }
+
+    /// Encode header, questions and record sections back to wire format (with name compression).
+    /// Section counts are derived from the vectors, not from `header`. Fails on names that cannot be encoded.
+    pub fn to_wire(&self) -> Result<Vec<u8>> {
+        crate::wire::encode(self)
+    }
+
+    pub fn question(&self) -> Option<&DnsQuestion> {
+        self.questions.first()
+    }
+
+    /// Full 12-bit RCODE, including the EDNS0 extended bits
+    pub fn rcode(&self) -> u16 {
+        ((self.edns.as_ref().map(|e| e.extended_rcode).unwrap_or(0) as u16) << 4) | self.header.flags.rcode as u16
+    }
+
+    /// Build an empty response skeleton for this query (same ID, opcode, RD/CD and question)
+    pub fn reply(&self) -> DnsMessage {
+        let mut resp = DnsMessage::default();
+        resp.header.id = self.header.id;
+        resp.header.flags = HeaderFlags {
+            qr: true, opcode: self.header.flags.opcode, rd: self.header.flags.rd, cd: self.header.flags.cd, ra: true,
+            ..Default::default()
+        };
+        resp.questions = self.questions.clone();
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_type_and_class_mnemonics() {
+        for (text, qtype) in [
+            ("A", QType::A), ("aaaa", QType::AAAA), ("ANY", QType::ANY), ("axfr", QType::AXFR),
+            ("DS", QType::Other(43)), ("hinfo", QType::Other(13)), ("HTTPS", QType::Other(65)), ("CAA", QType::Other(257)),
+            ("TYPE1", QType::A), ("type65534", QType::Other(65534)),
+        ] {
+            assert_eq!(text.parse::<QType>().unwrap(), qtype, "{}", text);
+        }
+        assert_eq!((QType::Other(43).as_str(), QType::Other(65534).as_str()), ("DS", "OTHER"));
+        for bad in ["", "NOSUCH", "TYPE", "TYPE65536", "TYPEx"] {
+            assert!(bad.parse::<QType>().is_err(), "{}", bad);
+        }
+
+        for (text, class) in [("IN", QClass::IN), ("chaos", QClass::CH), ("HS", QClass::HS), ("ANY", QClass::ANY), ("CLASS1", QClass::IN), ("CLASS42", QClass::Other(42))] {
+            assert_eq!(text.parse::<QClass>().unwrap(), class, "{}", text);
+        }
+        for bad in ["", "INET", "CLASS", "CLASS70000"] {
+            assert!(bad.parse::<QClass>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parses_rcodes() {
+        assert_eq!((parse_rcode("SERVFAIL"), parse_rcode("nxdomain"), parse_rcode("5"), parse_rcode("15")), (Some(2), Some(3), Some(5), Some(15)));
+        for bad in ["", "16", "-1", "SERVFAILED", "BADVERS"] { assert_eq!(parse_rcode(bad), None, "{}", bad); }
+    }
+}
@@ -0,0 +1,629 @@
+//! DNS wire-format (RFC 1035) decoder and encoder behind `types::DnsMessage`
+//!
+//! Domain names are represented without the trailing dot (`www.example.com`), the root is `.`.
+//! Dots and backslashes inside a label are escaped as `\.` / `\\`, other unprintable bytes as `\DDD`.
+
+use crate::types::{DnsHeader, DnsMessage, DnsQuestion, Edns, HeaderFlags, QClass, QType, Record, ResourceRecord};
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const MAX_POINTER_HOPS: usize = 64;
+
+pub fn decode(buf: &[u8]) -> Result<DnsMessage> {
+    if buf.len() < 12 { bail!("message too short: {} bytes", buf.len()); }
+    let header = DnsHeader {
+        id: read_u16(buf, 0)?,
+        flags: HeaderFlags::from_u16(read_u16(buf, 2)?),
+        question_count: read_u16(buf, 4)?,
+        answer_count: read_u16(buf, 6)?,
+        authority_count: read_u16(buf, 8)?,
+        additional_count: read_u16(buf, 10)?,
+    };
+
+    let mut pos = 12;
+    let mut questions = Vec::with_capacity(header.question_count as usize);
+    for _ in 0..header.question_count {
+        let (name, next) = read_name(buf, pos)?;
+        let qtype = QType::from_u16(read_u16(buf, next)?);
+        let qclass = QClass::from_u16(read_u16(buf, next + 2)?);
+        questions.push(DnsQuestion { name, qtype, qclass });
+        pos = next + 4;
+    }
+
+    let mut sections: [Vec<ResourceRecord>; 3] = Default::default();
+    let counts = [header.answer_count, header.authority_count, header.additional_count];
+    for (section, count) in sections.iter_mut().zip(counts) {
+        for _ in 0..count {
+            let (rr, next) = read_record(buf, pos)?;
+            section.push(rr);
+            pos = next;
+        }
+    }
+    let [answers, authority, mut additional] = sections;
+
+    // OPT 伪记录从附加段中剥离出来，单独放进 msg.edns
+    let mut edns = None;
+    if let Some(i) = additional.iter().position(|rr| rr.data.rtype() == QType::OPT) {
+        let opt = additional.remove(i);
+        if opt.name != "." || additional.iter().any(|rr| rr.data.rtype() == QType::OPT) { bail!("malformed or duplicate OPT record"); }
+        edns = Some(Edns::from_record(&opt).ok_or_else(|| anyhow::anyhow!("malformed OPT options"))?);
+    }
+
+    Ok(DnsMessage { header, questions, answers, authority, additional, edns, ..Default::default() })
+}
+
+/// Fails when a name cannot be encoded (empty label, label over 63 or name over 255 bytes)
+pub fn encode(msg: &DnsMessage) -> Result<Vec<u8>> {
+    let mut enc = Encoder::default();
+    enc.put_u16(msg.header.id);
+    enc.put_u16(msg.header.flags.to_u16());
+    enc.put_u16(msg.questions.len() as u16);
+    enc.put_u16(msg.answers.len() as u16);
+    enc.put_u16(msg.authority.len() as u16);
+    enc.put_u16((msg.additional.len() + msg.edns.is_some() as usize) as u16);
+
+    for q in &msg.questions {
+        enc.put_name(&q.name, true)?;
+        enc.put_u16(q.qtype.to_u16());
+        enc.put_u16(q.qclass.to_u16());
+    }
+    for rr in msg.answers.iter().chain(&msg.authority).chain(&msg.additional) {
+        enc.put_record(rr)?;
+    }
+    if let Some(edns) = &msg.edns {
+        enc.put_record(&edns.to_record())?;
+    }
+    Ok(enc.buf)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section { Answer, Authority, Additional }
+
+/// Byte position of one resource record inside a wire-format message
+#[derive(Debug, Clone, Copy)]
+pub struct RecordSpan {
+    pub section: Section,
+    pub start: usize,
+    pub end: usize,
+    pub rtype: u16,
+    /// 偏移处为 4 字节 TTL 字段，可原地改写而无需重新编码
+    pub ttl_offset: usize,
+}
+
+/// Walk a message and locate every record without decoding RDATA.
+/// Returns the offset right after the question section and the record spans in wire order.
+pub fn layout(buf: &[u8]) -> Result<(usize, Vec<RecordSpan>)> {
+    if buf.len() < 12 { bail!("message too short: {} bytes", buf.len()); }
+    let mut pos = 12;
+    for _ in 0..read_u16(buf, 4)? {
+        pos = skip_name(buf, pos)? + 4;
+    }
+    let question_end = pos;
+    if pos > buf.len() { bail!("question runs past end of message"); }
+
+    let mut spans = Vec::new();
+    let counts = [(Section::Answer, read_u16(buf, 6)?), (Section::Authority, read_u16(buf, 8)?), (Section::Additional, read_u16(buf, 10)?)];
+    for (section, count) in counts {
+        for _ in 0..count {
+            let start = pos;
+            let p = skip_name(buf, pos)?;
+            let rtype = read_u16(buf, p)?;
+            let end = p + 10 + read_u16(buf, p + 8)? as usize;
+            if end > buf.len() { bail!("RDATA runs past end of message"); }
+            spans.push(RecordSpan { section, start, end, rtype, ttl_offset: p + 4 });
+            pos = end;
+        }
+    }
+    Ok((question_end, spans))
+}
+
+/// Replace whatever OPT record a raw message carries with `edns` (or drop it when `None`)
+pub fn set_opt(buf: &mut Vec<u8>, edns: Option<&Edns>) -> Result<()> {
+    let (_, spans) = layout(buf)?;
+    let mut arcount = read_u16(buf, 10)?;
+    for span in spans.iter().rev().filter(|s| s.rtype == QType::OPT.to_u16()) {
+        buf.drain(span.start..span.end);
+        arcount -= 1;
+    }
+    if let Some(edns) = edns {
+        let mut enc = Encoder::default();
+        enc.put_record(&edns.to_record())?;
+        buf.extend_from_slice(&enc.buf);
+        arcount += 1;
+    }
+    buf[10..12].copy_from_slice(&arcount.to_be_bytes());
+    Ok(())
+}
+
+/// Shrink a raw response to at most `max_len` bytes by dropping whole records from the end
+/// (additional, then authority, then answer), keeping the OPT record and fixing up the counts.
+/// TC is only set when answer records had to be dropped (RFC 2181 9).
+pub fn truncate(buf: &mut Vec<u8>, max_len: usize) -> Result<()> {
+    if buf.len() <= max_len { return Ok(()); }
+    // 先把 OPT 从附加段中摘出来，裁剪之后再在末尾放回一次，OPT 不在附加段末尾时也不会出现两份
+    let (_, spans) = layout(buf)?;
+    let opt: Vec<u8> = spans.iter().find(|s| s.rtype == QType::OPT.to_u16()).map(|s| buf[s.start..s.end].to_vec()).unwrap_or_default();
+    set_opt(buf, None)?;
+    let (question_end, records) = layout(buf)?;
+    let budget = max_len.saturating_sub(opt.len());
+
+    // 压缩指针只会向前引用，所以保留记录的前缀、丢弃尾部是安全的
+    let kept = records.iter().take_while(|s| s.end <= budget).count();
+    let cut = if kept == 0 { question_end } else { records[kept - 1].end };
+    let count = |section: Section| records[..kept].iter().filter(|s| s.section == section).count() as u16;
+    let answers_dropped = records[kept..].iter().any(|s| s.section == Section::Answer);
+    let (an, ns, ar) = (count(Section::Answer), count(Section::Authority), count(Section::Additional) + (!opt.is_empty()) as u16);
+
+    buf.truncate(cut);
+    buf.extend_from_slice(&opt);
+    buf[6..8].copy_from_slice(&an.to_be_bytes());
+    buf[8..10].copy_from_slice(&ns.to_be_bytes());
+    buf[10..12].copy_from_slice(&ar.to_be_bytes());
+    if answers_dropped { buf[2] |= 0x02; }
+    Ok(())
+}
+
+/// Smallest TTL among answer and authority records (OPT excluded), used for HTTP caching headers.
+/// Negative answers (no answer records) are additionally bounded by the SOA MINIMUM (RFC 2308).
+pub fn min_ttl(buf: &[u8]) -> Option<u32> {
+    let (_, spans) = layout(buf).ok()?;
+    let min = spans
+        .iter()
+        .filter(|s| s.section != Section::Additional && s.rtype != QType::OPT.to_u16())
+        .filter_map(|s| read_u32(buf, s.ttl_offset).ok())
+        .min();
+    if spans.iter().any(|s| s.section == Section::Answer) { return min; }
+    match (min, negative_ttl(buf)) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// RFC 2308 section 5: TTL of a negative answer is min(SOA TTL, SOA MINIMUM) of the authority SOA.
+/// Returns `None` when the response carries no SOA and therefore must not be cached.
+pub fn negative_ttl(buf: &[u8]) -> Option<u32> {
+    let (_, spans) = layout(buf).ok()?;
+    let soa = spans.iter().find(|s| s.section == Section::Authority && s.rtype == QType::SOA.to_u16())?;
+    // MINIMUM 是 SOA RDATA 的最后 4 字节
+    let minimum = read_u32(buf, soa.end.checked_sub(4)?).ok()?;
+    Some(read_u32(buf, soa.ttl_offset).ok()?.min(minimum))
+}
+
+/// Age every record TTL (OPT excluded) by `elapsed` seconds and cap it at `max`, in place
+pub fn decrement_ttls(buf: &mut [u8], elapsed: u32, max: u32) -> Result<()> {
+    let (_, spans) = layout(buf)?;
+    for span in spans.iter().filter(|s| s.rtype != QType::OPT.to_u16()) {
+        let ttl = read_u32(buf, span.ttl_offset)?.saturating_sub(elapsed).min(max);
+        buf[span.ttl_offset..span.ttl_offset + 4].copy_from_slice(&ttl.to_be_bytes());
+    }
+    Ok(())
+}
+
+/// Make a response for the same question answer `query`: take over its transaction ID and
+/// its exact question bytes (preserving 0x20 mixed-case names). Left untouched if the questions differ in length.
+pub fn adopt_query(resp: &mut [u8], query: &[u8]) {
+    if resp.len() < 12 || query.len() < 12 { return; }
+    resp[..2].copy_from_slice(&query[..2]);
+    if let (Ok((query_end, _)), Ok((resp_end, _))) = (layout(query), layout(resp)) {
+        if query_end == resp_end { resp[12..resp_end].copy_from_slice(&query[12..query_end]); }
+    }
+}
+
+/// Decode only the OPT record of a raw message, if any
+pub fn read_opt(buf: &[u8]) -> Result<Option<Edns>> {
+    let (_, spans) = layout(buf)?;
+    match spans.iter().find(|s| s.rtype == QType::OPT.to_u16()) {
+        Some(span) => Ok(Edns::from_record(&read_record(buf, span.start)?.0)),
+        None => Ok(None),
+    }
+}
+
+fn skip_name(buf: &[u8], mut pos: usize) -> Result<usize> {
+    loop {
+        let Some(&len) = buf.get(pos) else { bail!("name runs past end of message") };
+        match len & 0xC0 {
+            0x00 if len == 0 => return Ok(pos + 1),
+            0x00 => pos += 1 + len as usize,
+            0xC0 => return Ok(pos + 2),
+            _ => bail!("unsupported label type {:#04x}", len),
+        }
+    }
+}
+
+/// Read a (possibly compressed) name starting at `pos`.
+/// Returns the name and the offset right after it in the original byte stream.
+pub fn read_name(buf: &[u8], mut pos: usize) -> Result<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut hops = 0;
+    let mut wire_len = 0;
+
+    loop {
+        let Some(&len) = buf.get(pos) else { bail!("name runs past end of message") };
+        match len & 0xC0 {
+            0x00 => {
+                if len == 0 {
+                    if end.is_none() { end = Some(pos + 1); }
+                    break;
+                }
+                let label = buf.get(pos + 1..pos + 1 + len as usize).ok_or_else(|| anyhow::anyhow!("label runs past end of message"))?;
+                wire_len += len as usize + 1;
+                if wire_len > 255 { bail!("name exceeds 255 bytes"); }
+                if !name.is_empty() { name.push('.'); }
+                push_label(&mut name, label);
+                pos += 1 + len as usize;
+            }
+            0xC0 => {
+                let target = (read_u16(buf, pos)? & 0x3FFF) as usize;
+                if end.is_none() { end = Some(pos + 2); }
+                // 压缩指针只允许向前引用，防止恶意报文构造死循环
+                if target >= pos { bail!("forward compression pointer"); }
+                hops += 1;
+                if hops > MAX_POINTER_HOPS { bail!("too many compression pointers"); }
+                pos = target;
+            }
+            _ => bail!("unsupported label type {:#04x}", len),
+        }
+    }
+
+    if name.is_empty() { name.push('.'); }
+    Ok((name, end.unwrap_or(pos + 1)))
+}
+
+/// Split a presentation-format name into raw labels, honoring `\.` and `\DDD` escapes.
+/// Fails on empty labels (`a..b`), labels over 63 bytes and names over 255 bytes.
+pub fn name_to_labels(name: &str) -> Result<Vec<Vec<u8>>> {
+    let mut labels = Vec::new();
+    if name.is_empty() || name == "." { return Ok(labels); }
+    let bytes = name.as_bytes();
+    let mut label = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if i + 3 < bytes.len() && bytes[i + 1..i + 4].iter().all(|b| b.is_ascii_digit()) => {
+                let v = (bytes[i + 1] - b'0') as u16 * 100 + (bytes[i + 2] - b'0') as u16 * 10 + (bytes[i + 3] - b'0') as u16;
+                label.push(v.min(255) as u8);
+                i += 4;
+            }
+            b'\\' if i + 1 < bytes.len() => { label.push(bytes[i + 1]); i += 2; }
+            b'.' => {
+                if label.is_empty() { bail!("empty label in name {}", name); }
+                labels.push(std::mem::take(&mut label));
+                i += 1;
+            }
+            b => { label.push(b); i += 1; }
+        }
+    }
+    if !label.is_empty() { labels.push(label); }
+    if labels.iter().any(|l| l.len() > 63) { bail!("label longer than 63 bytes in name {}", name); }
+    if labels.iter().map(|l| l.len() + 1).sum::<usize>() + 1 > 255 { bail!("name {} exceeds 255 bytes", name); }
+    Ok(labels)
+}
+
+/// Encode a name without compression (used for cache keys and hand-built packets)
+pub fn name_to_wire(name: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(name.len() + 2);
+    for label in name_to_labels(name)? {
+        out.push(label.len() as u8);
+        out.extend_from_slice(&label);
+    }
+    out.push(0);
+    Ok(out)
+}
+
+fn push_label(name: &mut String, label: &[u8]) {
+    for &b in label {
+        match b {
+            b'.' | b'\\' => { name.push('\\'); name.push(b as char); }
+            0x21..=0x7E => name.push(b as char),
+            _ => name.push_str(&format!("\\{:03}", b)),
+        }
+    }
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> Result<u16> {
+    match buf.get(pos..pos + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+        None => bail!("unexpected end of message at offset {}", pos),
+    }
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> Result<u32> {
+    match buf.get(pos..pos + 4) {
+        Some(b) => Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        None => bail!("unexpected end of message at offset {}", pos),
+    }
+}
+
+fn read_record(buf: &[u8], pos: usize) -> Result<(ResourceRecord, usize)> {
+    let (name, pos) = read_name(buf, pos)?;
+    let rtype = read_u16(buf, pos)?;
+    let rclass = QClass::from_u16(read_u16(buf, pos + 2)?);
+    let ttl = read_u32(buf, pos + 4)?;
+    let rdlen = read_u16(buf, pos + 8)? as usize;
+    let start = pos + 10;
+    let end = start + rdlen;
+    if end > buf.len() { bail!("RDATA runs past end of message"); }
+    let rdata = &buf[start..end];
+
+    let data = match QType::from_u16(rtype) {
+        // 动态更新 / IXFR 中可能出现空 RDATA，按未知类型保留
+        _ if rdlen == 0 => Record::Unknown { rtype, data: Vec::new() },
+        QType::A if rdlen == 4 => Record::A { addr: Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]) },
+        QType::AAAA if rdlen == 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(rdata);
+            Record::AAAA { addr: Ipv6Addr::from(octets) }
+        }
+        QType::CNAME => Record::CNAME { cname: read_rdata_name(buf, start, end)? },
+        QType::NS => Record::NS { nsdname: read_rdata_name(buf, start, end)? },
+        QType::PTR => Record::PTR { ptrdname: read_rdata_name(buf, start, end)? },
+        QType::MX if rdlen >= 3 => Record::MX { preference: read_u16(buf, start)?, exchange: read_rdata_name(buf, start + 2, end)? },
+        QType::SRV if rdlen >= 7 => Record::SRV {
+            priority: read_u16(buf, start)?, weight: read_u16(buf, start + 2)?, port: read_u16(buf, start + 4)?,
+            target: read_rdata_name(buf, start + 6, end)?,
+        },
+        QType::SOA => {
+            let (mname, p) = read_name(buf, start)?;
+            let (rname, p) = read_name(buf, p)?;
+            if p + 20 != end { bail!("malformed SOA RDATA"); }
+            Record::SOA {
+                mname, rname,
+                serial: read_u32(buf, p)?, refresh: read_u32(buf, p + 4)?, retry: read_u32(buf, p + 8)?,
+                expire: read_u32(buf, p + 12)?, minimum: read_u32(buf, p + 16)?,
+            }
+        }
+        QType::TXT => {
+            let mut text = Vec::new();
+            let mut p = 0;
+            while p < rdata.len() {
+                let l = rdata[p] as usize;
+                let s = rdata.get(p + 1..p + 1 + l).ok_or_else(|| anyhow::anyhow!("malformed TXT RDATA"))?;
+                text.push(s.to_vec());
+                p += 1 + l;
+            }
+            Record::TXT { text }
+        }
+        _ => Record::Unknown { rtype, data: rdata.to_vec() },
+    };
+
+    Ok((ResourceRecord { name, rclass, ttl, data }, end))
+}
+
+fn read_rdata_name(buf: &[u8], start: usize, end: usize) -> Result<String> {
+    let (name, next) = read_name(buf, start)?;
+    if next != end { bail!("name does not fill RDATA"); }
+    Ok(name)
+}
+
+#[derive(Default)]
+struct Encoder {
+    buf: Vec<u8>,
+    // 已写入名字的小写后缀 -> 报文偏移，用于名字压缩
+    names: HashMap<Vec<Vec<u8>>, u16>,
+}
+
+impl Encoder {
+    fn put_u16(&mut self, v: u16) { self.buf.extend_from_slice(&v.to_be_bytes()); }
+    fn put_u32(&mut self, v: u32) { self.buf.extend_from_slice(&v.to_be_bytes()); }
+
+    fn put_name(&mut self, name: &str, compress: bool) -> Result<()> {
+        let labels = name_to_labels(name)?;
+        for i in 0..labels.len() {
+            let suffix: Vec<Vec<u8>> = labels[i..].iter().map(|l| l.to_ascii_lowercase()).collect();
+            if compress {
+                if let Some(&ptr) = self.names.get(&suffix) {
+                    self.put_u16(0xC000 | ptr);
+                    return Ok(());
+                }
+            }
+            if self.buf.len() < 0x3FFF { self.names.entry(suffix).or_insert(self.buf.len() as u16); }
+            self.buf.push(labels[i].len() as u8);
+            self.buf.extend_from_slice(&labels[i]);
+        }
+        self.buf.push(0);
+        Ok(())
+    }
+
+    fn put_record(&mut self, rr: &ResourceRecord) -> Result<()> {
+        self.put_name(&rr.name, true)?;
+        self.put_u16(rr.data.rtype().to_u16());
+        self.put_u16(rr.rclass.to_u16());
+        self.put_u32(rr.ttl);
+        let len_pos = self.buf.len();
+        self.put_u16(0);
+
+        match &rr.data {
+            Record::A { addr } => self.buf.extend_from_slice(&addr.octets()),
+            Record::AAAA { addr } => self.buf.extend_from_slice(&addr.octets()),
+            Record::CNAME { cname } => self.put_name(cname, true)?,
+            Record::NS { nsdname } => self.put_name(nsdname, true)?,
+            Record::PTR { ptrdname } => self.put_name(ptrdname, true)?,
+            Record::MX { preference, exchange } => { self.put_u16(*preference); self.put_name(exchange, true)?; }
+            Record::SRV { priority, weight, port, target } => {
+                self.put_u16(*priority); self.put_u16(*weight); self.put_u16(*port);
+                // RFC 2782: SRV target 不允许压缩
+                self.put_name(target, false)?;
+            }
+            Record::SOA { mname, rname, serial, refresh, retry, expire, minimum } => {
+                self.put_name(mname, true)?;
+                self.put_name(rname, true)?;
+                for v in [*serial, *refresh, *retry, *expire, *minimum] { self.put_u32(v); }
+            }
+            Record::TXT { text } => {
+                for s in text {
+                    for chunk in s.chunks(255) {
+                        self.buf.push(chunk.len() as u8);
+                        self.buf.extend_from_slice(chunk);
+                    }
+                    if s.is_empty() { self.buf.push(0); }
+                }
+            }
+            Record::Unknown { data, .. } => self.buf.extend_from_slice(data),
+        }
+
+        let rdlen = u16::try_from(self.buf.len() - len_pos - 2).map_err(|_| anyhow::anyhow!("RDATA of {} exceeds 65535 bytes", rr.name))?;
+        self.buf[len_pos..len_pos + 2].copy_from_slice(&rdlen.to_be_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, data: Record) -> ResourceRecord {
+        ResourceRecord { name: name.to_string(), rclass: QClass::IN, ttl: 300, data }
+    }
+
+    fn response(answers: Vec<ResourceRecord>) -> DnsMessage {
+        let mut msg = DnsMessage::default();
+        msg.header.id = 0x1234;
+        msg.header.flags = HeaderFlags { qr: true, rd: true, ra: true, ..Default::default() };
+        msg.questions.push(DnsQuestion { name: "www.example.com".to_string(), qtype: QType::A, qclass: QClass::IN });
+        msg.answers = answers;
+        msg
+    }
+
+    #[test]
+    fn decode_compressed_response() {
+        let buf = [
+            0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 1,
+            3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 1, 0, 1,
+            // 指向问题段名字的压缩指针
+            0xC0, 12, 0, 1, 0, 1, 0, 0, 0x0E, 0x10, 0, 4, 192, 0, 2, 1,
+            0, 0, 41, 0x04, 0xD0, 0, 0, 0x80, 0, 0, 0,
+        ];
+        let msg = decode(&buf).unwrap();
+        assert_eq!(msg.header.id, 0x1234);
+        assert!(msg.header.flags.qr && msg.header.flags.rd && msg.header.flags.ra);
+        assert_eq!(msg.question().unwrap().name, "www.example.com");
+        assert_eq!(msg.answers, vec![ResourceRecord { ttl: 3600, ..record("www.example.com", Record::A { addr: Ipv4Addr::new(192, 0, 2, 1) }) }]);
+        assert!(msg.additional.is_empty());
+        let edns = msg.edns.unwrap();
+        assert_eq!(edns.udp_payload_size, 1232);
+        assert!(edns.dnssec_ok);
+    }
+
+    #[test]
+    fn decode_rejects_pointer_loops_and_short_messages() {
+        let mut buf = vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        buf.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1]);
+        assert!(decode(&buf).is_err());
+        assert!(decode(&buf[..11]).is_err());
+
+        let mut truncated = encode(&response(vec![record("www.example.com", Record::A { addr: Ipv4Addr::LOCALHOST })])).unwrap();
+        truncated.pop();
+        assert!(decode(&truncated).is_err());
+    }
+
+    #[test]
+    fn escaped_labels_round_trip() {
+        let mut buf = vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        buf.extend_from_slice(&[3, b'a', b'.', 0xFF, 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0, 0, 1, 0, 1]);
+        let msg = decode(&buf).unwrap();
+        assert_eq!(msg.question().unwrap().name, "a\\.\\255.example");
+        assert_eq!(encode(&msg).unwrap(), buf);
+    }
+
+    #[test]
+    fn encode_round_trip_with_compression() {
+        let answers = vec![
+            record("www.example.com", Record::CNAME { cname: "web.example.com".to_string() }),
+            record("web.example.com", Record::A { addr: Ipv4Addr::new(192, 0, 2, 1) }),
+            record("web.example.com", Record::TXT { text: vec![b"v=spf1 -all".to_vec(), vec![0xFF, 0x00, 0xFE], Vec::new()] }),
+            record("example.com", Record::MX { preference: 10, exchange: "mail.example.com".to_string() }),
+            record("_sip._udp.example.com", Record::SRV { priority: 1, weight: 2, port: 5060, target: "sip.example.com".to_string() }),
+            record("example.com", Record::SOA {
+                mname: "ns1.example.com".to_string(), rname: "hostmaster.example.com".to_string(),
+                serial: 2024010101, refresh: 7200, retry: 3600, expire: 1209600, minimum: 300,
+            }),
+            record("example.com", Record::Unknown { rtype: 99, data: vec![1, 2, 3] }),
+        ];
+        let mut msg = response(answers.clone());
+        msg.edns = Some(Edns { dnssec_ok: true, options: vec![(10, vec![1; 8])], ..Edns::new(1232) });
+        let buf = encode(&msg).unwrap();
+
+        let decoded = decode(&buf).unwrap();
+        assert_eq!(decoded.answers, answers);
+        assert_eq!(decoded.edns, msg.edns);
+        assert_eq!(decoded.header.additional_count, 1);
+        // example.com 后缀只在问题段和不允许压缩的 SRV target 中完整写出，其余都是指针
+        assert_eq!(buf.windows(10).filter(|w| *w == b"\x07example\x03c").count(), 2);
+    }
+
+    #[test]
+    fn encode_rejects_invalid_names() {
+        let long_label = "a".repeat(64);
+        let long_name = vec!["a".repeat(63); 4].join(".");
+        for name in ["a..example", ".example", long_label.as_str(), long_name.as_str()] {
+            assert!(name_to_wire(name).is_err(), "{}", name);
+            assert!(encode(&response(vec![record(name, Record::A { addr: Ipv4Addr::LOCALHOST })])).is_err(), "{}", name);
+        }
+        assert_eq!(name_to_wire("example.com.").unwrap(), b"\x07example\x03com\x00");
+        assert_eq!(name_to_wire(".").unwrap(), b"\x00");
+        assert!(name_to_wire(&"a".repeat(63)).is_ok());
+    }
+
+    #[test]
+    fn truncate_drops_trailing_records_and_keeps_opt() {
+        let answers: Vec<_> = (1..=20).map(|i| record("www.example.com", Record::A { addr: Ipv4Addr::new(192, 0, 2, i) })).collect();
+        let mut msg = response(answers);
+        msg.additional.push(record("ns.example.com", Record::A { addr: Ipv4Addr::new(198, 51, 100, 1) }));
+        msg.edns = Some(Edns::new(1232));
+        let mut buf = encode(&msg).unwrap();
+        let full_len = buf.len();
+
+        // 装得下时原样返回
+        truncate(&mut buf, full_len).unwrap();
+        assert_eq!(buf.len(), full_len);
+
+        // 只去掉附加段的记录：不置 TC
+        truncate(&mut buf, full_len - 1).unwrap();
+        let decoded = decode(&buf).unwrap();
+        assert_eq!((decoded.answers.len(), decoded.additional.len()), (20, 0));
+        assert!(decoded.edns.is_some() && !decoded.header.flags.tc);
+
+        truncate(&mut buf, 200).unwrap();
+        assert!(buf.len() <= 200);
+        let decoded = decode(&buf).unwrap();
+        assert!(decoded.header.flags.tc);
+        assert!(!decoded.answers.is_empty() && decoded.answers.len() < 20);
+        assert_eq!(decoded.header.additional_count, 1);
+        assert!(decoded.edns.is_some());
+    }
+
+    #[test]
+    fn truncate_moves_opt_out_of_the_middle() {
+        let mut msg = response(vec![record("www.example.com", Record::A { addr: Ipv4Addr::new(192, 0, 2, 1) })]);
+        // 上游把 OPT 放在了附加段中间
+        msg.additional.push(record("ns1.example.com", Record::A { addr: Ipv4Addr::new(198, 51, 100, 1) }));
+        msg.additional.push(Edns::new(1232).to_record());
+        msg.additional.push(record("ns2.example.com", Record::A { addr: Ipv4Addr::new(198, 51, 100, 2) }));
+        msg.additional.push(record("ns3.example.com", Record::A { addr: Ipv4Addr::new(198, 51, 100, 3) }));
+        let mut buf = encode(&msg).unwrap();
+        let max_len = buf.len() - 1;
+
+        truncate(&mut buf, max_len).unwrap();
+        let decoded = decode(&buf).unwrap();
+        assert_eq!(decoded.additional.len(), 2);
+        assert_eq!(decoded.header.additional_count, 3);
+        assert_eq!(decoded.edns, Some(Edns::new(1232)));
+        assert_eq!(layout(&buf).unwrap().1.last().unwrap().rtype, QType::OPT.to_u16());
+    }
+
+    #[test]
+    fn negative_ttl_is_bounded_by_soa_minimum() {
+        let mut msg = response(Vec::new());
+        msg.header.flags.rcode = 3;
+        msg.authority.push(record("example.com", Record::SOA {
+            mname: "ns1.example.com".to_string(), rname: "hostmaster.example.com".to_string(),
+            serial: 1, refresh: 7200, retry: 3600, expire: 1209600, minimum: 60,
+        }));
+        let buf = encode(&msg).unwrap();
+        assert_eq!(negative_ttl(&buf), Some(60));
+        assert_eq!(min_ttl(&buf), Some(60));
+        assert_eq!(negative_ttl(&encode(&response(Vec::new())).unwrap()), None);
+    }
+}
@@ -0,0 +1,783 @@
+//! Authoritative zone data: RFC 1035 master file parsing and the RFC 1034 §4.3.2 lookup algorithm
+//!
+//! Owner names follow the crate convention (no trailing dot, root is `.`); the lookup tables are keyed by
+//! the lowercased name while records keep the spelling of the master file.
+
+use crate::types::{QClass, QType, Record, ResourceRecord};
+use anyhow::{anyhow, bail, Context, Result};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+
+const MAX_INCLUDE_DEPTH: usize = 8;
+const MAX_CNAME_CHAIN: usize = 8;
+const QTYPE_DS: u16 = 43;
+/// 每个区保留的 SERIAL 变更历史条数，更早的版本只能走 AXFR
+const MAX_JOURNAL: usize = 32;
+
+pub struct Zone {
+    /// 小写、无尾点，根域为 "."
+    pub origin: String,
+    // 小写 owner -> 该名字下的全部记录，保持写入顺序
+    nodes: BTreeMap<String, Vec<ResourceRecord>>,
+    // 所有存在的名字，包括只有下级节点的空非终端 (ENT)，用于区分 NODATA 与 NXDOMAIN
+    names: HashSet<String>,
+}
+
+/// 一次权威查询的结果，由插件组装成应答
+#[derive(Default)]
+pub struct Lookup {
+    pub rcode: u8,
+    pub authoritative: bool,
+    pub answers: Vec<ResourceRecord>,
+    pub authority: Vec<ResourceRecord>,
+    pub additional: Vec<ResourceRecord>,
+}
+
+impl Zone {
+    /// Parse a master file (following `$INCLUDE`s) for `origin`
+    pub fn parse_file(path: &str, origin: &str) -> Result<Self> {
+        let mut parser = MasterParser { origin: normalize(origin), ..Default::default() };
+        parser.parse_file(Path::new(path), 0)?;
+        Self::from_records(origin, parser.records)
+    }
+
+    /// Build a zone from a flat record list (master file or AXFR); exactly one SOA at the apex is required
+    pub fn from_records(origin: &str, records: Vec<ResourceRecord>) -> Result<Self> {
+        let origin = normalize(origin);
+        let mut zone = Zone { origin: origin.clone(), nodes: BTreeMap::new(), names: HashSet::new() };
+        for rr in records {
+            let owner = normalize(&rr.name);
+            if !in_zone(&owner, &origin) {
+                tracing::warn!("[zone] Ignoring out-of-zone record '{}' in {}", rr.name, origin);
+                continue;
+            }
+            if rr.data.rtype() == QType::SOA && owner != origin { bail!("SOA record '{}' is not at the zone apex {}", rr.name, origin); }
+            let node = zone.nodes.entry(owner.clone()).or_default();
+            if node.iter().any(|r| r.data == rr.data) { continue; }
+            node.push(rr);
+            // owner 与 apex 之间的每一级都是存在的名字
+            let mut name = owner.as_str();
+            while zone.names.insert(name.to_string()) && name != origin {
+                let Some(up) = parent(name) else { break; };
+                name = up;
+            }
+        }
+        match zone.nodes.get(&origin).map(|rrs| rrs.iter().filter(|r| r.data.rtype() == QType::SOA).count()) {
+            Some(1) => Ok(zone),
+            Some(n) if n > 1 => bail!("zone {} has {} SOA records", origin, n),
+            _ => bail!("zone {} has no SOA record", origin),
+        }
+    }
+
+    pub fn soa(&self) -> &ResourceRecord {
+        // from_records 保证 apex 处恰好有一条 SOA
+        self.nodes[&self.origin].iter().find(|r| r.data.rtype() == QType::SOA).expect("zone without SOA")
+    }
+
+    pub fn serial(&self) -> u32 {
+        soa_serial(self.soa())
+    }
+
+    /// All records of the zone, the apex first (including its SOA)
+    pub fn records(&self) -> impl Iterator<Item = &ResourceRecord> {
+        let apex = self.nodes.get(&self.origin).into_iter().flatten();
+        apex.chain(self.nodes.iter().filter(|(name, _)| **name != self.origin).flat_map(|(_, rrs)| rrs))
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Answer `qname` (which must lie inside the zone) per RFC 1034 §4.3.2:
+    /// referrals at zone cuts, CNAME chasing inside the zone, wildcards, NODATA / NXDOMAIN with the SOA
+    pub fn lookup(&self, qname: &str, qtype: QType) -> Lookup {
+        let mut out = Lookup { authoritative: true, ..Default::default() };
+        let mut name = normalize(qname);
+        let mut visited = HashSet::new();
+
+        while visited.insert(name.clone()) && visited.len() <= MAX_CNAME_CHAIN {
+            // 1. 沿途遇到委派 (非 apex 的 NS) 就返回 referral；DS 属于父区，在切点处照常应答
+            if let Some(cut) = self.find_cut(&name) {
+                if !(cut == name && qtype == QType::Other(QTYPE_DS)) {
+                    let ns: Vec<_> = self.rrset(&cut, QType::NS).cloned().collect();
+                    // 由 CNAME 引入的 referral 仍对前面的 CNAME 负责
+                    out.authoritative = !out.answers.is_empty();
+                    out.additional.extend(self.glue(&ns));
+                    out.authority.extend(ns);
+                    return out;
+                }
+            }
+
+            // 2. 精确匹配，否则尝试最近祖先处的通配符
+            let (node, owner) = match self.nodes.get(&name) {
+                Some(rrs) => (rrs, None),
+                None if self.names.contains(&name) => { out.authority.push(self.negative_soa()); return out; }
+                None => match self.wildcard(&name) {
+                    Some(rrs) => (rrs, Some(name.clone())),
+                    None => {
+                        out.rcode = 3;
+                        out.authority.push(self.negative_soa());
+                        return out;
+                    }
+                },
+            };
+            let synthesize = |rr: &ResourceRecord| match &owner {
+                Some(owner) => ResourceRecord { name: owner.clone(), ..rr.clone() },
+                None => rr.clone(),
+            };
+
+            let matched: Vec<_> = node.iter().filter(|r| qtype == QType::ANY || r.data.rtype() == qtype).map(synthesize).collect();
+            if !matched.is_empty() {
+                out.additional.extend(self.glue(&matched));
+                out.answers.extend(matched);
+                return out;
+            }
+
+            // 3. CNAME：区内目标继续追，区外目标交给递归方
+            let Some(cname) = node.iter().find(|r| r.data.rtype() == QType::CNAME) else {
+                out.authority.push(self.negative_soa());
+                return out;
+            };
+            out.answers.push(synthesize(cname));
+            let Record::CNAME { cname: target } = &cname.data else { return out; };
+            let target = normalize(target);
+            if qtype == QType::CNAME || !in_zone(&target, &self.origin) { return out; }
+            name = target;
+        }
+        // CNAME 成环或链过长：已有的链原样返回
+        out
+    }
+
+    fn rrset<'a>(&'a self, name: &str, qtype: QType) -> impl Iterator<Item = &'a ResourceRecord> {
+        self.nodes.get(name).into_iter().flatten().filter(move |r| r.data.rtype() == qtype)
+    }
+
+    /// apex 与 name 之间最靠上的委派点 (含 name 自身)
+    fn find_cut(&self, name: &str) -> Option<String> {
+        let mut below_apex = Vec::new();
+        let mut current = name;
+        while current != self.origin {
+            below_apex.push(current);
+            current = parent(current)?;
+        }
+        below_apex.into_iter().rev().find(|n| self.rrset(n, QType::NS).next().is_some()).map(str::to_string)
+    }
+
+    /// 最近存在的祖先 (closest encloser) 下的 `*` 节点
+    fn wildcard(&self, name: &str) -> Option<&Vec<ResourceRecord>> {
+        let mut encloser = parent(name)?;
+        while !self.names.contains(encloser) { encloser = parent(encloser)?; }
+        let wildcard = if encloser == "." { "*".to_string() } else { format!("*.{}", encloser) };
+        self.nodes.get(&wildcard)
+    }
+
+    /// NS / MX / SRV 目标在区内的 A / AAAA 记录，委派时即为 glue
+    fn glue(&self, rrs: &[ResourceRecord]) -> Vec<ResourceRecord> {
+        let mut extra = Vec::new();
+        for rr in rrs {
+            let target = match &rr.data {
+                Record::NS { nsdname } => nsdname,
+                Record::MX { exchange, .. } => exchange,
+                Record::SRV { target, .. } => target,
+                _ => continue,
+            };
+            let target = normalize(target);
+            if !in_zone(&target, &self.origin) { continue; }
+            for addr in self.rrset(&target, QType::A).chain(self.rrset(&target, QType::AAAA)) {
+                if !extra.contains(addr) { extra.push(addr.clone()); }
+            }
+        }
+        extra
+    }
+
+    /// 否定应答附带的 SOA，TTL 取 min(SOA TTL, MINIMUM) (RFC 2308)
+    fn negative_soa(&self) -> ResourceRecord {
+        let soa = self.soa().clone();
+        let ttl = match soa.data { Record::SOA { minimum, .. } => soa.ttl.min(minimum), _ => soa.ttl };
+        ResourceRecord { ttl, ..soa }
+    }
+}
+
+/// One serial change of a zone: the records removed and added between two versions (RFC 1995)
+pub struct Delta {
+    pub from_soa: ResourceRecord,
+    pub to_soa: ResourceRecord,
+    pub removed: Vec<ResourceRecord>,
+    pub added: Vec<ResourceRecord>,
+}
+
+impl Delta {
+    fn between(old: &Zone, new: &Zone) -> Self {
+        let old_keys: HashMap<Vec<u8>, &ResourceRecord> = old.records().filter(|r| r.data.rtype() != QType::SOA).map(|r| (record_key(r), r)).collect();
+        let new_keys: HashMap<Vec<u8>, &ResourceRecord> = new.records().filter(|r| r.data.rtype() != QType::SOA).map(|r| (record_key(r), r)).collect();
+        Delta {
+            from_soa: old.soa().clone(),
+            to_soa: new.soa().clone(),
+            removed: old.records().filter(|r| r.data.rtype() != QType::SOA && !new_keys.contains_key(&record_key(r))).cloned().collect(),
+            added: new.records().filter(|r| r.data.rtype() != QType::SOA && !old_keys.contains_key(&record_key(r))).cloned().collect(),
+        }
+    }
+}
+
+/// A served zone shared between the plugin that loads it (file / secondary) and `transfer`:
+/// the current version plus a journal of recent serial changes for IXFR
+pub struct ZoneHandle {
+    pub origin: String,
+    current: RwLock<Option<Arc<Zone>>>,
+    journal: Mutex<VecDeque<Arc<Delta>>>,
+}
+
+impl ZoneHandle {
+    pub fn new(origin: &str, zone: Option<Zone>) -> Self {
+        Self { origin: normalize(origin), current: RwLock::new(zone.map(Arc::new)), journal: Mutex::new(VecDeque::new()) }
+    }
+
+    /// 当前版本；尚未成功加载时为 None
+    pub fn get(&self) -> Option<Arc<Zone>> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the zone if its serial differs, recording the difference in the journal; returns whether it was replaced
+    pub fn update(&self, zone: Zone) -> bool {
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(old) = current.as_ref() {
+            if old.serial() == zone.serial() { return false; }
+            let mut journal = self.journal.lock().unwrap_or_else(|e| e.into_inner());
+            journal.push_back(Arc::new(Delta::between(old, &zone)));
+            while journal.len() > MAX_JOURNAL { journal.pop_front(); }
+        }
+        *current = Some(Arc::new(zone));
+        true
+    }
+
+    /// Drop the zone and its journal (a secondary whose data expired); queries answer SERVFAIL until the next update
+    pub fn clear(&self) {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = None;
+        self.journal.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// 从 `serial` 到当前版本的连续变更；历史中找不到起点时返回 None (退回 AXFR)
+    pub fn changes_since(&self, serial: u32) -> Option<Vec<Arc<Delta>>> {
+        let journal = self.journal.lock().unwrap_or_else(|e| e.into_inner());
+        let start = journal.iter().position(|d| soa_serial(&d.from_soa) == serial)?;
+        Some(journal.iter().skip(start).cloned().collect())
+    }
+}
+
+/// Parse one record in master-file syntax (e.g. a rendered `template` answer); relative names are completed with `origin`
+pub fn parse_record(text: &str, origin: &str, default_ttl: u32) -> Result<ResourceRecord> {
+    let mut parser = MasterParser { origin: normalize(origin), default_ttl: Some(default_ttl), ..Default::default() };
+    let entries = tokenize(text)?;
+    let [entry] = entries.as_slice() else { bail!("expected exactly one record, got {}", entries.len()); };
+    if entry.blank_owner { bail!("record without owner name"); }
+    parser.parse_record(entry)?;
+    parser.records.pop().ok_or_else(|| anyhow!("no record parsed"))
+}
+
+pub fn soa_serial(rr: &ResourceRecord) -> u32 {
+    match rr.data { Record::SOA { serial, .. } => serial, _ => 0 }
+}
+
+/// RFC 1982 序列号比较：a 相对 b 更新为 Greater；两者相差恰为 2^31 时没有定义，返回 None
+pub fn serial_cmp(a: u32, b: u32) -> Option<Ordering> {
+    match a.wrapping_sub(b) {
+        0 => Some(Ordering::Equal),
+        0x8000_0000 => None,
+        diff if diff < 0x8000_0000 => Some(Ordering::Greater),
+        _ => Some(Ordering::Less),
+    }
+}
+
+/// 主 / 辅服务器地址："10.0.0.1"、"10.0.0.1:5300"、"[2001:db8::1]:5300"，端口默认 53
+pub fn parse_target(addr: &str) -> Option<SocketAddr> {
+    addr.parse().ok().or_else(|| addr.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 53)))
+}
+
+/// 比较两个版本时的记录标识：小写 owner 的线格式，TTL 变化也算一次删除 + 新增
+fn record_key(rr: &ResourceRecord) -> Vec<u8> {
+    let rr = ResourceRecord { name: rr.name.to_ascii_lowercase(), ..rr.clone() };
+    // 区数据的名字在加载时已经校验过，编码不会失败
+    crate::types::DnsMessage { answers: vec![rr], ..Default::default() }.to_wire().unwrap_or_default()
+}
+
+/// Canonical form of a name or zone: lowercase, no trailing dot, the root is "."
+pub fn normalize(name: &str) -> String {
+    let name = name.strip_suffix('.').filter(|n| !n.ends_with('\\')).unwrap_or(name).to_ascii_lowercase();
+    if name.is_empty() { ".".to_string() } else { name }
+}
+
+/// Whether a normalized name is `origin` itself or below it (an escaped `\.` is not a label boundary)
+pub fn in_zone(name: &str, origin: &str) -> bool {
+    origin == "." || name == origin || name.strip_suffix(origin).is_some_and(|head| head.ends_with('.') && !head.ends_with("\\."))
+}
+
+/// 带尾点的完整域名，根域为 "."
+pub fn fqdn(name: &str) -> String {
+    if name == "." { ".".to_string() } else { format!("{}.", name.trim_end_matches('.')) }
+}
+
+/// "a.b.c" -> "b.c"，"c" -> "."，根域没有上级；跳过转义的 `\.`
+fn parent(name: &str) -> Option<&str> {
+    if name == "." { return None; }
+    let bytes = name.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'.' => return Some(&name[i + 1..]),
+            _ => i += 1,
+        }
+    }
+    Some(".")
+}
+
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// 一条逻辑记录：括号内的换行已合并，`blank_owner` 表示行首为空白 (沿用上一条的 owner)
+struct Entry {
+    line: usize,
+    blank_owner: bool,
+    tokens: Vec<Token>,
+}
+
+#[derive(Default)]
+struct MasterParser {
+    origin: String,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_owner: Option<String>,
+    records: Vec<ResourceRecord>,
+}
+
+impl MasterParser {
+    fn parse_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH { bail!("$INCLUDE nested too deeply at {}", path.display()); }
+        let content = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let entries = tokenize(&content).with_context(|| path.display().to_string())?;
+
+        for entry in entries {
+            let at = || format!("{}:{}", path.display(), entry.line);
+            let first = &entry.tokens[0];
+            if !entry.blank_owner && !first.quoted && first.text.starts_with('$') {
+                let arg = |i: usize| entry.tokens.get(i).map(|t| t.text.as_str()).ok_or_else(|| anyhow!("{}: {} needs an argument", at(), first.text));
+                match first.text.to_ascii_uppercase().as_str() {
+                    "$ORIGIN" => self.origin = normalize(&absolute(arg(1)?, &self.origin)),
+                    "$TTL" => self.default_ttl = Some(parse_ttl(arg(1)?).with_context(at)?),
+                    "$INCLUDE" => {
+                        let file = arg(1)?;
+                        let file = path.parent().map(|dir| dir.join(file)).unwrap_or_else(|| file.into());
+                        // 被包含文件可以指定自己的 origin，结束后恢复当前的 origin 与 owner (RFC 1035 §5.1)
+                        let saved = (self.origin.clone(), self.last_owner.take());
+                        if let Some(origin) = entry.tokens.get(2) { self.origin = normalize(&absolute(&origin.text, &self.origin)); }
+                        self.parse_file(&file, depth + 1)?;
+                        (self.origin, self.last_owner) = saved;
+                    }
+                    other => bail!("{}: unknown directive {}", at(), other),
+                }
+                continue;
+            }
+            self.parse_record(&entry).with_context(at)?;
+        }
+        Ok(())
+    }
+
+    fn parse_record(&mut self, entry: &Entry) -> Result<()> {
+        let mut tokens = entry.tokens.iter().map(|t| t.text.as_str()).peekable();
+        let owner = if entry.blank_owner {
+            self.last_owner.clone().ok_or_else(|| anyhow!("record without owner name"))?
+        } else {
+            checked(absolute(tokens.next().unwrap_or_default(), &self.origin))?
+        };
+
+        // owner 之后 TTL 与 CLASS 都可省略，且顺序任意
+        let mut ttl = None;
+        while let Some(&token) = tokens.peek() {
+            if let Ok(class) = token.parse::<QClass>() {
+                if class != QClass::IN { bail!("unsupported class {}", token); }
+            } else if let Ok(value) = parse_ttl(token) {
+                ttl = Some(value);
+            } else {
+                break;
+            }
+            tokens.next();
+        }
+        let rtype = tokens.next().ok_or_else(|| anyhow!("missing record type"))?;
+        let rtype = match rtype.parse::<QType>()? {
+            QType::OPT | QType::AXFR | QType::IXFR | QType::ANY => bail!("{} is not a record type", rtype),
+            qtype => qtype.to_u16(),
+        };
+        let rdata: Vec<&Token> = entry.tokens.iter().skip(entry.tokens.len() - tokens.count()).collect();
+        let data = self.parse_rdata(rtype, &rdata)?;
+
+        // 缺省 TTL：$TTL，其次为上一条记录的 TTL，SOA 自身可退回其 MINIMUM 字段
+        let ttl = match (ttl.or(self.default_ttl), &data) {
+            (Some(ttl), _) => ttl,
+            (None, Record::SOA { minimum, .. }) => self.last_ttl.unwrap_or(*minimum),
+            (None, _) => self.last_ttl.ok_or_else(|| anyhow!("no TTL specified and no $TTL in effect"))?,
+        };
+        self.last_ttl = Some(ttl);
+        self.last_owner = Some(owner.clone());
+        self.records.push(ResourceRecord { name: owner, rclass: QClass::IN, ttl, data });
+        Ok(())
+    }
+
+    fn parse_rdata(&self, rtype: u16, rdata: &[&Token]) -> Result<Record> {
+        let text = |i: usize| rdata.get(i).map(|t| t.text.as_str()).ok_or_else(|| anyhow!("missing RDATA field {}", i + 1));
+        let name = |i: usize| text(i).and_then(|t| checked(absolute(t, &self.origin)));
+        let number = |i: usize| -> Result<u16> { Ok(text(i)?.parse()?) };
+
+        // RFC 3597 通用格式：\# LENGTH HEX...
+        if rdata.first().is_some_and(|t| !t.quoted && t.text == "\\#") {
+            let len: usize = text(1)?.parse()?;
+            let hex: String = rdata.iter().skip(2).map(|t| t.text.as_str()).collect();
+            let data = hex::decode(&hex).map_err(|e| anyhow!("invalid generic RDATA: {}", e))?;
+            if data.len() != len { bail!("generic RDATA length {} does not match {}", data.len(), len); }
+            return Ok(Record::Unknown { rtype, data });
+        }
+
+        let data = match QType::from_u16(rtype) {
+            QType::A => Record::A { addr: text(0)?.parse()? },
+            QType::AAAA => Record::AAAA { addr: text(0)?.parse()? },
+            QType::NS => Record::NS { nsdname: name(0)? },
+            QType::CNAME => Record::CNAME { cname: name(0)? },
+            QType::PTR => Record::PTR { ptrdname: name(0)? },
+            QType::MX => Record::MX { preference: number(0)?, exchange: name(1)? },
+            QType::SRV => Record::SRV { priority: number(0)?, weight: number(1)?, port: number(2)?, target: name(3)? },
+            QType::SOA => Record::SOA {
+                mname: name(0)?, rname: name(1)?,
+                serial: text(2)?.parse()?, refresh: parse_ttl(text(3)?)?, retry: parse_ttl(text(4)?)?,
+                expire: parse_ttl(text(5)?)?, minimum: parse_ttl(text(6)?)?,
+            },
+            QType::TXT => {
+                if rdata.is_empty() { bail!("TXT record without strings"); }
+                Record::TXT { text: rdata.iter().map(|t| unescape(&t.text)).collect() }
+            }
+            _ => bail!("record type {} needs RFC 3597 generic RDATA (\\# LENGTH HEX)", rtype),
+        };
+        Ok(data)
+    }
+}
+
+/// 相对名字补上 origin；`@` 即 origin 本身
+fn absolute(name: &str, origin: &str) -> String {
+    if name == "@" { return origin.to_string(); }
+    if name.ends_with('.') && !name.ends_with("\\.") {
+        let name = &name[..name.len() - 1];
+        return if name.is_empty() { ".".to_string() } else { name.to_string() };
+    }
+    if origin == "." { name.to_string() } else { format!("{}.{}", name, origin) }
+}
+
+/// 名字不能编码为线格式 (空标签、标签超过 63 字节等) 时报错，加载后的区数据都能直接编码
+fn checked(name: String) -> Result<String> {
+    crate::wire::name_to_labels(&name)?;
+    Ok(name)
+}
+
+/// 纯数字秒数，或 BIND 风格的 1w2d3h4m5s
+fn parse_ttl(s: &str) -> Result<u32> {
+    if let Ok(secs) = s.parse() { return Ok(secs); }
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() { digits.push(c); continue; }
+        let unit = match c.to_ascii_lowercase() { 's' => 1, 'm' => 60, 'h' => 3600, 'd' => 86400, 'w' => 604800, _ => bail!("invalid TTL {}", s) };
+        if digits.is_empty() { bail!("invalid TTL {}", s); }
+        total += digits.parse::<u64>()? * unit;
+        digits.clear();
+    }
+    if !digits.is_empty() || total > u32::MAX as u64 { bail!("invalid TTL {}", s); }
+    Ok(total as u32)
+}
+
+/// 还原 `\X` 与 `\DDD` 转义
+fn unescape(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if i + 3 < bytes.len() && bytes[i + 1..i + 4].iter().all(|b| b.is_ascii_digit()) => {
+                let v = (bytes[i + 1] - b'0') as u16 * 100 + (bytes[i + 2] - b'0') as u16 * 10 + (bytes[i + 3] - b'0') as u16;
+                out.push(v.min(255) as u8);
+                i += 4;
+            }
+            b'\\' if i + 1 < bytes.len() => { out.push(bytes[i + 1]); i += 2; }
+            b => { out.push(b); i += 1; }
+        }
+    }
+    out
+}
+
+/// 切分为逻辑记录：处理注释、引号、转义与跨行括号
+fn tokenize(content: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut current = Entry { line: 1, blank_owner: false, tokens: Vec::new() };
+    let mut token: Option<Token> = None;
+    let mut depth = 0;
+    let mut in_quote = false;
+    let mut line = 1;
+    let mut line_start = true;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if line_start && depth == 0 && current.tokens.is_empty() {
+            current.line = line;
+            current.blank_owner = c == ' ' || c == '\t';
+        }
+        line_start = false;
+        match c {
+            '\\' => {
+                let t = token.get_or_insert_with(|| Token { text: String::new(), quoted: in_quote });
+                t.text.push('\\');
+                if let Some(next) = chars.next() { t.text.push(next); }
+            }
+            '"' if in_quote => { in_quote = false; current.tokens.extend(token.take()); }
+            '"' => {
+                current.tokens.extend(token.take());
+                in_quote = true;
+                token = Some(Token { text: String::new(), quoted: true });
+            }
+            _ if in_quote => {
+                if c == '\n' { line += 1; }
+                token.get_or_insert_with(|| Token { text: String::new(), quoted: true }).text.push(c);
+            }
+            ';' => {
+                while chars.peek().is_some_and(|&n| n != '\n') { chars.next(); }
+            }
+            '(' => { current.tokens.extend(token.take()); depth += 1; }
+            ')' => {
+                current.tokens.extend(token.take());
+                if depth == 0 { bail!("line {}: unbalanced ')'", line); }
+                depth -= 1;
+            }
+            '\n' => {
+                current.tokens.extend(token.take());
+                line += 1;
+                line_start = true;
+                if depth == 0 && !current.tokens.is_empty() {
+                    entries.push(std::mem::replace(&mut current, Entry { line, blank_owner: false, tokens: Vec::new() }));
+                }
+            }
+            c if c.is_whitespace() => { current.tokens.extend(token.take()); }
+            c => token.get_or_insert_with(|| Token { text: String::new(), quoted: false }).text.push(c),
+        }
+    }
+    if in_quote { bail!("line {}: unterminated quoted string", line); }
+    if depth != 0 { bail!("line {}: unbalanced '('", line); }
+    current.tokens.extend(token.take());
+    if !current.tokens.is_empty() { entries.push(current); }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const ZONE: &str = r#"$ORIGIN example.com.
+$TTL 1h
+@   IN SOA ns1 hostmaster (
+        2024010101 ; serial
+        2h 1h 2w 5m )
+    IN NS ns1
+    IN MX 10 mail
+ns1 IN A 192.0.2.1
+mail 300 IN A 192.0.2.2
+www IN CNAME web
+web IN CNAME host
+host IN A 192.0.2.3
+ext IN CNAME www.example.net.
+loop1 IN CNAME loop2
+loop2 IN CNAME loop1
+*.wild IN A 192.0.2.4
+sub.wild IN TXT "explicit"
+a.b.ent IN A 192.0.2.5
+txt IN TXT "hello world" "semi\;colon" "\255bin"
+gen IN TYPE65534 \# 3 abcdef
+sub IN NS ns.sub
+ns.sub IN A 192.0.2.53
+sub IN DS \# 4 01020304
+outside.example.org. IN A 192.0.2.99
+$INCLUDE inc.zone inc.example.com.
+after IN A 192.0.2.10
+"#;
+
+    fn load() -> Zone {
+        let dir = std::env::temp_dir().join(format!("coredns-zone-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("inc.zone"), "@ IN A 192.0.2.9\nx IN A 192.0.2.8\n").unwrap();
+        std::fs::write(dir.join("db.example.com"), ZONE).unwrap();
+        let zone = Zone::parse_file(dir.join("db.example.com").to_str().unwrap(), "Example.COM.").unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        zone
+    }
+
+    fn a(addr: [u8; 4]) -> Record {
+        Record::A { addr: Ipv4Addr::from(addr) }
+    }
+
+    fn names(rrs: &[ResourceRecord]) -> Vec<&str> {
+        rrs.iter().map(|rr| rr.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parses_master_file() {
+        let zone = load();
+        assert_eq!(zone.origin, "example.com");
+        assert_eq!(zone.serial(), 2024010101);
+        let Record::SOA { mname, rname, refresh, expire, minimum, .. } = &zone.soa().data else { panic!("not a SOA") };
+        assert_eq!((mname.as_str(), rname.as_str()), ("ns1.example.com", "hostmaster.example.com"));
+        assert_eq!((*refresh, *expire, *minimum), (7200, 1209600, 300));
+        // 区外记录被忽略，其余 22 条全部载入
+        assert_eq!(zone.len(), 22);
+        assert!(zone.records().all(|rr| in_zone(&normalize(&rr.name), "example.com")));
+
+        let find = |name: &str, qtype: QType| zone.rrset(name, qtype).next().cloned().unwrap();
+        assert_eq!(find("ns1.example.com", QType::A).ttl, 3600);
+        assert_eq!(find("mail.example.com", QType::A).ttl, 300);
+        assert_eq!(find("example.com", QType::NS).data, Record::NS { nsdname: "ns1.example.com".to_string() });
+        assert_eq!(find("txt.example.com", QType::TXT).data, Record::TXT { text: vec![b"hello world".to_vec(), b"semi;colon".to_vec(), b"\xFFbin".to_vec()] });
+        assert_eq!(find("gen.example.com", QType::Other(65534)).data, Record::Unknown { rtype: 65534, data: vec![0xAB, 0xCD, 0xEF] });
+        // $INCLUDE 带自己的 origin，结束后恢复
+        assert_eq!(find("inc.example.com", QType::A).data, a([192, 0, 2, 9]));
+        assert_eq!(find("x.inc.example.com", QType::A).data, a([192, 0, 2, 8]));
+        assert_eq!(find("after.example.com", QType::A).data, a([192, 0, 2, 10]));
+    }
+
+    #[test]
+    fn compares_serials_and_parses_targets() {
+        assert_eq!(serial_cmp(7, 7), Some(Ordering::Equal));
+        assert_eq!(serial_cmp(2, 1), Some(Ordering::Greater));
+        // 回绕：0 比 0xFFFFFFFF 新
+        assert_eq!(serial_cmp(0, u32::MAX), Some(Ordering::Greater));
+        assert_eq!(serial_cmp(u32::MAX, 0), Some(Ordering::Less));
+        assert_eq!(serial_cmp(0x8000_0000, 0), None);
+
+        assert_eq!(parse_target("192.0.2.1"), Some("192.0.2.1:53".parse().unwrap()));
+        assert_eq!(parse_target("192.0.2.1:5300"), Some("192.0.2.1:5300".parse().unwrap()));
+        assert_eq!(parse_target("[2001:db8::1]:5300"), Some("[2001:db8::1]:5300".parse().unwrap()));
+        assert_eq!(parse_target("2001:db8::1"), Some("[2001:db8::1]:53".parse().unwrap()));
+        assert_eq!(parse_target("ns1.example.com"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_records() {
+        let rr = parse_record("www 60 IN A 192.0.2.1", "example.com", 300).unwrap();
+        assert_eq!((rr.name.as_str(), rr.ttl), ("www.example.com", 60));
+        assert_eq!(parse_record("@ IN TXT \"x\"", "example.com.", 300).unwrap().ttl, 300);
+
+        for bad in [
+            "a..b 60 IN A 192.0.2.1",
+            "www 60 IN CNAME a..example.com.",
+            "www 60 IN A 192.0.2.300",
+            "www 60 IN MX mail",
+            "www 60 IN TXT",
+            "www 60 IN NOSUCHTYPE x",
+            "www 60 CH A 192.0.2.1",
+            "www 60 IN SOA ( ns1 hostmaster 1 2 3 4 5",
+        ] {
+            assert!(parse_record(bad, "example.com", 300).is_err(), "{}", bad);
+        }
+        let long = format!("{} 60 IN A 192.0.2.1", "a".repeat(64));
+        assert!(parse_record(&long, "example.com", 300).is_err());
+
+        let soa = parse_record("@ 60 IN SOA ns1 hostmaster 1 2 3 4 5", "example.com", 300).unwrap();
+        assert!(Zone::from_records("example.com", vec![rr.clone()]).is_err());
+        assert!(Zone::from_records("example.com", vec![soa.clone(), soa.clone(), rr.clone()]).is_ok());
+        let misplaced = ResourceRecord { name: "www.example.com".to_string(), ..soa.clone() };
+        assert!(Zone::from_records("example.com", vec![soa, misplaced]).is_err());
+    }
+
+    #[test]
+    fn answers_and_follows_cnames() {
+        let zone = load();
+        let out = zone.lookup("HOST.Example.com", QType::A);
+        assert!(out.authoritative && out.rcode == 0);
+        assert_eq!(out.answers.iter().map(|rr| &rr.data).collect::<Vec<_>>(), vec![&a([192, 0, 2, 3])]);
+
+        let out = zone.lookup("www.example.com", QType::A);
+        assert_eq!(names(&out.answers), vec!["www.example.com", "web.example.com", "host.example.com"]);
+        assert_eq!(out.answers[2].data, a([192, 0, 2, 3]));
+
+        // 只问 CNAME 时不追
+        assert_eq!(names(&zone.lookup("www.example.com", QType::CNAME).answers), vec!["www.example.com"]);
+        // 区外目标交给递归方
+        let out = zone.lookup("ext.example.com", QType::A);
+        assert_eq!((names(&out.answers), out.rcode), (vec!["ext.example.com"], 0));
+        // 成环的链原样返回
+        assert_eq!(names(&zone.lookup("loop1.example.com", QType::A).answers), vec!["loop1.example.com", "loop2.example.com"]);
+
+        // MX 目标在区内时带上地址
+        let out = zone.lookup("example.com", QType::MX);
+        assert_eq!(out.answers.len(), 1);
+        assert_eq!(out.additional.iter().map(|rr| &rr.data).collect::<Vec<_>>(), vec![&a([192, 0, 2, 2])]);
+    }
+
+    #[test]
+    fn distinguishes_nodata_and_nxdomain() {
+        let zone = load();
+        let soa_ttl = |out: &Lookup| out.authority.iter().find(|rr| rr.data.rtype() == QType::SOA).map(|rr| rr.ttl);
+
+        // 名字存在但没有该类型
+        let out = zone.lookup("host.example.com", QType::AAAA);
+        assert!(out.answers.is_empty());
+        assert_eq!((out.rcode, soa_ttl(&out)), (0, Some(300)));
+        // 空非终端也是存在的名字
+        let out = zone.lookup("b.ent.example.com", QType::A);
+        assert_eq!((out.rcode, soa_ttl(&out)), (0, Some(300)));
+        // 名字不存在
+        let out = zone.lookup("nope.example.com", QType::A);
+        assert!(out.answers.is_empty());
+        assert_eq!((out.rcode, soa_ttl(&out)), (3, Some(300)));
+        assert_eq!(zone.lookup("c.b.ent.example.com", QType::A).rcode, 3);
+    }
+
+    #[test]
+    fn expands_wildcards() {
+        let zone = load();
+        for name in ["foo.wild.example.com", "x.y.wild.example.com"] {
+            let out = zone.lookup(name, QType::A);
+            assert_eq!(out.rcode, 0);
+            assert_eq!(names(&out.answers), vec![name]);
+            assert_eq!(out.answers[0].data, a([192, 0, 2, 4]));
+        }
+        // 显式存在的名字不套用通配符
+        let out = zone.lookup("sub.wild.example.com", QType::A);
+        assert!(out.answers.is_empty() && out.rcode == 0);
+        // 最近祖先是 sub.wild，它下面没有通配符
+        assert_eq!(zone.lookup("a.sub.wild.example.com", QType::A).rcode, 3);
+        // 通配符只匹配其下的名字，不匹配上级
+        assert_eq!(zone.lookup("wild.example.com", QType::A).rcode, 0);
+        assert!(zone.lookup("wild.example.com", QType::A).answers.is_empty());
+    }
+
+    #[test]
+    fn refers_at_zone_cuts() {
+        let zone = load();
+        for name in ["sub.example.com", "www.sub.example.com", "a.b.sub.example.com"] {
+            let out = zone.lookup(name, QType::A);
+            assert!(!out.authoritative, "{}", name);
+            assert!(out.answers.is_empty());
+            assert_eq!(out.rcode, 0);
+            assert_eq!(out.authority.iter().map(|rr| &rr.data).collect::<Vec<_>>(), vec![&Record::NS { nsdname: "ns.sub.example.com".to_string() }]);
+            assert_eq!(out.additional.iter().map(|rr| &rr.data).collect::<Vec<_>>(), vec![&a([192, 0, 2, 53])]);
+        }
+        // DS 属于父区，在切点处权威应答
+        let out = zone.lookup("sub.example.com", QType::Other(QTYPE_DS));
+        assert!(out.authoritative);
+        assert_eq!(out.answers.len(), 1);
+        // 切点下的 DS 仍是 referral
+        assert!(!zone.lookup("www.sub.example.com", QType::Other(QTYPE_DS)).authoritative);
+    }
+}